@@ -0,0 +1,63 @@
+use std::fs;
+use std::time::{Duration, Instant};
+
+use minbpe::BasicTokenizer;
+use minbpe::GPT4Tokenizer;
+use minbpe::RegexTokenizerStruct;
+use minbpe::RegexTokenizerTrait;
+use minbpe::Tokenizer;
+use minbpe::Trainable;
+
+const ITERATIONS: u32 = 5;
+
+/// Returns the fastest of `ITERATIONS` runs of `f`.
+fn time<T>(mut f: impl FnMut() -> T) -> Duration {
+    (0..ITERATIONS)
+        .map(|_| {
+            let start = Instant::now();
+            std::hint::black_box(f());
+            start.elapsed()
+        })
+        .min()
+        .unwrap()
+}
+
+fn report(name: &str, text: &str, tokenizer: &dyn Tokenizer) {
+    let ids = tokenizer.encode(text);
+    let encode = time(|| tokenizer.encode(text));
+    let decode = time(|| tokenizer.decode(&ids));
+    println!(
+        "{:<8} {:>8} tokens  encode {:>10.2?}  decode {:>10.2?}",
+        name,
+        ids.len(),
+        encode,
+        decode
+    );
+}
+
+fn main() -> std::io::Result<()> {
+    let text = fs::read_to_string("tests/taylorswift.txt")?;
+    println!("Encoding and decoding {} bytes", text.len());
+
+    let mut basic = BasicTokenizer::new();
+    basic.train(&text, 512, false);
+    report("basic", &text, &basic);
+
+    let mut regex = RegexTokenizerStruct::default();
+    regex.train(&text, 512, false);
+    report("regex", &text, &regex);
+
+    let gpt4 = GPT4Tokenizer::default();
+    let ids = gpt4.encode_ordinary(&text);
+    let encode = time(|| gpt4.encode_ordinary(&text));
+    let decode = time(|| gpt4.decode(&ids));
+    println!(
+        "{:<8} {:>8} tokens  encode {:>10.2?}  decode {:>10.2?}",
+        "gpt4",
+        ids.len(),
+        encode,
+        decode
+    );
+
+    Ok(())
+}
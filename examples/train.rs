@@ -3,30 +3,34 @@ use std::path::Path;
 use std::time::Instant;
 
 use minbpe::BasicTokenizer;
+use minbpe::DocumentSplit;
 use minbpe::RegexTokenizerStruct;
 use minbpe::Saveable;
 use minbpe::Tokenizer;
 use minbpe::Trainable;
 
 fn main() {
-    let text = fs::read_to_string("tests/taylorswift.txt").expect("Unable to read file");
+    let path = Path::new("tests/taylorswift.txt");
 
     fs::create_dir_all("models").expect("Unable to create models directory");
 
     let basic = BasicTokenizer::new();
     let regex = RegexTokenizerStruct::default();
 
-    fn doit<T: Tokenizer + Trainable + Saveable>(tokenizer: T, name: &str, text: &str) {
+    fn doit<T: Tokenizer + Trainable + Saveable>(tokenizer: T, name: &str, path: &Path) {
         let mut tokenizer = tokenizer;
-        tokenizer.train(text, 512, true);
+        // Stream the file line by line instead of reading it into memory
+        tokenizer
+            .train_from_files([path], DocumentSplit::Lines, 512, true)
+            .expect("Unable to train tokenizer");
 
         let dir = Path::new("models").to_path_buf();
         tokenizer.save(&dir, name);
     }
 
     let t0 = Instant::now();
-    doit(basic, "basic", &text);
-    doit(regex, "regex", &text);
+    doit(basic, "basic", path);
+    doit(regex, "regex", path);
     let t1 = Instant::now();
 
     let duration = t1.duration_since(t0);
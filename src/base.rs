@@ -4,7 +4,9 @@
 //! e.g. isolating all regex/pattern parts to the RegexTokenizer, but
 //! some concessions are made for simplicity.
 
-use std::io::Write;
+use std::borrow::Cow;
+use std::collections::HashSet;
+use std::io::{self, Write};
 use std::path::Path;
 use std::{
     fs::File,
@@ -12,6 +14,15 @@ use std::{
 };
 
 use indexmap::IndexMap;
+#[cfg(feature = "parallel")]
+use rayon::prelude::*;
+
+use crate::decode::{bytes_to_string, DecodeOptions, UnknownIdPolicy};
+use crate::error::{MinbpeError, Result};
+use crate::observer::{verbose_observer, TrainingObserver, TrainingPhase};
+use crate::trainer::{
+    ChunkCounts, DocumentSplit, SpecialTokenSplitter, TrainingConfig, TrainingOutcome,
+};
 
 /// Token type to support up to 2^31 distinct tokens. It is signed in case a Tokenizer
 /// needs to use negative values for special tokens.
@@ -20,6 +31,32 @@ pub type Token = i32;
 /// Count type to support up to 2^64 occurences of any token pair.
 pub type Count = u64;
 
+/// Declares trait methods that share `self` across threads with the `parallel` feature, where
+/// they require `Self: Sync`. Without the feature they have no bound, so that tokenizers need
+/// not be `Sync`.
+macro_rules! parallel_methods {
+    ($(
+        $(#[$attr:meta])*
+        fn $name:ident(&$self:ident $(, $arg:ident: $ty:ty)* $(,)?) -> $ret:ty $body:block
+    )*) => {
+        $(
+            $(#[$attr])*
+            #[cfg(feature = "parallel")]
+            fn $name(&$self $(, $arg: $ty)*) -> $ret
+            where
+                Self: Sync,
+            $body
+
+            $(#[$attr])*
+            #[cfg(not(feature = "parallel"))]
+            fn $name(&$self $(, $arg: $ty)*) -> $ret $body
+        )*
+    };
+}
+
+#[cfg(feature = "regex")]
+pub(crate) use parallel_methods;
+
 /// Base trait for Tokenizers to implement.
 pub trait Tokenizer {
     fn special_tokens(&self) -> &IndexMap<String, Token>;
@@ -28,17 +65,652 @@ pub trait Tokenizer {
 
     fn vocab(&self) -> &IndexMap<Token, Vec<u8>>;
 
+    /// The split pattern, which text is split into chunks with before they are encoded, or ""
+    /// if the whole text is a single chunk. Tokenizers that split text override it.
+    fn split_pattern(&self) -> &str {
+        ""
+    }
+
+    /// Saves the tokenizer in the format of tiktoken to two files:
+    /// - `prefix.tiktoken`: The rank file, with the base64-encoded bytes and the id of every
+    ///   token other than the special tokens, the ids being the ranks.
+    /// - `prefix.json`: A sidecar with the prefix as `name`, the split pattern as `pat_str` and
+    ///   the `special_tokens`, the other arguments of tiktoken's `Encoding`.
+    ///
+    /// The bytes are those of `token_bytes`, so that a tokenizer that shuffles the bytes of its
+    /// vocabulary, such as `TiktokenTokenizer`, saves the original ranks.
+    /// `TiktokenTokenizer::try_from_saved` loads them back with the same ranks. The rank file
+    /// does not list the merges, which are recovered from the ranks: this gives back the merges
+    /// of a trained tokenizer, but merges set by hand may be recovered differently. A tokenizer
+    /// without a pattern is saved with one matching the whole text.
+    ///
+    /// # Errors
+    ///
+    /// Returns `MinbpeError::Io` if either file cannot be created or written, and
+    /// `MinbpeError::MalformedRanks` if the tokens cannot be ranked by id: if an id is negative,
+    /// if two tokens have the same bytes, or if the ids 0 to 255 are not those of the 256 bytes.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use tempfile::tempdir;
+    /// use minbpe::{RegexTokenizerStruct, RegexTokenizerTrait, TiktokenTokenizer};
+    /// use minbpe::{Tokenizer, Trainable};
+    ///
+    /// let mut tokenizer = RegexTokenizerStruct::default();
+    /// tokenizer.train("hello hello world", 256 + 3, false);
+    /// let dir = tempdir().unwrap();
+    /// tokenizer.try_save_tiktoken(dir.path(), "hello").unwrap();
+    ///
+    /// let loaded = TiktokenTokenizer::try_from_saved(dir.path(), "hello").unwrap();
+    /// assert_eq!(
+    ///     loaded.encode_ordinary("hello world"),
+    ///     tokenizer.encode_ordinary("hello world")
+    /// );
+    /// ```
+    #[cfg(feature = "tiktoken")]
+    fn try_save_tiktoken(&self, dir: &Path, prefix: &str) -> Result<()> {
+        crate::tiktoken::save_tiktoken(self, dir, prefix)
+    }
+
+    /// Saves the tokenizer in the format of tiktoken, see `try_save_tiktoken`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `try_save_tiktoken` returns an error.
+    #[cfg(feature = "tiktoken")]
+    fn save_tiktoken(&self, dir: &Path, prefix: &str) {
+        self.try_save_tiktoken(dir, prefix)
+            .unwrap_or_else(|e| panic!("{}", e))
+    }
+
+    /// Returns the bytes that token `idx` decodes to, special tokens included, or `None` if the
+    /// id is unknown.
+    fn token_bytes(&self, idx: Token) -> Option<Cow<'_, [u8]>> {
+        if let Some(bytes) = self.vocab().get(&idx) {
+            return Some(Cow::Borrowed(bytes));
+        }
+        self.special_tokens()
+            .iter()
+            .find(|&(_, &id)| id == idx)
+            .map(|(token, _)| Cow::Borrowed(token.as_bytes()))
+    }
+
+    /// Returns the bytes that token `idx` decodes to where it is in a text, `first` being
+    /// whether it is the first token other than the special tokens. `StreamingDecoder` decodes
+    /// tokens with it. Tokenizers whose first token decodes differently override it, such as
+    /// `SentencePieceTokenizer`, which drops its dummy prefix.
+    fn token_bytes_at(&self, idx: Token, first: bool) -> Option<Cow<'_, [u8]>> {
+        let _ = first;
+        self.token_bytes(idx)
+    }
+
     /// A Tokenizer can encode a string into a list of integers.
-    fn encode(&self, text: &str) -> Vec<Token>;
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the text cannot be encoded, e.g. because a split pattern exceeded
+    /// its backtracking limit.
+    fn try_encode(&self, text: &str) -> Result<Vec<Token>>;
+
+    /// A Tokenizer can decode a list of integers into a string. Bytes that are not valid UTF-8
+    /// are replaced with U+FFFD, see `try_decode_with` for other options.
+    ///
+    /// # Errors
+    ///
+    /// Returns `MinbpeError::UnknownTokenId` if an id is unknown.
+    fn try_decode(&self, ids: &[Token]) -> Result<String> {
+        self.try_decode_with(ids, &DecodeOptions::default())
+    }
+
+    /// Decodes a list of integers into the exact bytes of their tokens.
+    ///
+    /// # Errors
+    ///
+    /// Returns `MinbpeError::UnknownTokenId` if an id is unknown.
+    fn try_decode_bytes(&self, ids: &[Token]) -> Result<Vec<u8>> {
+        let mut bytes = Vec::new();
+        for &idx in ids {
+            let token = self
+                .token_bytes(idx)
+                .ok_or(MinbpeError::UnknownTokenId(idx))?;
+            bytes.extend_from_slice(&token);
+        }
+        Ok(bytes)
+    }
+
+    /// Decodes a list of integers into the exact bytes of their tokens, see `try_decode_bytes`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `try_decode_bytes` returns an error.
+    fn decode_bytes(&self, ids: &[Token]) -> Vec<u8> {
+        self.try_decode_bytes(ids)
+            .unwrap_or_else(|e| panic!("{}", e))
+    }
+
+    /// Decodes a list of integers into a string as specified by `options`.
+    ///
+    /// # Errors
+    ///
+    /// Returns `MinbpeError::UnknownTokenId` on an unknown id unless unknown ids are skipped, and
+    /// `MinbpeError::InvalidUtf8` on invalid UTF-8 with `Utf8Policy::Strict`.
+    fn try_decode_with(&self, ids: &[Token], options: &DecodeOptions) -> Result<String> {
+        let special: HashSet<Token> = if options.skip_special_tokens {
+            self.special_tokens().values().copied().collect()
+        } else {
+            HashSet::new()
+        };
+
+        let mut bytes = Vec::new();
+        for &idx in ids {
+            if special.contains(&idx) {
+                continue;
+            }
+            match self.token_bytes(idx) {
+                Some(token) => bytes.extend_from_slice(&token),
+                None if options.unknown_ids == UnknownIdPolicy::Skip => {}
+                None => return Err(MinbpeError::UnknownTokenId(idx)),
+            }
+        }
+        bytes_to_string(bytes, options.utf8)
+    }
+
+    /// A Tokenizer can encode a string into a list of integers.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `try_encode` returns an error.
+    fn encode(&self, text: &str) -> Vec<Token> {
+        self.try_encode(text).unwrap_or_else(|e| panic!("{}", e))
+    }
 
     /// A Tokenizer can decode a list of integers into a string.
-    fn decode(&self, ids: &[Token]) -> String;
+    ///
+    /// # Panics
+    ///
+    /// Panics if `try_decode` returns an error.
+    fn decode(&self, ids: &[Token]) -> String {
+        self.try_decode(ids).unwrap_or_else(|e| panic!("{}", e))
+    }
+
+    parallel_methods! {
+        /// Encodes every text of a batch, in parallel with the `parallel` feature. The ids are
+        /// returned in the order of the texts.
+        ///
+        /// # Errors
+        ///
+        /// Returns an error if any text cannot be encoded.
+        fn try_encode_batch(&self, texts: &[&str]) -> Result<Vec<Vec<Token>>> {
+            map_batch(texts, || (), |_, text| self.try_encode(text))
+        }
+
+        /// Encodes every text of a batch, see `try_encode_batch`.
+        ///
+        /// # Panics
+        ///
+        /// Panics if `try_encode_batch` returns an error.
+        fn encode_batch(&self, texts: &[&str]) -> Vec<Vec<Token>> {
+            self.try_encode_batch(texts)
+                .unwrap_or_else(|e| panic!("{}", e))
+        }
+
+        /// Decodes every list of ids of a batch, in parallel with the `parallel` feature. The
+        /// texts are returned in the order of the lists.
+        ///
+        /// # Errors
+        ///
+        /// Returns an error if any list of ids cannot be decoded.
+        fn try_decode_batch(&self, batch: &[Vec<Token>]) -> Result<Vec<String>> {
+            map_batch(batch, || (), |_, ids| self.try_decode(ids))
+        }
+
+        /// Decodes every list of ids of a batch, see `try_decode_batch`.
+        ///
+        /// # Panics
+        ///
+        /// Panics if `try_decode_batch` returns an error.
+        fn decode_batch(&self, batch: &[Vec<Token>]) -> Vec<String> {
+            self.try_decode_batch(batch)
+                .unwrap_or_else(|e| panic!("{}", e))
+        }
+    }
+}
+
+/// Applies `f` to every item of a batch, in parallel with the `parallel` feature, and returns the
+/// results in the order of the items. Each worker creates its scratch space with `init` once and
+/// passes it to `f` for every item it processes.
+#[cfg(feature = "parallel")]
+pub(crate) fn map_batch<T, S, R, I, F>(items: &[T], init: I, f: F) -> Result<Vec<R>>
+where
+    T: Sync,
+    R: Send,
+    I: Fn() -> S + Sync + Send,
+    F: Fn(&mut S, &T) -> Result<R> + Sync + Send,
+{
+    items
+        .par_iter()
+        .map_init(init, |scratch, item| f(scratch, item))
+        .collect()
+}
+
+/// Applies `f` to every item of a batch, in parallel with the `parallel` feature, and returns the
+/// results in the order of the items. Each worker creates its scratch space with `init` once and
+/// passes it to `f` for every item it processes.
+#[cfg(not(feature = "parallel"))]
+pub(crate) fn map_batch<T, S, R, I, F>(items: &[T], init: I, f: F) -> Result<Vec<R>>
+where
+    I: Fn() -> S,
+    F: Fn(&mut S, &T) -> Result<R>,
+{
+    let mut scratch = init();
+    items.iter().map(|item| f(&mut scratch, item)).collect()
 }
 
 /// A Tokenizer that can be trained.
+///
+/// Training first splits the corpus into chunks with `pre_tokenize`, counting each distinct
+/// chunk, then learns merges over the chunk counts with `train_chunks`. Pairs are never
+/// counted across two chunks, nor across two documents of a corpus.
 pub trait Trainable: Tokenizer {
+    /// Splits `text` into the chunks that pairs are counted within, adding them to `chunks`.
+    fn pre_tokenize(&self, text: &str, chunks: &mut ChunkCounts) -> Result<()>;
+
+    /// Train a vocabulary as configured by `config` from the chunks of a corpus, reporting
+    /// progress to `observer`. Returns the number of merges learned and why training stopped.
+    ///
+    /// # Errors
+    ///
+    /// Returns `MinbpeError::InvalidVocabSize` if `config.vocab_size` is less than 256,
+    /// `MinbpeError::SpecialTokenIdCollision` if `config` declares no special tokens and a merge
+    /// takes the id of one of the tokenizer's, and `MinbpeError::Io` if a checkpoint cannot be
+    /// written.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use tempfile::tempdir;
+    /// use minbpe::{BasicTokenizer, CheckpointConfig, ChunkCounts, SilentObserver, Trainable};
+    /// use minbpe::TrainingConfig;
+    ///
+    /// let dir = tempdir().unwrap();
+    /// let mut tokenizer = BasicTokenizer::new();
+    /// let mut chunks = ChunkCounts::new();
+    /// tokenizer.pre_tokenize("aaabdaaabac", &mut chunks).unwrap();
+    /// let config = TrainingConfig {
+    ///     checkpoint: Some(CheckpointConfig {
+    ///         path: dir.path().join("basic.checkpoint"),
+    ///         interval: 1,
+    ///     }),
+    ///     ..TrainingConfig::new(256 + 3)
+    /// };
+    /// tokenizer
+    ///     .train_chunks_with_config(&chunks, &config, &mut SilentObserver)
+    ///     .unwrap();
+    /// ```
+    fn train_chunks_with_config(
+        &mut self,
+        chunks: &ChunkCounts,
+        config: &TrainingConfig,
+        observer: &mut dyn TrainingObserver,
+    ) -> Result<TrainingOutcome>;
+
+    /// Continues the training run saved in a checkpoint written by `train_chunks_with_config`
+    /// (or by a previous `resume_training`), up to `config.vocab_size`.
+    ///
+    /// The result is identical to that of an uninterrupted run over the same corpus. The
+    /// tokenizer must have the split pattern of that run, and `config` its special tokens. If
+    /// `config.checkpoint` is set, checkpoints keep being written while training continues.
+    ///
+    /// # Errors
+    ///
+    /// Returns `MinbpeError::InvalidVocabSize` if `config.vocab_size` is less than 256,
+    /// `MinbpeError::Io` if a checkpoint cannot be read or written,
+    /// `MinbpeError::MalformedCheckpoint` if the checkpoint is invalid,
+    /// `MinbpeError::CheckpointMismatch` if it was written by a run with another split pattern
+    /// or other special tokens, and `MinbpeError::SpecialTokenIdCollision` as
+    /// `train_chunks_with_config` does.
+    fn resume_training(
+        &mut self,
+        checkpoint: &Path,
+        config: &TrainingConfig,
+        observer: &mut dyn TrainingObserver,
+    ) -> Result<TrainingOutcome>;
+
+    /// Train a vocabulary of size `vocab_size` in distinct Tokens from the chunks of a corpus.
+    ///
+    /// # Errors
+    ///
+    /// Returns `MinbpeError::InvalidVocabSize` if `vocab_size` is less than 256.
+    fn train_chunks(
+        &mut self,
+        chunks: &ChunkCounts,
+        vocab_size: Token,
+        verbose: bool,
+    ) -> Result<()> {
+        let config = TrainingConfig::new(vocab_size);
+        self.train_chunks_with_config(chunks, &config, verbose_observer(verbose).as_mut())?;
+        Ok(())
+    }
+
+    /// Train a vocabulary of size `vocab_size` in distinct Tokens from `text`.
+    ///
+    /// The vocabulary is smaller if the text runs out of pairs to merge before reaching it.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if `vocab_size` is less than 256 or if the text cannot be split into chunks.
+    fn try_train(&mut self, text: &str, vocab_size: Token, verbose: bool) -> Result<()> {
+        let config = TrainingConfig::new(vocab_size);
+        self.train_with_observer(text, &config, verbose_observer(verbose).as_mut())?;
+        Ok(())
+    }
+
+    /// Train a vocabulary as configured by `config` from `text`, reporting progress to
+    /// `observer`, which can also stop training early. Returns the number of merges learned and
+    /// why training stopped.
+    ///
+    /// The text is split on the special tokens of `config`, which are never trained on.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use minbpe::{BasicTokenizer, SilentObserver, StopReason, Trainable, TrainingConfig};
+    ///
+    /// let mut tokenizer = BasicTokenizer::new();
+    /// let config = TrainingConfig {
+    ///     min_frequency: Some(2),
+    ///     ..TrainingConfig::new(512)
+    /// };
+    /// let outcome = tokenizer
+    ///     .train_with_observer("aaabdaaabac", &config, &mut SilentObserver)
+    ///     .unwrap();
+    /// assert_eq!(outcome.num_merges, 3);
+    /// assert_eq!(outcome.stop_reason, StopReason::MinFrequency);
+    /// ```
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if `config.vocab_size` is less than 256, if the text cannot be split
+    /// into chunks or if a checkpoint cannot be written.
+    fn train_with_observer(
+        &mut self,
+        text: &str,
+        config: &TrainingConfig,
+        observer: &mut dyn TrainingObserver,
+    ) -> Result<TrainingOutcome> {
+        train_on_documents(self, [text], config, observer)
+    }
+
     /// Train a vocabulary of size `vocab_size` in distinct Tokens from `text`.
-    fn train(&mut self, text: &str, vocab_size: Token, verbose: bool);
+    ///
+    /// # Panics
+    ///
+    /// Panics if `try_train` returns an error.
+    fn train(&mut self, text: &str, vocab_size: Token, verbose: bool) {
+        self.try_train(text, vocab_size, verbose)
+            .unwrap_or_else(|e| panic!("{}", e))
+    }
+
+    /// Train a vocabulary of size `vocab_size` in distinct Tokens from a stream of documents.
+    ///
+    /// Each document is pre-tokenized and counted as it arrives, so only the distinct chunks
+    /// are held in memory, not the corpus. Documents are independent: no pair spanning the end
+    /// of one document and the start of the next is ever counted.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use minbpe::{RegexTokenizerStruct, Trainable};
+    ///
+    /// let documents = ["Hello, world!", "Goodbye, world!", "Hello again"];
+    /// let mut tokenizer = RegexTokenizerStruct::default();
+    /// tokenizer.train_from_iter(documents, 256 + 5, false).unwrap();
+    /// ```
+    fn train_from_iter<I, S>(
+        &mut self,
+        documents: I,
+        vocab_size: Token,
+        verbose: bool,
+    ) -> Result<()>
+    where
+        Self: Sized,
+        I: IntoIterator<Item = S>,
+        S: AsRef<str>,
+    {
+        let config = TrainingConfig::new(vocab_size);
+        self.train_from_iter_with_observer(documents, &config, verbose_observer(verbose).as_mut())?;
+        Ok(())
+    }
+
+    /// Train a vocabulary as configured by `config` from a stream of documents, as in
+    /// `train_from_iter`, reporting progress to `observer`. Documents are split on the special
+    /// tokens of `config`, which are never trained on.
+    fn train_from_iter_with_observer<I, S>(
+        &mut self,
+        documents: I,
+        config: &TrainingConfig,
+        observer: &mut dyn TrainingObserver,
+    ) -> Result<TrainingOutcome>
+    where
+        Self: Sized,
+        I: IntoIterator<Item = S>,
+        S: AsRef<str>,
+    {
+        train_on_documents(self, documents, config, observer)
+    }
+
+    /// Train a vocabulary of size `vocab_size` in distinct Tokens from a list of documents, as in
+    /// `train_from_iter`.
+    ///
+    /// No pair spanning the end of one document and the start of the next is ever counted, so
+    /// no merge can cross a document boundary. Unlike `train_from_iter` this can be called on a
+    /// `dyn Trainable`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use minbpe::{BasicTokenizer, Tokenizer, Trainable};
+    ///
+    /// // Concatenated, "abab" would also count the pair "ba"
+    /// let mut tokenizer = BasicTokenizer::new();
+    /// tokenizer.train_documents(&["ab", "ab"], 256 + 1, false).unwrap();
+    /// assert_eq!(tokenizer.merges().keys().next(), Some(&(97, 98)));
+    /// ```
+    fn train_documents(
+        &mut self,
+        documents: &[&str],
+        vocab_size: Token,
+        verbose: bool,
+    ) -> Result<()> {
+        let config = TrainingConfig::new(vocab_size);
+        train_on_documents(self, documents, &config, verbose_observer(verbose).as_mut())?;
+        Ok(())
+    }
+
+    /// Train a vocabulary of size `vocab_size` in distinct Tokens from `text` made of documents
+    /// joined by `separator`, e.g. `<|endoftext|>`.
+    ///
+    /// The separator is declared as the special token of a `TrainingConfig`, see
+    /// `train_with_observer`: the text is split on it, so no pair is counted across two
+    /// documents, and it becomes a special token of the tokenizer, with the id following the
+    /// merges. An empty separator leaves the text as a single document.
+    fn train_with_separator(
+        &mut self,
+        text: &str,
+        separator: &str,
+        vocab_size: Token,
+        verbose: bool,
+    ) -> Result<()> {
+        let mut config = TrainingConfig::new(vocab_size);
+        if !separator.is_empty() {
+            config.special_tokens.push(separator.to_string());
+        }
+        self.train_with_observer(text, &config, verbose_observer(verbose).as_mut())?;
+        Ok(())
+    }
+
+    /// Train a vocabulary of size `vocab_size` in distinct Tokens from the text files at `paths`,
+    /// split into documents according to `split` and streamed as in `train_from_iter`.
+    ///
+    /// # Errors
+    ///
+    /// Returns `MinbpeError::Io` if a file cannot be read or is not valid UTF-8, or if the
+    /// delimiter of `DocumentSplit::Delimiter` is empty, and `MinbpeError::MalformedJsonLine` if
+    /// a line of a JSONL file does not hold a document.
+    fn train_from_files<I, P>(
+        &mut self,
+        paths: I,
+        split: DocumentSplit,
+        vocab_size: Token,
+        verbose: bool,
+    ) -> Result<()>
+    where
+        Self: Sized,
+        I: IntoIterator<Item = P>,
+        P: AsRef<Path>,
+    {
+        let config = TrainingConfig::new(vocab_size);
+        let mut observer = verbose_observer(verbose);
+        self.train_from_files_with_observer(paths, split, &config, observer.as_mut())?;
+        Ok(())
+    }
+
+    /// Train a vocabulary as configured by `config` from the text files at `paths`, as in
+    /// `train_from_files`, reporting progress to `observer`. Documents are split on the special
+    /// tokens of `config`, which are never trained on.
+    ///
+    /// # Errors
+    ///
+    /// Returns the errors of `train_from_files` and of `train_chunks_with_config`.
+    fn train_from_files_with_observer<I, P>(
+        &mut self,
+        paths: I,
+        split: DocumentSplit,
+        config: &TrainingConfig,
+        observer: &mut dyn TrainingObserver,
+    ) -> Result<TrainingOutcome>
+    where
+        Self: Sized,
+        I: IntoIterator<Item = P>,
+        P: AsRef<Path>,
+    {
+        observer.on_phase(TrainingPhase::PreTokenizing);
+        let splitter = SpecialTokenSplitter::new(config);
+        let mut chunks = ChunkCounts::new();
+        let mut add_document = |document: &str| -> Result<()> {
+            for part in splitter.split(document) {
+                self.pre_tokenize(part, &mut chunks)?;
+            }
+            Ok(())
+        };
+        for path in paths {
+            let path = path.as_ref();
+            let mut reader = BufReader::new(File::open(path)?);
+            match &split {
+                DocumentSplit::Lines => {
+                    let mut line = String::new();
+                    while reader.read_line(&mut line)? > 0 {
+                        add_document(&line)?;
+                        line.clear();
+                    }
+                }
+                DocumentSplit::Delimiter(delimiter) => {
+                    for_each_delimited(&mut reader, delimiter, &mut add_document)?;
+                }
+                #[cfg(feature = "jsonl")]
+                DocumentSplit::JsonLines { field } => {
+                    let mut line = String::new();
+                    let mut line_number = 0;
+                    while reader.read_line(&mut line)? > 0 {
+                        line_number += 1;
+                        if !line.trim().is_empty() {
+                            let document = json_document(&line, field).map_err(|message| {
+                                MinbpeError::MalformedJsonLine {
+                                    path: path.to_path_buf(),
+                                    line: line_number,
+                                    message,
+                                }
+                            })?;
+                            add_document(&document)?;
+                        }
+                        line.clear();
+                    }
+                }
+            }
+        }
+        self.train_chunks_with_config(&chunks, config, observer)
+    }
+}
+
+/// Reads the documents of `reader` separated by `delimiter`, passing each one to `f`. Only one
+/// document is held in memory at a time.
+fn for_each_delimited<R: BufRead>(
+    reader: &mut R,
+    delimiter: &str,
+    f: &mut dyn FnMut(&str) -> Result<()>,
+) -> Result<()> {
+    let delimiter = delimiter.as_bytes();
+    let last = *delimiter
+        .last()
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "Empty document delimiter"))?;
+    let mut document = Vec::new();
+    loop {
+        // Every occurrence of the delimiter ends with its last byte, so reading up to that byte
+        // never reads past the end of a document
+        let at_end = reader.read_until(last, &mut document)? == 0;
+        if !at_end && !document.ends_with(delimiter) {
+            continue;
+        }
+        if !at_end {
+            document.truncate(document.len() - delimiter.len());
+        }
+        let text = std::str::from_utf8(&document)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        f(text)?;
+        document.clear();
+        if at_end {
+            return Ok(());
+        }
+    }
+}
+
+/// Returns the string member `field` of the JSON object on `line`, or why there is none.
+#[cfg(feature = "jsonl")]
+fn json_document(line: &str, field: &str) -> std::result::Result<String, String> {
+    let value: serde_json::Value = serde_json::from_str(line).map_err(|e| e.to_string())?;
+    match value {
+        serde_json::Value::Object(mut object) => match object.remove(field) {
+            Some(serde_json::Value::String(document)) => Ok(document),
+            Some(_) => Err(format!("Member {:?} is not a string", field)),
+            None => Err(format!("Missing member {:?}", field)),
+        },
+        _ => Err("Not a JSON object".to_string()),
+    }
+}
+
+/// Pre-tokenizes `documents`, split on the special tokens of `config`, and trains on their
+/// chunks. The training entry points taking text in memory all go through it.
+fn train_on_documents<T, I, S>(
+    tokenizer: &mut T,
+    documents: I,
+    config: &TrainingConfig,
+    observer: &mut dyn TrainingObserver,
+) -> Result<TrainingOutcome>
+where
+    T: Trainable + ?Sized,
+    I: IntoIterator<Item = S>,
+    S: AsRef<str>,
+{
+    observer.on_phase(TrainingPhase::PreTokenizing);
+    let splitter = SpecialTokenSplitter::new(config);
+    let mut chunks = ChunkCounts::new();
+    for document in documents {
+        for part in splitter.split(document.as_ref()) {
+            tokenizer.pre_tokenize(part, &mut chunks)?;
+        }
+    }
+    tokenizer.train_chunks_with_config(&chunks, config, observer)
 }
 
 pub trait Saveable: Tokenizer {
@@ -55,6 +727,10 @@ pub trait Saveable: Tokenizer {
     /// * `dir` - The path to the output directory.
     /// * `prefix` - The prefix for the output file name.
     ///
+    /// # Errors
+    ///
+    /// Returns `MinbpeError::Io` if either file cannot be created or written.
+    ///
     /// # Examples
     ///
     /// ```
@@ -65,24 +741,21 @@ pub trait Saveable: Tokenizer {
     /// let tokenizer = BasicTokenizer::new();
     /// let dir = tempdir().unwrap();
     /// let path = dir.path();
-    /// tokenizer.save(&path, "prefix");
+    /// tokenizer.try_save(&path, "prefix").unwrap();
     /// ```
-    fn save(&self, dir: &Path, prefix: &str) {
-        // let dir = dir.as_ref();
-
+    fn try_save(&self, dir: &Path, prefix: &str) -> Result<()> {
         // Write the model file (used for loading the tokenizer later)
         let model_file_path = dir.join(format!("{}.model", prefix));
-        let mut model_file = File::create(model_file_path).expect("Unable to create model file");
+        let mut model_file = File::create(model_file_path)?;
 
         // Write the version, pattern, and merges
-        writeln!(model_file, "minbpe v1").expect("Unable to write to model file");
-        writeln!(model_file, "{}", self.pattern()).expect("Unable to write to model file");
+        writeln!(model_file, "minbpe v1")?;
+        writeln!(model_file, "{}", self.pattern())?;
 
         // Write the special tokens (first the number, then each token and its index)
-        writeln!(model_file, "{}", self.special_tokens().len())
-            .expect("Unable to write to model file");
+        writeln!(model_file, "{}", self.special_tokens().len())?;
         for (special, idx) in self.special_tokens() {
-            writeln!(model_file, "{} {}", special, idx).expect("Unable to write to model file");
+            writeln!(model_file, "{} {}", special, idx)?;
         }
 
         let mut merges: Vec<(&(Token, Token), &Token)> = self.merges().iter().collect();
@@ -90,13 +763,12 @@ pub trait Saveable: Tokenizer {
 
         // Write the merges dictionary
         for (token_pair, _new_token) in merges {
-            writeln!(model_file, "{} {}", token_pair.0, token_pair.1)
-                .expect("Unable to write to model file");
+            writeln!(model_file, "{} {}", token_pair.0, token_pair.1)?;
         }
 
         // Write the vocabulary file (for human inspection)
         let vocab_file_path = dir.join(format!("{}.vocab", prefix));
-        let mut vocab_file = File::create(vocab_file_path).expect("Unable to create vocab file");
+        let mut vocab_file = File::create(vocab_file_path)?;
 
         // Invert the merges dictionary for easier lookup
         let inverted_merges: IndexMap<Token, (Token, Token)> = self
@@ -115,33 +787,166 @@ pub trait Saveable: Tokenizer {
                 // If the token has children, render it as a merge
                 let s0 = render_token(&vocab[idx0]);
                 let s1 = render_token(&vocab[idx1]);
-                writeln!(vocab_file, "[{}][{}] -> [{}] {}", s0, s1, s, idx)
-                    .expect("Unable to write to vocab file");
+                writeln!(vocab_file, "[{}][{}] -> [{}] {}", s0, s1, s, idx)?;
             } else {
                 // Otherwise, it's a leaf token (one of the first 256 bytes)
-                writeln!(vocab_file, "[{}] {}", s, idx).expect("Unable to write to vocab file");
+                writeln!(vocab_file, "[{}] {}", s, idx)?;
             }
         }
+
+        Ok(())
+    }
+
+    /// Saves the tokenizer's model and vocabulary, see `try_save`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `try_save` returns an error.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use tempfile::tempdir;
+    /// use minbpe::Saveable;
+    /// use minbpe::Tokenizer;
+    /// use minbpe::BasicTokenizer;
+    /// let tokenizer = BasicTokenizer::new();
+    /// let dir = tempdir().unwrap();
+    /// let path = dir.path();
+    /// tokenizer.save(&path, "prefix");
+    /// ```
+    fn save(&self, dir: &Path, prefix: &str) {
+        self.try_save(dir, prefix)
+            .unwrap_or_else(|e| panic!("{}", e))
+    }
+
+    /// Saves the tokenizer as a HuggingFace byte-level BPE `tokenizer.json` file: the vocabulary
+    /// with its tokens written through GPT-2's byte-to-unicode mapping, the merges in order, the
+    /// pattern as a `Split` pre-tokenizer and the special tokens as `added_tokens`.
+    ///
+    /// Loading the file with `HuggingFaceTokenizer` gives a tokenizer that encodes exactly like
+    /// this one.
+    ///
+    /// # Errors
+    ///
+    /// Returns `MinbpeError::Io` if the file cannot be created or written,
+    /// `MinbpeError::InvalidTokenizerJson` if two tokens have the same bytes, which
+    /// `tokenizer.json` cannot represent, and `MinbpeError::UnknownTokenId` if a merge refers to
+    /// a token that is not in the vocabulary.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use tempfile::tempdir;
+    /// use minbpe::{HuggingFaceTokenizer, RegexTokenizerStruct, RegexTokenizerTrait};
+    /// use minbpe::{Saveable, Trainable};
+    ///
+    /// let mut tokenizer = RegexTokenizerStruct::default();
+    /// tokenizer.train("hello hello world", 256 + 3, false);
+    /// let dir = tempdir().unwrap();
+    /// let path = dir.path().join("tokenizer.json");
+    /// tokenizer.try_save_hf_json(&path).unwrap();
+    ///
+    /// let imported = HuggingFaceTokenizer::try_from_file(&path).unwrap();
+    /// assert_eq!(
+    ///     imported.encode_ordinary("hello world"),
+    ///     tokenizer.encode_ordinary("hello world")
+    /// );
+    /// ```
+    #[cfg(feature = "huggingface")]
+    fn try_save_hf_json(&self, path: &Path) -> Result<()> {
+        let mut file = std::io::BufWriter::new(File::create(path)?);
+        crate::huggingface::write_tokenizer_json(
+            &mut file,
+            self.pattern(),
+            self.special_tokens(),
+            self.merges(),
+            self.vocab(),
+        )?;
+        file.flush()?;
+        Ok(())
+    }
+
+    /// Saves the tokenizer as a HuggingFace `tokenizer.json` file, see `try_save_hf_json`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `try_save_hf_json` returns an error.
+    #[cfg(feature = "huggingface")]
+    fn save_hf_json(&self, path: &Path) {
+        self.try_save_hf_json(path)
+            .unwrap_or_else(|e| panic!("{}", e))
     }
 }
 
 pub trait Loadable: Tokenizer {
-    fn set_pattern(&mut self, pattern: &str);
+    /// Sets the split pattern.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the pattern is invalid or not supported by the Tokenizer, in which
+    /// case the Tokenizer must be left unchanged.
+    fn try_set_pattern(&mut self, pattern: &str) -> Result<()>;
     fn set_special_tokens(&mut self, special_tokens: IndexMap<String, Token>);
     fn set_merges(&mut self, merges: IndexMap<(Token, Token), Token>);
     fn set_vocab(&mut self, vocab: IndexMap<Token, Vec<u8>>);
 
+    /// Sets the split pattern.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `try_set_pattern` returns an error.
+    fn set_pattern(&mut self, pattern: &str) {
+        self.try_set_pattern(pattern)
+            .unwrap_or_else(|e| panic!("{}", e))
+    }
+
+    /// Sets the special tokens, checking their ids against the merges. Unlike
+    /// `set_special_tokens`, which trusts its caller, this never lets a special token shadow
+    /// another token.
+    ///
+    /// # Errors
+    ///
+    /// Returns `MinbpeError::SpecialTokenIdCollision` if a special token has the id of a byte,
+    /// of a merge or of another special token. The tokenizer is left unchanged in that case.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use indexmap::IndexMap;
+    /// use minbpe::{BasicTokenizer, Loadable, MinbpeError, Trainable};
+    ///
+    /// let mut tokenizer = BasicTokenizer::new();
+    /// tokenizer.train("aaabdaaabac", 256 + 3, false);
+    /// let special_tokens = IndexMap::from([("<|eot|>".to_string(), 256)]);
+    /// assert!(matches!(
+    ///     tokenizer.try_set_special_tokens(special_tokens),
+    ///     Err(MinbpeError::SpecialTokenIdCollision { id: 256, .. })
+    /// ));
+    /// let special_tokens = IndexMap::from([("<|eot|>".to_string(), 256 + 3)]);
+    /// tokenizer.try_set_special_tokens(special_tokens).unwrap();
+    /// ```
+    fn try_set_special_tokens(&mut self, special_tokens: IndexMap<String, Token>) -> Result<()> {
+        check_special_token_ids(&special_tokens, self.merges())?;
+        self.set_special_tokens(special_tokens);
+        Ok(())
+    }
+
     /// Loads the tokenizer's model from a file.
     ///
-    /// This is the inverse of `save` but only for the model file.
+    /// This is the inverse of `save` but only for the model file. Merges take ids in order from
+    /// 256, skipping the ids of the special tokens, so special tokens reserved before the merges
+    /// keep their ids.
     ///
     /// # Arguments
     ///
     /// * `model_file` - The path to the model file.
     ///
-    /// # Panics
+    /// # Errors
     ///
-    /// Panics if the model file does not have a ".model" extension or if the file format is invalid.
+    /// Returns an error if the model file does not have a ".model" extension, cannot be read,
+    /// if the file format is invalid, or if a special token has the id of a byte or of another
+    /// special token. The tokenizer is left unchanged in that case.
     ///
     /// # Examples
     ///
@@ -152,92 +957,140 @@ pub trait Loadable: Tokenizer {
     /// use minbpe::BasicTokenizer;
     /// let mut tokenizer = BasicTokenizer::new();
     /// let model_path = PathBuf::from("examples/basic_example.model");
-    /// tokenizer.load(&model_path);
+    /// tokenizer.try_load(&model_path).unwrap();
     /// ```
-    fn load(&mut self, model_file: &Path) {
-        // FIXME: Return a Result instead of panicking
-        // let model_file = model_file.as_ref();
-        assert!(
-            model_file.extension().map_or(false, |ext| ext == "model"),
-            "Model file must have a .model extension"
-        );
+    fn try_load(&mut self, model_file: &Path) -> Result<()> {
+        if model_file.extension() != Some(std::ffi::OsStr::new("model")) {
+            return Err(MinbpeError::InvalidModelExtension(model_file.to_path_buf()));
+        }
 
         let mut merges: IndexMap<(Token, Token), Token> = IndexMap::new();
         let mut special_tokens: IndexMap<String, Token> = IndexMap::new();
         let mut idx: Token = 256;
 
-        let file = File::open(model_file).expect("Unable to open model file");
+        let file = File::open(model_file)?;
         let reader = BufReader::new(file);
 
-        let lines: Vec<String> = reader
-            .lines()
-            .map(|line| line.expect("Unable to read line from model file"))
-            .collect();
+        let lines: Vec<String> = reader.lines().collect::<std::io::Result<_>>()?;
 
-        let mut line_iter = lines.iter();
+        // Line numbers are 1-based for error reporting
+        let mut line_iter = lines.iter().enumerate().map(|(i, line)| (i + 1, line));
 
-        if let Some(version) = line_iter.next() {
-            assert_eq!(version, "minbpe v1", "Invalid model file version");
-        } else {
-            panic!("Missing version line in model file");
+        match line_iter.next() {
+            Some((_, version)) if version == "minbpe v1" => {}
+            Some((_, version)) => return Err(MinbpeError::InvalidVersion(version.clone())),
+            None => return Err(MinbpeError::InvalidVersion(String::new())),
         }
 
         // FIXME: Check whether Tokenizer supports a Pattern at all.
 
-        if let Some(pattern) = line_iter.next() {
-            self.set_pattern(pattern);
-        } else {
-            panic!("Missing pattern line in model file");
-        }
+        let (_, pattern) = line_iter
+            .next()
+            .ok_or_else(|| malformed_model(2, "Missing pattern line"))?;
+
+        let (line, num_special_str) = line_iter
+            .next()
+            .ok_or_else(|| malformed_model(3, "Missing number of special tokens line"))?;
+        let num_special = num_special_str
+            .parse::<usize>()
+            .map_err(|_| malformed_model(line, "Invalid number of special tokens"))?;
 
-        if let Some(num_special_str) = line_iter.next() {
-            let num_special = num_special_str
+        // FIXME: Check whether Tokenizer supports Special Tokens at all.
+        // FIXME: Enforce some reasonable maximum less than 2^31.
+
+        for i in 0..num_special {
+            let (line, special_line) = line_iter
+                .next()
+                .ok_or_else(|| malformed_model(line + i + 1, "Missing special token line"))?;
+            let mut parts = special_line.split_whitespace();
+            let special = parts
+                .next()
+                .ok_or_else(|| malformed_model(line, "Missing special token"))?
+                .to_string();
+            let special_idx = parts
+                .next()
+                .ok_or_else(|| malformed_model(line, "Missing special token index"))?
                 .parse::<Token>()
-                .expect("Invalid number of special tokens");
-
-            // FIXME: Check whether Tokenizer supports Special Tokens at all.
-            // FIXME: Ensure it is >= 0 because Token type is signed.
-            // FIXME: Enforce some reasonable maximum less than 2^31.
-
-            for _ in 0..num_special {
-                if let Some(special_line) = line_iter.next() {
-                    let mut parts = special_line.split_whitespace();
-                    let special = parts.next().expect("Missing special token").to_string();
-                    let special_idx = parts
-                        .next()
-                        .expect("Missing special token index")
-                        .parse::<Token>()
-                        .expect("Invalid special token index");
-                    special_tokens.insert(special, special_idx);
-                } else {
-                    panic!("Missing special token line in model file");
-                }
+                .map_err(|_| malformed_model(line, "Invalid special token index"))?;
+            if special_idx < 256 || special_tokens.values().any(|&idx| idx == special_idx) {
+                return Err(MinbpeError::SpecialTokenIdCollision {
+                    token: special,
+                    id: special_idx,
+                });
             }
-        } else {
-            panic!("Missing number of special tokens line in model file");
+            special_tokens.insert(special, special_idx);
         }
 
-        for merge_line in line_iter {
+        // Merges take the next ids in order, skipping those of the special tokens
+        let special_ids: HashSet<Token> = special_tokens.values().copied().collect();
+        while special_ids.contains(&idx) {
+            idx += 1;
+        }
+
+        for (line, merge_line) in line_iter {
+            let malformed = || MinbpeError::MalformedMerge {
+                line,
+                content: merge_line.clone(),
+            };
             let mut parts = merge_line.split_whitespace();
             let idx1 = parts
                 .next()
-                .expect("Missing first index")
-                .parse::<Token>()
-                .expect("Invalid first index");
+                .and_then(|p| p.parse::<Token>().ok())
+                .ok_or_else(malformed)?;
             let idx2 = parts
                 .next()
-                .expect("Missing second index")
-                .parse::<Token>()
-                .expect("Invalid second index");
+                .and_then(|p| p.parse::<Token>().ok())
+                .ok_or_else(malformed)?;
+            let valid = |id: Token| (0..idx).contains(&id) && !special_ids.contains(&id);
+            if parts.next().is_some() || !valid(idx1) || !valid(idx2) {
+                return Err(malformed());
+            }
             merges.insert((idx1, idx2), idx);
             idx += 1;
+            while special_ids.contains(&idx) {
+                idx += 1;
+            }
         }
 
         let vocab = build_vocab(&special_tokens, &merges);
 
+        // Only update the tokenizer once the whole file is valid. Setting the pattern is the
+        // only step that can fail, and it leaves the tokenizer unchanged when it does.
+        self.try_set_pattern(pattern)?;
         self.set_special_tokens(special_tokens);
         self.set_merges(merges);
         self.set_vocab(vocab);
+
+        Ok(())
+    }
+
+    /// Loads the tokenizer's model from a file, see `try_load`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the model file does not have a ".model" extension or if the file format is invalid.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use std::path::PathBuf;
+    /// use minbpe::Loadable;
+    /// use minbpe::Tokenizer;
+    /// use minbpe::BasicTokenizer;
+    /// let mut tokenizer = BasicTokenizer::new();
+    /// let model_path = PathBuf::from("examples/basic_example.model");
+    /// tokenizer.load(&model_path);
+    /// ```
+    fn load(&mut self, model_file: &Path) {
+        self.try_load(model_file)
+            .unwrap_or_else(|e| panic!("{}", e))
+    }
+}
+
+fn malformed_model(line: usize, message: &str) -> MinbpeError {
+    MinbpeError::MalformedModel {
+        line,
+        message: message.to_string(),
     }
 }
 
@@ -334,6 +1187,24 @@ pub fn merge(ids: &[Token], pair: (Token, Token), new_id: Token) -> Vec<Token> {
     new_ids
 }
 
+/// Checks that no special token has the id of a byte, of a merge or of another special token.
+pub(crate) fn check_special_token_ids(
+    special_tokens: &IndexMap<String, Token>,
+    merges: &IndexMap<(Token, Token), Token>,
+) -> Result<()> {
+    let merge_ids: HashSet<Token> = merges.values().copied().collect();
+    let mut seen: HashSet<Token> = HashSet::new();
+    for (token, &id) in special_tokens {
+        if id < 256 || merge_ids.contains(&id) || !seen.insert(id) {
+            return Err(MinbpeError::SpecialTokenIdCollision {
+                token: token.clone(),
+                id,
+            });
+        }
+    }
+    Ok(())
+}
+
 /// vocab is simply and deterministically derived from merges
 pub fn build_vocab(
     special_tokens: &IndexMap<String, Token>,
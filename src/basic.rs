@@ -1,8 +1,16 @@
+use std::borrow::Cow;
+use std::path::Path;
+use std::sync::OnceLock;
+
 use indexmap::IndexMap;
 
-use crate::base::{
-    get_max_entry, get_stats, merge, Loadable, Saveable, Token, Tokenizer, Trainable,
-};
+use crate::base::{check_special_token_ids, Loadable, Saveable, Token, Tokenizer, Trainable};
+use crate::error::{MinbpeError, Result};
+use crate::observer::TrainingObserver;
+use crate::offsets::{SpanBuilder, TokenSpan};
+use crate::pair_trainer::{resume_merges, train_merges, Trained};
+use crate::trainer::{ChunkCounts, TrainingConfig, TrainingOutcome};
+use crate::TokenTables;
 
 /// Minimal (byte-level) Byte Pair Encoding tokenizer.
 ///
@@ -35,6 +43,8 @@ pub struct BasicTokenizer {
     special_tokens: IndexMap<String, Token>,
     merges: IndexMap<(Token, Token), Token>,
     vocab: IndexMap<Token, Vec<u8>>,
+    /// Built from the merges and vocabulary when first used, see `tables`.
+    tables: OnceLock<TokenTables>,
 }
 
 impl BasicTokenizer {
@@ -43,8 +53,26 @@ impl BasicTokenizer {
             special_tokens: IndexMap::new(),
             merges: IndexMap::new(),
             vocab: IndexMap::new(),
+            tables: OnceLock::new(),
         }
     }
+
+    /// The lookup tables compiled from the merges and vocabulary. They are built when first
+    /// used after the merges or vocabulary changed, so that setting both builds them once.
+    fn tables(&self) -> &TokenTables {
+        self.tables
+            .get_or_init(|| TokenTables::new(&self.merges, &self.vocab))
+    }
+
+    /// Encodes the given text, also returning where each token is in the text.
+    pub fn encode_with_offsets(&self, text: &str) -> Vec<TokenSpan> {
+        let ids = self.tables().encode_bytes(text.as_bytes());
+        let mut spans = SpanBuilder::new();
+        spans.push_part(0, &ids, |idx| {
+            self.tables().token_bytes(idx).map_or(0, <[u8]>::len)
+        });
+        spans.finish(text)
+    }
 }
 
 impl Default for BasicTokenizer {
@@ -66,86 +94,71 @@ impl Tokenizer for BasicTokenizer {
         &self.vocab
     }
 
-    fn decode(&self, ids: &[Token]) -> String {
-        // Given ids (list of integers), return Rust string
-        let text_bytes: Vec<u8> = ids
-            .iter()
-            .flat_map(|&idx| self.vocab[&idx].clone())
-            .collect();
-        String::from_utf8_lossy(&text_bytes).into_owned()
+    fn token_bytes(&self, idx: Token) -> Option<Cow<'_, [u8]>> {
+        self.tables()
+            .token_bytes(idx)
+            .or_else(|| {
+                self.special_tokens
+                    .iter()
+                    .find(|&(_, &id)| id == idx)
+                    .map(|(token, _)| token.as_bytes())
+            })
+            .map(Cow::Borrowed)
     }
 
-    fn encode(&self, text: &str) -> Vec<Token> {
+    fn try_encode(&self, text: &str) -> Result<Vec<Token>> {
         // Given a string text, return the token ids
-        let text_bytes = text.as_bytes();
-        let mut ids: Vec<Token> = text_bytes.iter().map(|&b| b as Token).collect();
-        while ids.len() >= 2 {
-            // Find the pair with the lowest merge index
-            let stats = get_stats(&ids);
-
-            let pair_opt = stats
-                .keys()
-                .filter_map(|&pair| self.merges.get(&pair).map(|_| pair))
-                .min_by_key(|&pair| self.merges[&pair]);
-
-            match pair_opt {
-                None => break, // If there are no more merges available, break
-                Some(pair) => {
-                    // Otherwise, merge the best pair (lowest merge index)
-                    let idx = self.merges[&pair];
-                    ids = merge(&ids, pair, idx);
-                }
-            };
+        Ok(self.tables().encode_bytes(text.as_bytes()))
+    }
+}
+
+impl BasicTokenizer {
+    /// Saves the result of training as instance variables. The special tokens are only replaced
+    /// if the training configuration declares some; the ones kept must not have the id of a
+    /// learned merge, or the tokenizer is left unchanged.
+    fn save_trained(
+        &mut self,
+        trained: Trained,
+        config: &TrainingConfig,
+    ) -> Result<TrainingOutcome> {
+        if config.special_tokens.is_empty() {
+            check_special_token_ids(&self.special_tokens, &trained.merges)?;
+        }
+        self.merges = trained.merges;
+        self.vocab = trained.vocab;
+        self.tables = OnceLock::new();
+        if !config.special_tokens.is_empty() {
+            self.special_tokens = trained.special_tokens;
         }
-        ids
+        Ok(trained.outcome)
     }
 }
 
 impl Trainable for BasicTokenizer {
-    fn train(&mut self, text: &str, vocab_size: Token, verbose: bool) {
-        assert!(vocab_size >= 256, "Vocab size must be at least 256");
-        let num_merges = vocab_size - 256;
-
-        // Input text preprocessing
-        let text_bytes = text.as_bytes();
-        let mut ids: Vec<Token> = text_bytes.iter().map(|&b| b as Token).collect();
-
-        // Iteratively merge the most common pairs to create new tokens
-        let mut merges: IndexMap<(Token, Token), Token> = IndexMap::new();
-        let mut vocab: IndexMap<Token, Vec<u8>> =
-            (0..256).map(|idx| (idx, vec![idx as u8])).collect();
-        for i in 0..num_merges {
-            // Count up the number of times every consecutive pair appears
-            let stats = get_stats(&ids);
-            // Find the pair with the highest count
-            let pair = get_max_entry(&stats).unwrap().0;
-            // Mint a new token: assign it the next available id
-            let idx = 256 + i;
-            // Replace all occurrences of pair in ids with idx
-            ids = merge(&ids, *pair, idx);
-            // Save the merge
-            merges.insert(*pair, idx);
-            vocab.insert(
-                idx,
-                [vocab[&pair.0].clone(), vocab[&pair.1].clone()].concat(),
-            );
-            // Prints
-            if verbose {
-                println!(
-                    "merge {}/{}: {:?} -> {} ({:?}) had {} occurrences",
-                    i + 1,
-                    num_merges,
-                    pair,
-                    idx,
-                    vocab[&idx],
-                    stats[pair]
-                );
-            }
-        }
+    /// Without a split pattern the whole text is a single chunk.
+    fn pre_tokenize(&self, text: &str, chunks: &mut ChunkCounts) -> Result<()> {
+        chunks.add(text.as_bytes());
+        Ok(())
+    }
 
-        // Save instance variables
-        self.merges = merges;
-        self.vocab = vocab; // FIXME: vs. build_vocab(&self.special_tokens, &self.merges);
+    fn train_chunks_with_config(
+        &mut self,
+        chunks: &ChunkCounts,
+        config: &TrainingConfig,
+        observer: &mut dyn TrainingObserver,
+    ) -> Result<TrainingOutcome> {
+        let trained = train_merges(chunks, config, "", observer)?;
+        self.save_trained(trained, config)
+    }
+
+    fn resume_training(
+        &mut self,
+        checkpoint: &Path,
+        config: &TrainingConfig,
+        observer: &mut dyn TrainingObserver,
+    ) -> Result<TrainingOutcome> {
+        let trained = resume_merges(checkpoint, config, "", observer)?;
+        self.save_trained(trained, config)
     }
 }
 
@@ -156,12 +169,14 @@ impl Saveable for BasicTokenizer {
 }
 
 impl Loadable for BasicTokenizer {
-    fn set_pattern(&mut self, pattern: &str) {
+    fn try_set_pattern(&mut self, pattern: &str) -> Result<()> {
         let temp = pattern.trim();
 
         if !temp.is_empty() {
-            panic!("Cannot set a non-empty pattern!")
+            return Err(MinbpeError::UnsupportedPattern(pattern.to_string()));
         }
+
+        Ok(())
     }
 
     fn set_special_tokens(&mut self, special_tokens: IndexMap<String, Token>) {
@@ -170,9 +185,11 @@ impl Loadable for BasicTokenizer {
 
     fn set_merges(&mut self, merges: IndexMap<(Token, Token), Token>) {
         self.merges = merges;
+        self.tables = OnceLock::new();
     }
 
     fn set_vocab(&mut self, vocab: IndexMap<Token, Vec<u8>>) {
         self.vocab = vocab;
+        self.tables = OnceLock::new();
     }
 }
@@ -0,0 +1,188 @@
+//! Contains the merge engine that encoding uses to apply the merges of a tokenizer to a
+//! sequence of ids.
+//!
+//! The ids are kept in a doubly-linked list and the mergeable pairs in a min-heap keyed by
+//! merge rank (the id of the merged token) and position, so applying all merges to `n` ids takes
+//! `O(n log n)` instead of recounting every pair and rebuilding the sequence for every merge.
+//!
+//! The result is exactly that of repeatedly merging every (non-overlapping, left to right)
+//! occurrence of the pair with the lowest rank, which is how the tokenizers used to encode.
+
+use std::cmp::Reverse;
+use std::collections::BinaryHeap;
+
+use crate::Token;
+
+/// Marks the absence of a previous or next symbol.
+const NONE: usize = usize::MAX;
+
+/// A mergeable pair in the heap: its rank, the position of its left symbol and the pair itself,
+/// which tells whether the entry is stale.
+type Entry = (Token, usize, (Token, Token));
+
+/// Applies merges to `ids`. `rank` returns the id of the token a pair merges into, or `None` if
+/// the pair does not merge; pairs with lower ids are merged first.
+pub(crate) fn apply_merges<F>(ids: &[Token], rank: F) -> Vec<Token>
+where
+    F: Fn((Token, Token)) -> Option<Token>,
+{
+    if ids.len() < 2 {
+        return ids.to_vec();
+    }
+
+    let n = ids.len();
+    let mut symbols = ids.to_vec();
+    let mut prev: Vec<usize> = (0..n).map(|i| if i == 0 { NONE } else { i - 1 }).collect();
+    let mut next: Vec<usize> = (0..n)
+        .map(|i| if i + 1 == n { NONE } else { i + 1 })
+        .collect();
+
+    let mut heap: BinaryHeap<Reverse<Entry>> = BinaryHeap::new();
+    for (i, pair) in ids.windows(2).enumerate() {
+        let pair = (pair[0], pair[1]);
+        if let Some(r) = rank(pair) {
+            heap.push(Reverse((r, i, pair)));
+        }
+    }
+
+    let mut batch: Vec<Entry> = Vec::new();
+    while let Some(&Reverse((min_rank, _, _))) = heap.peek() {
+        // Take every occurrence of the lowest ranked pair at once: pairs created by merging them
+        // are only considered once all of them are merged, even if they rank lower
+        batch.clear();
+        while let Some(&Reverse(entry)) = heap.peek() {
+            if entry.0 != min_rank {
+                break;
+            }
+            batch.push(entry);
+            heap.pop();
+        }
+
+        // Entries pop in order of position, so occurrences are merged left to right
+        for &(new_id, pos, pair) in &batch {
+            // Skip stale entries: the pair at `pos` changed since the entry was pushed
+            let right = next[pos];
+            if symbols[pos] != pair.0 || right == NONE || symbols[right] != pair.1 {
+                continue;
+            }
+
+            // Merge the right symbol into the left one
+            let after = next[right];
+            symbols[pos] = new_id;
+            next[pos] = after;
+            if after != NONE {
+                prev[after] = pos;
+            }
+            // Unlinking `right` makes any entry at its position stale
+            next[right] = NONE;
+
+            let before = prev[pos];
+            if before != NONE {
+                let pair = (symbols[before], new_id);
+                if let Some(r) = rank(pair) {
+                    heap.push(Reverse((r, before, pair)));
+                }
+            }
+            if after != NONE {
+                let pair = (new_id, symbols[after]);
+                if let Some(r) = rank(pair) {
+                    heap.push(Reverse((r, pos, pair)));
+                }
+            }
+        }
+    }
+
+    let mut merged = Vec::new();
+    let mut pos = 0;
+    while pos != NONE {
+        merged.push(symbols[pos]);
+        pos = next[pos];
+    }
+    merged
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{get_stats, merge};
+    use indexmap::IndexMap;
+
+    // The straightforward algorithm the engine replaces
+    fn reference(ids: &[Token], merges: &IndexMap<(Token, Token), Token>) -> Vec<Token> {
+        let mut ids = ids.to_vec();
+        while ids.len() >= 2 {
+            let stats = get_stats(&ids);
+            let Some(pair) = stats
+                .keys()
+                .filter(|pair| merges.contains_key(*pair))
+                .min_by_key(|pair| merges[*pair])
+            else {
+                break;
+            };
+            ids = merge(&ids, *pair, merges[pair]);
+        }
+        ids
+    }
+
+    fn bytes(text: &str) -> Vec<Token> {
+        text.bytes().map(|b| b as Token).collect()
+    }
+
+    #[test]
+    fn test_overlapping_pairs() {
+        let merges = IndexMap::from([((97, 97), 256), ((256, 97), 257)]);
+        for text in ["a", "aa", "aaa", "aaaa", "aaaaa", "aaaaaaa"] {
+            let ids = bytes(text);
+            assert_eq!(
+                apply_merges(&ids, |pair| merges.get(&pair).copied()),
+                reference(&ids, &merges),
+                "{:?}",
+                text
+            );
+        }
+    }
+
+    #[test]
+    fn test_matches_reference() {
+        // Merges learned from the Wikipedia example, applied to other texts
+        let mut merges = IndexMap::new();
+        let mut ids = bytes("aaabdaaabac aaabdaaabac abracadabra");
+        for idx in 256..272 {
+            let stats = get_stats(&ids);
+            let Some((&pair, _)) = crate::get_max_entry(&stats) else {
+                break;
+            };
+            ids = merge(&ids, pair, idx);
+            merges.insert(pair, idx);
+        }
+
+        for text in [
+            "",
+            "abracadabra",
+            "aaabdaaabac",
+            "daaabacaaabdaaabacabracadabraaaab",
+            "cadabra aaabdaaabac aaaaaaaaa abab",
+        ] {
+            let ids = bytes(text);
+            assert_eq!(
+                apply_merges(&ids, |pair| merges.get(&pair).copied()),
+                reference(&ids, &merges),
+                "{:?}",
+                text
+            );
+        }
+    }
+
+    #[test]
+    fn test_lower_rank_created_by_batch() {
+        // Merging the first (97, 98) of "abab" creates (300, 97), which ranks lower than the
+        // second (97, 98) but is only considered once every occurrence of (97, 98) is merged.
+        let merges = IndexMap::from([((97, 98), 300), ((300, 97), 200)]);
+        let ids = bytes("abab");
+        assert_eq!(
+            apply_merges(&ids, |pair| merges.get(&pair).copied()),
+            vec![300, 300]
+        );
+        assert_eq!(reference(&ids, &merges), vec![300, 300]);
+    }
+}
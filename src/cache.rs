@@ -0,0 +1,140 @@
+//! Contains the cache of encoded chunks that regex tokenizers can use to skip applying the merges
+//! to chunks they have already encoded.
+
+use std::num::NonZeroUsize;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Mutex, MutexGuard};
+
+use lru::LruCache;
+
+use crate::Token;
+
+/// Statistics of a `ChunkCache`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct CacheStats {
+    /// Number of chunks found in the cache.
+    pub hits: u64,
+
+    /// Number of chunks not found in the cache, which were encoded and inserted.
+    pub misses: u64,
+
+    /// Number of chunks currently in the cache.
+    pub len: usize,
+
+    /// Maximum number of chunks in the cache.
+    pub capacity: usize,
+}
+
+impl CacheStats {
+    /// Returns the fraction of lookups that were hits, or 0 if there were none.
+    pub fn hit_rate(&self) -> f64 {
+        let lookups = self.hits + self.misses;
+        if lookups == 0 {
+            0.0
+        } else {
+            self.hits as f64 / lookups as f64
+        }
+    }
+}
+
+/// A thread-safe cache of the ids of encoded chunks, evicting the least recently used chunk once
+/// it holds `capacity` chunks.
+///
+/// # Examples
+///
+/// ```
+/// use minbpe::ChunkCache;
+/// use std::num::NonZeroUsize;
+///
+/// let cache = ChunkCache::new(NonZeroUsize::new(2).unwrap());
+/// assert_eq!(cache.get_or_insert_with(b"ab", |_| vec![256]), vec![256]);
+/// assert_eq!(cache.get_or_insert_with(b"ab", |_| unreachable!()), vec![256]);
+///
+/// let stats = cache.stats();
+/// assert_eq!((stats.hits, stats.misses, stats.len), (1, 1, 1));
+/// ```
+#[derive(Debug)]
+pub struct ChunkCache {
+    entries: Mutex<LruCache<Box<[u8]>, Vec<Token>>>,
+    hits: AtomicU64,
+    misses: AtomicU64,
+}
+
+impl ChunkCache {
+    pub fn new(capacity: NonZeroUsize) -> Self {
+        ChunkCache {
+            entries: Mutex::new(LruCache::new(capacity)),
+            hits: AtomicU64::new(0),
+            misses: AtomicU64::new(0),
+        }
+    }
+
+    // A panic while holding the lock cannot leave the cache inconsistent, so poisoning is ignored
+    fn entries(&self) -> MutexGuard<'_, LruCache<Box<[u8]>, Vec<Token>>> {
+        self.entries.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Returns the ids of `chunk`, calling `encode` and caching its result if `chunk` is not in
+    /// the cache. The lock is not held while encoding.
+    pub fn get_or_insert_with<F>(&self, chunk: &[u8], encode: F) -> Vec<Token>
+    where
+        F: FnOnce(&[u8]) -> Vec<Token>,
+    {
+        if let Some(ids) = self.entries().get(chunk) {
+            self.hits.fetch_add(1, Ordering::Relaxed);
+            return ids.clone();
+        }
+        self.misses.fetch_add(1, Ordering::Relaxed);
+        let ids = encode(chunk);
+        self.entries().put(chunk.into(), ids.clone());
+        ids
+    }
+
+    /// Removes every chunk from the cache, e.g. because the merges changed. The hit and miss
+    /// counts are kept.
+    pub fn clear(&self) {
+        self.entries().clear();
+    }
+
+    pub fn stats(&self) -> CacheStats {
+        let entries = self.entries();
+        CacheStats {
+            hits: self.hits.load(Ordering::Relaxed),
+            misses: self.misses.load(Ordering::Relaxed),
+            len: entries.len(),
+            capacity: entries.cap().get(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_evicts_least_recently_used() {
+        let cache = ChunkCache::new(NonZeroUsize::new(2).unwrap());
+        cache.get_or_insert_with(b"a", |_| vec![97]);
+        cache.get_or_insert_with(b"b", |_| vec![98]);
+        // Using "a" makes "b" the least recently used chunk
+        cache.get_or_insert_with(b"a", |_| unreachable!());
+        cache.get_or_insert_with(b"c", |_| vec![99]);
+
+        assert_eq!(cache.get_or_insert_with(b"a", |_| vec![0]), vec![97]);
+        assert_eq!(cache.get_or_insert_with(b"b", |_| vec![0]), vec![0]);
+        assert_eq!(
+            cache.stats(),
+            CacheStats {
+                hits: 2,
+                misses: 4,
+                len: 2,
+                capacity: 2
+            }
+        );
+        assert!((cache.stats().hit_rate() - 1.0 / 3.0).abs() < 1e-9);
+
+        cache.clear();
+        assert_eq!(cache.stats().len, 0);
+        assert_eq!(cache.stats().hits, 2);
+    }
+}
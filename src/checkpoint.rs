@@ -0,0 +1,369 @@
+//! Contains the checkpoint file written periodically during training, from which an
+//! interrupted training run can be resumed.
+//!
+//! Unlike a `.model` file, a checkpoint holds the state of the trainer as well as the merges
+//! learned so far: every distinct chunk of the corpus, as currently merged, and the number of
+//! times it occurs. Its header fingerprints the run, so that it is only resumed by the same
+//! run: the split pattern, the special tokens of the configuration, and a hash of the chunks of
+//! the corpus and their counts. The format is line-based text:
+//!
+//! ```text
+//! minbpe checkpoint v2
+//! <split pattern>
+//! <number of special tokens>
+//! <special token>                    (one line per special token, in order)
+//! <chunk hash>                       (16 hexadecimal digits)
+//! <number of merges>
+//! <left id> <right id> <new id>      (one line per merge, in order)
+//! <number of chunks>
+//! <count> <id> <id> ...              (one line per chunk, in order of first appearance)
+//! ```
+//!
+//! The chunk hash is the 64-bit FNV-1a hash of every chunk, before any merge, as its count and
+//! length in little-endian `u64`s followed by its bytes.
+
+use std::fs::{self, File};
+use std::io::{BufRead, BufReader, BufWriter, Write};
+use std::path::Path;
+
+use indexmap::IndexMap;
+
+use crate::pair_trainer::PairTrainer;
+use crate::trainer::ChunkCounts;
+use crate::{build_vocab, Count, MinbpeError, Result, Token};
+
+const VERSION: &str = "minbpe checkpoint v2";
+
+const FNV_OFFSET_BASIS: u64 = 0xcbf2_9ce4_8422_2325;
+const FNV_PRIME: u64 = 0x0000_0100_0000_01b3;
+
+/// Computes the chunk hash of a corpus incrementally, see the module documentation.
+struct ChunkHasher(u64);
+
+impl ChunkHasher {
+    fn new() -> Self {
+        ChunkHasher(FNV_OFFSET_BASIS)
+    }
+
+    fn write(&mut self, bytes: &[u8]) {
+        for &b in bytes {
+            self.0 = (self.0 ^ u64::from(b)).wrapping_mul(FNV_PRIME);
+        }
+    }
+
+    fn add_chunk(&mut self, chunk: &[u8], count: Count) {
+        self.write(&count.to_le_bytes());
+        self.write(&(chunk.len() as u64).to_le_bytes());
+        self.write(chunk);
+    }
+}
+
+/// What a checkpoint must match to be resumed: the split pattern and special tokens of the
+/// training run, and the hash of the chunks of its corpus.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Fingerprint {
+    pub(crate) pattern: String,
+    pub(crate) special_tokens: Vec<String>,
+    pub(crate) chunk_hash: u64,
+}
+
+impl Fingerprint {
+    pub(crate) fn new(pattern: &str, special_tokens: &[String], chunks: &ChunkCounts) -> Self {
+        let mut hasher = ChunkHasher::new();
+        for (chunk, count) in chunks.iter() {
+            hasher.add_chunk(chunk, count);
+        }
+        Fingerprint {
+            pattern: pattern.to_string(),
+            special_tokens: special_tokens.to_vec(),
+            chunk_hash: hasher.0,
+        }
+    }
+
+    /// Checks that a run with `pattern` and `special_tokens` can resume from the checkpoint.
+    ///
+    /// # Errors
+    ///
+    /// Returns `MinbpeError::CheckpointMismatch` if either differs from the checkpoint's.
+    pub(crate) fn check(&self, pattern: &str, special_tokens: &[String]) -> Result<()> {
+        if self.pattern != pattern {
+            return Err(MinbpeError::CheckpointMismatch(format!(
+                "the checkpoint has the split pattern {:?}, not {:?}",
+                self.pattern, pattern
+            )));
+        }
+        if self.special_tokens != special_tokens {
+            return Err(MinbpeError::CheckpointMismatch(format!(
+                "the checkpoint has the special tokens {:?}, not {:?}",
+                self.special_tokens, special_tokens
+            )));
+        }
+        Ok(())
+    }
+}
+
+/// The state of a training run.
+pub(crate) struct Checkpoint {
+    pub(crate) fingerprint: Fingerprint,
+    pub(crate) merges: IndexMap<(Token, Token), Token>,
+    pub(crate) words: Vec<(Vec<Token>, Count)>,
+}
+
+impl Checkpoint {
+    /// Writes the state of a training run to `path`, through a temporary file next to it so
+    /// that an existing checkpoint is only replaced by a complete one.
+    pub(crate) fn write(
+        path: &Path,
+        fingerprint: &Fingerprint,
+        merges: &IndexMap<(Token, Token), Token>,
+        trainer: &PairTrainer,
+    ) -> Result<()> {
+        let mut temp_path = path.as_os_str().to_owned();
+        temp_path.push(".tmp");
+
+        let mut file = BufWriter::new(File::create(&temp_path)?);
+        writeln!(file, "{}", VERSION)?;
+        writeln!(file, "{}", fingerprint.pattern)?;
+        writeln!(file, "{}", fingerprint.special_tokens.len())?;
+        for token in &fingerprint.special_tokens {
+            writeln!(file, "{}", token)?;
+        }
+        writeln!(file, "{:016x}", fingerprint.chunk_hash)?;
+        writeln!(file, "{}", merges.len())?;
+        for (&(idx1, idx2), idx) in merges {
+            writeln!(file, "{} {} {}", idx1, idx2, idx)?;
+        }
+        writeln!(file, "{}", trainer.num_words())?;
+        for (ids, count) in trainer.words() {
+            write!(file, "{}", count)?;
+            for id in ids {
+                write!(file, " {}", id)?;
+            }
+            writeln!(file)?;
+        }
+        file.into_inner().map_err(|e| e.into_error())?.sync_all()?;
+
+        fs::rename(&temp_path, path)?;
+        Ok(())
+    }
+
+    /// Reads the state of a training run whose merges have ids from `first_id` from `path`.
+    /// Merges and chunks may only refer to the byte tokens and to merges, and the chunks must
+    /// have the chunk hash of the header once their merges are undone.
+    ///
+    /// # Errors
+    ///
+    /// Returns `MinbpeError::Io` if the file cannot be read, and
+    /// `MinbpeError::MalformedCheckpoint` if it is not a valid checkpoint.
+    pub(crate) fn read(path: &Path, first_id: Token) -> Result<Checkpoint> {
+        let reader = BufReader::new(File::open(path)?);
+        let lines: Vec<String> = reader.lines().collect::<std::io::Result<_>>()?;
+
+        // Line numbers are 1-based for error reporting
+        let mut line_iter = lines.iter().enumerate().map(|(i, line)| (i + 1, line));
+        let mut next_line = |what: &str| {
+            line_iter
+                .next()
+                .ok_or_else(|| MinbpeError::MalformedCheckpoint {
+                    line: lines.len() + 1,
+                    message: format!("Missing {}", what),
+                })
+        };
+
+        let (line, version) = next_line("version line")?;
+        if version != VERSION {
+            return Err(malformed_checkpoint(line, "Invalid version"));
+        }
+
+        let (_, pattern) = next_line("pattern line")?;
+        let (line, num_special) = next_line("number of special tokens line")?;
+        let num_special = parse_number::<usize>(line, num_special)?;
+        let mut special_tokens = Vec::with_capacity(num_special);
+        for _ in 0..num_special {
+            let (_, token) = next_line("special token line")?;
+            special_tokens.push(token.clone());
+        }
+        let (hash_line, chunk_hash) = next_line("chunk hash line")?;
+        let chunk_hash = u64::from_str_radix(chunk_hash, 16)
+            .map_err(|_| malformed_checkpoint(hash_line, "Invalid chunk hash"))?;
+
+        let (line, num_merges) = next_line("number of merges line")?;
+        let num_merges = parse_number::<usize>(line, num_merges)?;
+
+        // Ids of tokens that merges and chunks can refer to: bytes and earlier merges
+        let known = |id: Token, end: Token| (0..256).contains(&id) || (first_id..end).contains(&id);
+
+        let mut merges = IndexMap::new();
+        for i in 0..num_merges {
+            let (line, merge_line) = next_line("merge line")?;
+            let parts = parse_numbers::<Token>(line, merge_line)?;
+            let idx = first_id + i as Token;
+            match parts[..] {
+                [idx1, idx2, new_idx] if known(idx1, idx) && known(idx2, idx) && new_idx == idx => {
+                    merges.insert((idx1, idx2), idx);
+                }
+                _ => return Err(malformed_checkpoint(line, "Invalid merge")),
+            }
+        }
+        let end = first_id + num_merges as Token;
+
+        let (line, num_words) = next_line("number of chunks line")?;
+        let num_words = parse_number::<usize>(line, num_words)?;
+
+        let mut words = Vec::with_capacity(num_words);
+        for _ in 0..num_words {
+            let (line, word_line) = next_line("chunk line")?;
+            let mut parts = word_line.split(' ');
+            let count = parse_number::<Count>(line, parts.next().unwrap_or_default())?;
+            let ids = parts
+                .map(|part| match part.parse::<Token>() {
+                    Ok(id) if known(id, end) => Ok(id),
+                    _ => Err(malformed_checkpoint(line, "Invalid token id")),
+                })
+                .collect::<Result<Vec<Token>>>()?;
+            words.push((ids, count));
+        }
+
+        if let Some((line, _)) = line_iter.next() {
+            return Err(malformed_checkpoint(line, "Unexpected line"));
+        }
+
+        // The hash is of the chunks before any merge
+        let vocab = build_vocab(&IndexMap::new(), &merges);
+        let mut hasher = ChunkHasher::new();
+        let mut chunk = Vec::new();
+        for (ids, count) in &words {
+            chunk.clear();
+            for id in ids {
+                chunk.extend_from_slice(&vocab[id]);
+            }
+            hasher.add_chunk(&chunk, *count);
+        }
+        if hasher.0 != chunk_hash {
+            return Err(malformed_checkpoint(
+                hash_line,
+                "The chunks do not have the chunk hash",
+            ));
+        }
+
+        Ok(Checkpoint {
+            fingerprint: Fingerprint {
+                pattern: pattern.clone(),
+                special_tokens,
+                chunk_hash,
+            },
+            merges,
+            words,
+        })
+    }
+}
+
+fn parse_number<T: std::str::FromStr>(line: usize, s: &str) -> Result<T> {
+    s.parse::<T>()
+        .map_err(|_| malformed_checkpoint(line, &format!("Invalid number {:?}", s)))
+}
+
+fn parse_numbers<T: std::str::FromStr>(line: usize, s: &str) -> Result<Vec<T>> {
+    s.split(' ').map(|part| parse_number(line, part)).collect()
+}
+
+fn malformed_checkpoint(line: usize, message: &str) -> MinbpeError {
+    MinbpeError::MalformedCheckpoint {
+        line,
+        message: message.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    /// The header of a checkpoint of a run over the chunks "aab" twice, "" once and "a" three
+    /// times with no pattern and no special tokens, then `rest`.
+    fn checkpoint(rest: &str) -> String {
+        let mut chunks = ChunkCounts::new();
+        for chunk in ["aab", "aab", "", "a", "a", "a"] {
+            chunks.add(chunk.as_bytes());
+        }
+        let fingerprint = Fingerprint::new("", &[], &chunks);
+        format!(
+            "{}\n\n0\n{:016x}\n{}",
+            VERSION, fingerprint.chunk_hash, rest
+        )
+    }
+
+    #[test]
+    fn test_write_read() {
+        let mut chunks = ChunkCounts::new();
+        for chunk in ["aab", "aab", "", "a", "a", "a"] {
+            chunks.add(chunk.as_bytes());
+        }
+        let special_tokens = vec!["<|endoftext|>".to_string()];
+        let fingerprint = Fingerprint::new(r"\p{L}+| ", &special_tokens, &chunks);
+
+        let mut trainer = PairTrainer::new(vec![(vec![97, 97, 98], 2), (vec![], 1), (vec![97], 3)]);
+        trainer.merge((97, 97), 256);
+        let merges = IndexMap::from([((97, 97), 256)]);
+
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("train.checkpoint");
+        Checkpoint::write(&path, &fingerprint, &merges, &trainer).unwrap();
+
+        let checkpoint = Checkpoint::read(&path, 256).unwrap();
+        assert_eq!(checkpoint.fingerprint, fingerprint);
+        assert_eq!(checkpoint.merges, merges);
+        assert_eq!(
+            checkpoint.words,
+            vec![(vec![256, 98], 2), (vec![], 1), (vec![97], 3)]
+        );
+
+        assert!(fingerprint.check(r"\p{L}+| ", &special_tokens).is_ok());
+        assert!(matches!(
+            fingerprint.check("", &special_tokens),
+            Err(MinbpeError::CheckpointMismatch(_))
+        ));
+        assert!(matches!(
+            fingerprint.check(r"\p{L}+| ", &[]),
+            Err(MinbpeError::CheckpointMismatch(_))
+        ));
+    }
+
+    #[test]
+    fn test_read_malformed() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("train.checkpoint");
+        let chunks = "3\n2 97 97 98\n1\n3 97\n";
+        for (content, line) in [
+            ("minbpe v1\n".to_string(), 1),
+            ("minbpe checkpoint v1\n0\n0\n".to_string(), 1),
+            (format!("{}\n", VERSION), 2),
+            (format!("{}\n\nx\n", VERSION), 3),
+            (format!("{}\n\n1\n", VERSION), 4),
+            (format!("{}\n\n0\nxyz\n", VERSION), 4),
+            (checkpoint(""), 5),
+            (checkpoint("1\n97 98 257\n0\n"), 6),
+            (checkpoint("1\n97 256 256\n0\n"), 6),
+            (checkpoint("0\n1\n1 256\n"), 7),
+            (checkpoint("0\n1\nx 97\n"), 7),
+            (checkpoint("0\n0\n\n"), 7),
+            // The chunks of another corpus, or counted differently
+            (checkpoint("0\n3\n2 97 97 98\n1\n3 98\n"), 4),
+            (checkpoint("0\n3\n2 97 97 98\n2\n3 97\n"), 4),
+        ] {
+            fs::write(&path, &content).unwrap();
+            match Checkpoint::read(&path, 256) {
+                Err(MinbpeError::MalformedCheckpoint { line: l, .. }) => {
+                    assert_eq!(l, line, "{:?}", content)
+                }
+                _ => panic!("Expected a malformed checkpoint: {:?}", content),
+            }
+        }
+
+        // Merged chunks have the hash of the chunks they were merged from
+        fs::write(&path, checkpoint(&format!("0\n{}", chunks))).unwrap();
+        assert!(Checkpoint::read(&path, 256).is_ok());
+        fs::write(&path, checkpoint("1\n97 97 256\n3\n2 256 98\n1\n3 97\n")).unwrap();
+        assert!(Checkpoint::read(&path, 256).is_ok());
+    }
+}
@@ -0,0 +1,135 @@
+//! Contains the options controlling how tokenizers decode ids into text.
+
+use crate::{MinbpeError, Result};
+
+/// How decoding handles bytes that are not valid UTF-8.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Utf8Policy {
+    /// Return `MinbpeError::InvalidUtf8`.
+    Strict,
+
+    /// Replace each invalid sequence with U+FFFD, like `String::from_utf8_lossy`.
+    #[default]
+    Lossy,
+
+    /// Replace each invalid byte with its escape, e.g. `\xE6`, and each backslash with `\\`, so
+    /// that unescaping the text gives back the decoded bytes.
+    Escape,
+}
+
+/// How decoding handles ids that are neither in the vocabulary nor special tokens.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum UnknownIdPolicy {
+    /// Return `MinbpeError::UnknownTokenId`.
+    #[default]
+    Error,
+
+    /// Leave the id out of the decoded text.
+    Skip,
+}
+
+/// Options for `Tokenizer::try_decode_with`. The default options are those of `decode`.
+///
+/// # Examples
+///
+/// ```
+/// use minbpe::{BasicTokenizer, DecodeOptions, Tokenizer, Trainable, Utf8Policy};
+///
+/// let mut tokenizer = BasicTokenizer::new();
+/// tokenizer.train("hi", 256, false);
+/// let ids = [104, 105, 0xE6];
+///
+/// let options = DecodeOptions {
+///     utf8: Utf8Policy::Escape,
+///     ..Default::default()
+/// };
+/// assert_eq!(tokenizer.try_decode_with(&ids, &options).unwrap(), "hi\\xE6");
+/// assert_eq!(tokenizer.decode(&ids), "hi\u{FFFD}");
+/// assert_eq!(tokenizer.decode_bytes(&ids), b"hi\xE6");
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct DecodeOptions {
+    pub utf8: Utf8Policy,
+    pub unknown_ids: UnknownIdPolicy,
+
+    /// Whether to leave special tokens out of the decoded text.
+    pub skip_special_tokens: bool,
+}
+
+/// Converts decoded bytes into text according to `policy`.
+pub(crate) fn bytes_to_string(bytes: Vec<u8>, policy: Utf8Policy) -> Result<String> {
+    match policy {
+        Utf8Policy::Strict => {
+            String::from_utf8(bytes).map_err(|e| MinbpeError::InvalidUtf8(e.utf8_error()))
+        }
+        Utf8Policy::Lossy => Ok(String::from_utf8_lossy(&bytes).into_owned()),
+        Utf8Policy::Escape => {
+            let mut text = String::with_capacity(bytes.len());
+            for chunk in bytes.utf8_chunks() {
+                // Backslashes are escaped too, so that a `\xE6` in the text is not mistaken
+                // for an escaped byte
+                text.push_str(&chunk.valid().replace('\\', "\\\\"));
+                for b in chunk.invalid() {
+                    text.push_str(&format!("\\x{:02X}", b));
+                }
+            }
+            Ok(text)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_bytes_to_string() {
+        // A valid "é", then a truncated "日" and a lone continuation byte
+        let bytes = b"\xC3\xA9 \xE6\x97 \xA9".to_vec();
+        assert!(matches!(
+            bytes_to_string(bytes.clone(), Utf8Policy::Strict),
+            Err(MinbpeError::InvalidUtf8(e)) if e.valid_up_to() == 3
+        ));
+        assert_eq!(
+            bytes_to_string(bytes.clone(), Utf8Policy::Lossy).unwrap(),
+            "é \u{FFFD} \u{FFFD}"
+        );
+        assert_eq!(
+            bytes_to_string(bytes, Utf8Policy::Escape).unwrap(),
+            "é \\xE6\\x97 \\xA9"
+        );
+    }
+
+    /// Reverses the escapes of `Utf8Policy::Escape`.
+    fn unescape(text: &str) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        let mut rest = text.as_bytes();
+        while let Some((&b, tail)) = rest.split_first() {
+            match (b, tail) {
+                (b'\\', [b'\\', tail @ ..]) => {
+                    bytes.push(b'\\');
+                    rest = tail;
+                }
+                (b'\\', [b'x', hi, lo, tail @ ..]) => {
+                    let hex = std::str::from_utf8(&[*hi, *lo]).unwrap().to_string();
+                    bytes.push(u8::from_str_radix(&hex, 16).unwrap());
+                    rest = tail;
+                }
+                _ => {
+                    bytes.push(b);
+                    rest = tail;
+                }
+            }
+        }
+        bytes
+    }
+
+    #[test]
+    fn test_escape_round_trip() {
+        // A literal "\xE6" and backslashes in the text, next to an invalid byte
+        let bytes = b"C:\\dir\\ \\xE6 \xE6 \\\\".to_vec();
+        let text = bytes_to_string(bytes.clone(), Utf8Policy::Escape).unwrap();
+        assert_eq!(text, "C:\\\\dir\\\\ \\\\xE6 \\xE6 \\\\\\\\");
+        assert_eq!(unescape(&text), bytes);
+    }
+}
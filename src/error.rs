@@ -0,0 +1,190 @@
+//! Contains the error type returned by the fallible (`try_*`) entry points of the crate.
+//! The panicking entry points are thin wrappers around these that panic with the
+//! `Display` rendering of the error.
+
+use std::fmt;
+use std::io;
+use std::path::PathBuf;
+
+use crate::Token;
+
+/// Errors that can occur while loading, saving, training, encoding or decoding.
+#[derive(Debug)]
+pub enum MinbpeError {
+    /// An I/O error while reading or writing a file.
+    Io(io::Error),
+
+    /// The model file does not have a `.model` extension.
+    InvalidModelExtension(PathBuf),
+
+    /// The first line of a model file is missing or is not `minbpe v1`.
+    InvalidVersion(String),
+
+    /// A header line of a model file (pattern, number of special tokens or a special token) is
+    /// missing or malformed. `line` is 1-based.
+    MalformedModel { line: usize, message: String },
+
+    /// A merge line of a model file could not be parsed. `line` is 1-based.
+    MalformedMerge { line: usize, content: String },
+
+    /// A line of a training checkpoint is missing or malformed. `line` is 1-based.
+    MalformedCheckpoint { line: usize, message: String },
+
+    /// A training checkpoint was written by a run with another split pattern or other special
+    /// tokens than the run resuming it.
+    CheckpointMismatch(String),
+
+    /// A line of a tiktoken rank file is malformed, the ranks do not form a byte-level BPE
+    /// vocabulary, or the sidecar of a saved rank file is malformed. `line` is 1-based, or 0 if
+    /// the problem is not on a single line.
+    MalformedRanks { line: usize, message: String },
+
+    /// A line of a JSONL training file is not a JSON object with a string member of the
+    /// configured name. `line` is 1-based.
+    MalformedJsonLine {
+        path: PathBuf,
+        line: usize,
+        message: String,
+    },
+
+    /// A HuggingFace `tokenizer.json`, or a GPT-2 `encoder.json` and `vocab.bpe`, is malformed
+    /// or is not a byte-level BPE model this crate supports.
+    InvalidTokenizerJson(String),
+
+    /// A SentencePiece `.model` file is malformed or is not a BPE model this crate supports.
+    InvalidSentencePieceModel(String),
+
+    /// A token id is neither in the vocabulary nor a special token.
+    UnknownTokenId(Token),
+
+    /// A special token was found in text encoded with `AllowedSpecial::NoneRaise`.
+    DisallowedSpecialToken(String),
+
+    /// A regular expression (split pattern or special token pattern) failed to compile.
+    InvalidRegex(Box<fancy_regex::Error>),
+
+    /// A regular expression exceeded its backtracking limit while matching.
+    RegexBacktrackLimit,
+
+    /// The requested vocabulary size is smaller than the 256 byte tokens and the special tokens.
+    InvalidVocabSize(Token),
+
+    /// A special token declared for training is empty or declared twice.
+    InvalidSpecialToken(String),
+
+    /// The id of a special token is also the id of a byte, a merge or another special token.
+    SpecialTokenIdCollision { token: String, id: Token },
+
+    /// The tokenizer does not support a (non-empty) split pattern.
+    UnsupportedPattern(String),
+
+    /// Decoded bytes were required to be valid UTF-8 but were not.
+    InvalidUtf8(std::str::Utf8Error),
+}
+
+/// Result type of the fallible entry points, with `MinbpeError` as the error.
+pub type Result<T> = std::result::Result<T, MinbpeError>;
+
+impl fmt::Display for MinbpeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MinbpeError::Io(e) => write!(f, "I/O error: {}", e),
+            MinbpeError::InvalidModelExtension(path) => {
+                write!(f, "Model file must have a .model extension: {:?}", path)
+            }
+            MinbpeError::InvalidVersion(version) => {
+                write!(f, "Invalid model file version: {:?}", version)
+            }
+            MinbpeError::MalformedModel { line, message } => {
+                write!(f, "Malformed model file at line {}: {}", line, message)
+            }
+            MinbpeError::MalformedMerge { line, content } => {
+                write!(f, "Malformed merge at line {}: {:?}", line, content)
+            }
+            MinbpeError::MalformedCheckpoint { line, message } => {
+                write!(f, "Malformed checkpoint at line {}: {}", line, message)
+            }
+            MinbpeError::CheckpointMismatch(message) => {
+                write!(f, "Checkpoint of another training run: {}", message)
+            }
+            MinbpeError::MalformedRanks { line: 0, message } => {
+                write!(f, "Malformed tiktoken ranks: {}", message)
+            }
+            MinbpeError::MalformedRanks { line, message } => {
+                write!(f, "Malformed tiktoken ranks at line {}: {}", line, message)
+            }
+            MinbpeError::MalformedJsonLine {
+                path,
+                line,
+                message,
+            } => {
+                write!(f, "Malformed JSON line {} of {:?}: {}", line, path, message)
+            }
+            MinbpeError::InvalidTokenizerJson(message) => {
+                write!(f, "Invalid HuggingFace tokenizer: {}", message)
+            }
+            MinbpeError::InvalidSentencePieceModel(message) => {
+                write!(f, "Invalid SentencePiece model: {}", message)
+            }
+            MinbpeError::UnknownTokenId(id) => write!(f, "Invalid token id: {}", id),
+            MinbpeError::DisallowedSpecialToken(token) => {
+                write!(f, "Special token found in text: {}", token)
+            }
+            MinbpeError::InvalidRegex(e) => write!(f, "Invalid regex: {}", e),
+            MinbpeError::RegexBacktrackLimit => write!(f, "Regex backtrack limit exceeded"),
+            MinbpeError::InvalidVocabSize(size) => write!(
+                f,
+                "Vocab size must be at least 256 plus the number of special tokens, got {}",
+                size
+            ),
+            MinbpeError::InvalidSpecialToken(token) => {
+                write!(f, "Special token is empty or declared twice: {:?}", token)
+            }
+            MinbpeError::SpecialTokenIdCollision { token, id } => {
+                write!(
+                    f,
+                    "Special token {:?} has id {} which is already taken",
+                    token, id
+                )
+            }
+            MinbpeError::UnsupportedPattern(pattern) => {
+                write!(f, "Cannot set a non-empty pattern: {:?}", pattern)
+            }
+            MinbpeError::InvalidUtf8(e) => write!(f, "Invalid UTF-8 sequence: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for MinbpeError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            MinbpeError::Io(e) => Some(e),
+            MinbpeError::InvalidRegex(e) => Some(e.as_ref()),
+            MinbpeError::InvalidUtf8(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for MinbpeError {
+    fn from(e: io::Error) -> Self {
+        MinbpeError::Io(e)
+    }
+}
+
+impl From<fancy_regex::Error> for MinbpeError {
+    fn from(e: fancy_regex::Error) -> Self {
+        match e {
+            fancy_regex::Error::RuntimeError(fancy_regex::RuntimeError::BacktrackLimitExceeded) => {
+                MinbpeError::RegexBacktrackLimit
+            }
+            e => MinbpeError::InvalidRegex(Box::new(e)),
+        }
+    }
+}
+
+impl From<std::str::Utf8Error> for MinbpeError {
+    fn from(e: std::str::Utf8Error) -> Self {
+        MinbpeError::InvalidUtf8(e)
+    }
+}
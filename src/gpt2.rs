@@ -0,0 +1,32 @@
+use indexmap::IndexMap;
+use lazy_static::lazy_static;
+
+use crate::regex::GPT2_SPLIT_PATTERN;
+use crate::tiktoken::{parse_tiktoken_ranks, tiktoken_preset};
+use crate::Token;
+
+lazy_static! {
+    static ref GPT2_SPECIAL_TOKENS: IndexMap<&'static str, Token> = {
+        let mut map = IndexMap::new();
+        map.insert("<|endoftext|>", 50256);
+        map
+    };
+}
+
+lazy_static! {
+    static ref GPT2_MERGEABLE_RANKS: IndexMap<Vec<u8>, Token> = {
+        // https://github.com/zurawiki/tiktoken-rs/blob/main/tiktoken-rs/assets/r50k_base.tiktoken
+        let r50k_base: &[u8] = include_bytes!("../assets/r50k_base.tiktoken");
+        parse_tiktoken_ranks(r50k_base).unwrap_or_else(|e| panic!("{}", e))
+    };
+}
+
+tiktoken_preset! {
+    /// The `r50k_base` tokenizer of GPT-2 and GPT-3, a `TiktokenTokenizer` with its bundled rank
+    /// file, split pattern and special tokens.
+    pub struct GPT2Tokenizer {
+        ranks: GPT2_MERGEABLE_RANKS,
+        pattern: GPT2_SPLIT_PATTERN,
+        special_tokens: GPT2_SPECIAL_TOKENS,
+    }
+}
@@ -0,0 +1,886 @@
+//! Contains `HuggingFaceTokenizer`, which loads the byte-level BPE models of HuggingFace
+//! `tokenizer.json` files, such as those of GPT-2, RoBERTa or Llama 3, and the loader of the
+//! `encoder.json` and `vocab.bpe` files of the original GPT-2 release into a
+//! `RegexTokenizerStruct`.
+//!
+//! Byte-level models write every token as text by mapping each byte to a printable character
+//! (GPT-2's `bytes_to_unicode`), so the vocabulary and the merges are mapped back to bytes. The
+//! merges are ranked by their position in the `merges` list rather than by the ids of the tokens
+//! they produce, and the bytes can have any ids, so chunks are merged in a space of ranks of its
+//! own, where the bytes are 0 to 255 and each merge ranks after them in list order, and the
+//! resulting ranks are then mapped to the ids of the model.
+//!
+//! It also contains the exporter behind `Saveable::save_hf_json`, which writes the inverse: the
+//! merges in order of id and the split pattern as a `Split` pre-tokenizer.
+
+use std::borrow::Cow;
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::io::{Read, Write};
+use std::num::NonZeroUsize;
+use std::path::Path;
+
+use fancy_regex::Regex;
+use indexmap::IndexMap;
+use serde::Deserialize;
+use serde_json::json;
+
+use crate::regex::{GPT2_SPLIT_PATTERN, WHOLE_TEXT_PATTERN};
+use crate::{build_vocab, ChunkCache, Loadable, MinbpeError, RegexTokenizerStruct};
+use crate::{RegexTokenizerTrait, Result, Token, TokenTables, Tokenizer};
+
+fn invalid(message: impl Into<String>) -> MinbpeError {
+    MinbpeError::InvalidTokenizerJson(message.into())
+}
+
+/// Returns the character GPT-2's `bytes_to_unicode` maps each byte to: the printable Latin-1
+/// characters map to themselves and the other bytes to the characters from U+0100 in order.
+pub(crate) fn byte_to_char() -> [char; 256] {
+    let mut chars = ['\0'; 256];
+    let mut n = 0;
+    for b in 0..=255u8 {
+        chars[b as usize] = if matches!(b, b'!'..=b'~' | 0xA1..=0xAC | 0xAE..=0xFF) {
+            b as char
+        } else {
+            n += 1;
+            char::from_u32(255 + n).unwrap()
+        };
+    }
+    chars
+}
+
+/// Maps the text of a byte-level token back to its bytes, or returns `None` if a character is
+/// not the image of a byte.
+pub(crate) fn token_to_bytes(token: &str, char_to_byte: &HashMap<char, u8>) -> Option<Vec<u8>> {
+    token
+        .chars()
+        .map(|c| char_to_byte.get(&c).copied())
+        .collect()
+}
+
+pub(crate) fn char_to_byte() -> HashMap<char, u8> {
+    byte_to_char()
+        .iter()
+        .enumerate()
+        .map(|(b, &c)| (c, b as u8))
+        .collect()
+}
+
+/// The parts of a `tokenizer.json` the tokenizer reads; the others are ignored.
+#[derive(Deserialize)]
+struct TokenizerJson {
+    #[serde(default)]
+    added_tokens: Vec<AddedToken>,
+    normalizer: Option<serde_json::Value>,
+    pre_tokenizer: Option<PreTokenizer>,
+    model: BpeModel,
+}
+
+#[derive(Deserialize)]
+struct AddedToken {
+    id: Token,
+    content: String,
+}
+
+#[derive(Deserialize)]
+#[serde(tag = "type")]
+enum PreTokenizer {
+    ByteLevel {
+        #[serde(default)]
+        add_prefix_space: bool,
+        #[serde(default = "default_use_regex")]
+        use_regex: bool,
+    },
+    Split {
+        pattern: SplitPattern,
+        behavior: String,
+        #[serde(default)]
+        invert: bool,
+    },
+    Sequence {
+        pretokenizers: Vec<PreTokenizer>,
+    },
+    #[serde(other)]
+    Unsupported,
+}
+
+fn default_use_regex() -> bool {
+    true
+}
+
+/// The pattern of a `Split` pre-tokenizer. Splitting on a literal string is not supported.
+#[derive(Deserialize)]
+enum SplitPattern {
+    Regex(String),
+    String(serde::de::IgnoredAny),
+}
+
+#[derive(Deserialize)]
+struct BpeModel {
+    #[serde(rename = "type")]
+    kind: Option<String>,
+    vocab: HashMap<String, Token>,
+    merges: Vec<Merge>,
+    dropout: Option<f64>,
+    continuing_subword_prefix: Option<String>,
+    end_of_word_suffix: Option<String>,
+    #[serde(default)]
+    byte_fallback: bool,
+    #[serde(default)]
+    ignore_merges: bool,
+}
+
+/// A merge, written `"a b"` by older versions of `tokenizers` and `["a", "b"]` by newer ones.
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum Merge {
+    Joined(String),
+    Pair(String, String),
+}
+
+/// The special token GPT-2's `encoder.json` lists with the other tokens.
+const GPT2_END_OF_TEXT: &str = "<|endoftext|>";
+
+/// Returns the split pattern of the byte-level pre-tokenizer: GPT-2's for a `ByteLevel`
+/// pre-tokenizer that splits, that of a `Split` followed by a `ByteLevel` that does not, or one
+/// matching the whole text for a `ByteLevel` alone that does not.
+fn split_pattern(pre_tokenizer: Option<&PreTokenizer>) -> Result<String> {
+    let pre_tokenizers = match pre_tokenizer {
+        Some(PreTokenizer::Sequence { pretokenizers }) => pretokenizers.iter().collect(),
+        Some(pre_tokenizer) => vec![pre_tokenizer],
+        None => vec![],
+    };
+    match pre_tokenizers[..] {
+        [PreTokenizer::ByteLevel {
+            add_prefix_space: false,
+            use_regex: true,
+        }] => Ok(GPT2_SPLIT_PATTERN.to_string()),
+        // The whole text is a single chunk, which is how `BasicTokenizer` is exported
+        [PreTokenizer::ByteLevel {
+            add_prefix_space: false,
+            use_regex: false,
+        }] => Ok(WHOLE_TEXT_PATTERN.to_string()),
+        [PreTokenizer::Split {
+            pattern: SplitPattern::Regex(pattern),
+            behavior,
+            invert: false,
+        }, PreTokenizer::ByteLevel {
+            add_prefix_space: false,
+            use_regex: false,
+        }] if behavior == "Isolated" => Ok(pattern.clone()),
+        _ => Err(invalid(
+            "unsupported pre_tokenizer: expected a ByteLevel pre-tokenizer, optionally after an \
+             isolating regex Split, without add_prefix_space",
+        )),
+    }
+}
+
+/// Writes a byte-level BPE `tokenizer.json` for the given model, see `Saveable::save_hf_json`.
+pub(crate) fn write_tokenizer_json<W: Write>(
+    writer: W,
+    pattern: &str,
+    special_tokens: &IndexMap<String, Token>,
+    merges: &IndexMap<(Token, Token), Token>,
+    vocab: &IndexMap<Token, Vec<u8>>,
+) -> Result<()> {
+    let byte_to_char = byte_to_char();
+    let to_text =
+        |bytes: &[u8]| -> String { bytes.iter().map(|&b| byte_to_char[b as usize]).collect() };
+    let is_special: HashSet<Token> = special_tokens.values().copied().collect();
+
+    let mut json_vocab = serde_json::Map::new();
+    for (&idx, bytes) in vocab {
+        if is_special.contains(&idx) {
+            continue;
+        }
+        let token = to_text(bytes);
+        if json_vocab.insert(token.clone(), json!(idx)).is_some() {
+            return Err(invalid(format!(
+                "token {:?} has two ids, which tokenizer.json cannot represent",
+                token
+            )));
+        }
+    }
+
+    let mut ordered_merges: Vec<(&(Token, Token), &Token)> = merges.iter().collect();
+    ordered_merges.sort_by_key(|&(_, &idx)| idx);
+    let mut json_merges = Vec::with_capacity(ordered_merges.len());
+    for (&(ix0, ix1), _) in ordered_merges {
+        let part = |idx| {
+            vocab
+                .get(&idx)
+                .map(|bytes| to_text(bytes))
+                .ok_or(MinbpeError::UnknownTokenId(idx))
+        };
+        json_merges.push(format!("{} {}", part(ix0)?, part(ix1)?));
+    }
+
+    let added_tokens: Vec<serde_json::Value> = special_tokens
+        .iter()
+        .map(|(content, &idx)| {
+            json!({
+                "id": idx,
+                "content": content,
+                "single_word": false,
+                "lstrip": false,
+                "rstrip": false,
+                "normalized": false,
+                "special": true,
+            })
+        })
+        .collect();
+
+    let byte_level = json!({
+        "type": "ByteLevel",
+        "add_prefix_space": false,
+        "trim_offsets": true,
+        "use_regex": false,
+    });
+    let pre_tokenizer = if pattern.is_empty() {
+        byte_level
+    } else {
+        json!({
+            "type": "Sequence",
+            "pretokenizers": [
+                {
+                    "type": "Split",
+                    "pattern": { "Regex": pattern },
+                    "behavior": "Isolated",
+                    "invert": false,
+                },
+                byte_level,
+            ],
+        })
+    };
+
+    let tokenizer = json!({
+        "version": "1.0",
+        "truncation": null,
+        "padding": null,
+        "added_tokens": added_tokens,
+        "normalizer": null,
+        "pre_tokenizer": pre_tokenizer,
+        "post_processor": null,
+        "decoder": {
+            "type": "ByteLevel",
+            "add_prefix_space": true,
+            "trim_offsets": true,
+            "use_regex": true,
+        },
+        "model": {
+            "type": "BPE",
+            "dropout": null,
+            "unk_token": null,
+            "continuing_subword_prefix": null,
+            "end_of_word_suffix": null,
+            "fuse_unk": false,
+            "byte_fallback": false,
+            "ignore_merges": false,
+            "vocab": json_vocab,
+            "merges": json_merges,
+        },
+    });
+    serde_json::to_writer_pretty(writer, &tokenizer).map_err(|e| match e.io_error_kind() {
+        Some(_) => MinbpeError::Io(e.into()),
+        None => invalid(e.to_string()),
+    })
+}
+
+/// Tokenizer for the byte-level BPE models of HuggingFace `tokenizer.json` files and GPT-2
+/// `encoder.json` and `vocab.bpe` files.
+///
+/// The `added_tokens` become the special tokens, which are only encoded as such by
+/// `encode_special`. Normalizers are not supported, and post-processors (e.g. one adding a
+/// beginning-of-sequence token) are not applied.
+///
+/// Implements `Tokenizer` and `RegexTokenizerTrait` but not `Trainable`, `Saveable` or
+/// `Loadable`.
+///
+/// # Examples
+///
+/// ```no_run
+/// use minbpe::{HuggingFaceTokenizer, RegexTokenizerTrait};
+///
+/// let tokenizer = HuggingFaceTokenizer::try_from_file("tokenizer.json").unwrap();
+/// let ids = tokenizer.encode_ordinary("hello world");
+/// assert_eq!(tokenizer.decode(&ids), "hello world");
+/// ```
+pub struct HuggingFaceTokenizer {
+    pattern: String,
+    compiled_pattern: Regex,
+    special_tokens: IndexMap<String, Token>,
+    inverse_special_tokens: IndexMap<Token, String>,
+    merges: IndexMap<(Token, Token), Token>,
+    vocab: IndexMap<Token, Vec<u8>>,
+
+    /// The tables in the space of ranks, see the module documentation.
+    tables: TokenTables,
+    /// The tables in the space of ids. They have no merges.
+    decode_tables: TokenTables,
+    /// The id of each rank.
+    rank_ids: Vec<Token>,
+    /// The id of every token, if a chunk that is a whole token is encoded as that token
+    /// without merging (`ignore_merges`).
+    whole_tokens: Option<HashMap<Vec<u8>, Token>>,
+
+    chunk_cache: Option<ChunkCache>,
+}
+
+impl HuggingFaceTokenizer {
+    /// Creates a tokenizer from the contents of a `tokenizer.json` file.
+    ///
+    /// # Errors
+    ///
+    /// Returns `MinbpeError::InvalidTokenizerJson` if the JSON is malformed, if the model is not
+    /// a byte-level BPE model or uses a normalizer, if a token or merge is not byte-level or a
+    /// merge refers to tokens that are not in the vocabulary, and `MinbpeError::InvalidRegex` if
+    /// the split pattern is not a valid regex.
+    pub fn try_from_bytes(data: &[u8]) -> Result<Self> {
+        let json: TokenizerJson =
+            serde_json::from_slice(data).map_err(|e| invalid(e.to_string()))?;
+        let model = json.model;
+
+        if model.kind.as_deref().is_some_and(|kind| kind != "BPE") {
+            return Err(invalid(format!(
+                "unsupported model type {:?}",
+                model.kind.unwrap()
+            )));
+        }
+        if model.dropout.is_some_and(|dropout| dropout > 0.0)
+            || model
+                .continuing_subword_prefix
+                .is_some_and(|s| !s.is_empty())
+            || model.end_of_word_suffix.is_some_and(|s| !s.is_empty())
+            || model.byte_fallback
+        {
+            return Err(invalid(
+                "unsupported model: dropout, continuing_subword_prefix, end_of_word_suffix and \
+                 byte_fallback must be unset",
+            ));
+        }
+        if json.normalizer.as_ref().is_some_and(|n| !n.is_null()) {
+            return Err(invalid("unsupported normalizer"));
+        }
+        let pattern = split_pattern(json.pre_tokenizer.as_ref())?;
+
+        let special_tokens: IndexMap<String, Token> = json
+            .added_tokens
+            .into_iter()
+            .map(|token| (token.content, token.id))
+            .collect();
+        let mut merges = Vec::with_capacity(model.merges.len());
+        for merge in &model.merges {
+            merges.push(match merge {
+                Merge::Joined(merge) => merge
+                    .split_once(' ')
+                    .ok_or_else(|| invalid(format!("malformed merge {:?}", merge)))?,
+                Merge::Pair(left, right) => (left.as_str(), right.as_str()),
+            });
+        }
+
+        Self::try_from_parts(
+            pattern,
+            special_tokens,
+            &model.vocab,
+            &merges,
+            model.ignore_merges,
+        )
+    }
+
+    /// Creates a tokenizer from a byte-level vocabulary and its merges, in order of rank.
+    fn try_from_parts(
+        pattern: String,
+        special_tokens: IndexMap<String, Token>,
+        model_vocab: &HashMap<String, Token>,
+        model_merges: &[(&str, &str)],
+        ignore_merges: bool,
+    ) -> Result<Self> {
+        let compiled_pattern = Regex::new(&pattern)?;
+        let inverse_special_tokens: IndexMap<Token, String> = special_tokens
+            .iter()
+            .map(|(k, &v)| (v, k.clone()))
+            .collect();
+
+        let char_to_byte = char_to_byte();
+        let to_bytes = |token: &str| {
+            token_to_bytes(token, &char_to_byte)
+                .ok_or_else(|| invalid(format!("token {:?} is not byte-level", token)))
+        };
+
+        let mut tokens: Vec<(Token, Vec<u8>)> = Vec::new();
+        for (token, &idx) in model_vocab {
+            if !inverse_special_tokens.contains_key(&idx) {
+                tokens.push((idx, to_bytes(token)?));
+            }
+        }
+        tokens.sort_by_key(|&(idx, _)| idx);
+        let vocab: IndexMap<Token, Vec<u8>> = tokens.into_iter().collect();
+        let ids: HashMap<&[u8], Token> = vocab
+            .iter()
+            .map(|(&idx, bytes)| (bytes.as_slice(), idx))
+            .collect();
+        let id_of = |bytes: &[u8]| {
+            ids.get(bytes).copied().ok_or_else(|| {
+                invalid(format!(
+                    "merge token {:?} is not in the vocabulary",
+                    String::from_utf8_lossy(bytes)
+                ))
+            })
+        };
+
+        // The bytes, then the token of every merge in order, unless an earlier merge produced it
+        let mut rank_ids = Vec::with_capacity(256 + model_merges.len());
+        let mut ranks: HashMap<Vec<u8>, Token> = HashMap::new();
+        let mut rank_vocab = IndexMap::new();
+        for b in 0..=255u8 {
+            let idx = ids
+                .get(&[b][..])
+                .copied()
+                .ok_or_else(|| invalid(format!("byte {} is not in the vocabulary", b)))?;
+            rank_ids.push(idx);
+            ranks.insert(vec![b], b as Token);
+            rank_vocab.insert(b as Token, vec![b]);
+        }
+        let mut pairs = Vec::with_capacity(model_merges.len());
+        for &(left, right) in model_merges {
+            let (left, right) = (to_bytes(left)?, to_bytes(right)?);
+            let merged = [left.as_slice(), right.as_slice()].concat();
+            let ids = (id_of(&left)?, id_of(&right)?, id_of(&merged)?);
+            if !ranks.contains_key(&merged) {
+                let rank = rank_ids.len() as Token;
+                rank_ids.push(ids.2);
+                ranks.insert(merged.clone(), rank);
+                rank_vocab.insert(rank, merged.clone());
+            }
+            pairs.push((left, right, merged, ids));
+        }
+
+        let mut merges = IndexMap::new();
+        let mut rank_merges = IndexMap::new();
+        for (left, right, merged, (ix0, ix1, idx)) in &pairs {
+            // A merge of a token that no merge produces can never apply
+            let (Some(&r0), Some(&r1)) = (ranks.get(left), ranks.get(right)) else {
+                continue;
+            };
+            rank_merges.entry((r0, r1)).or_insert(ranks[merged]);
+            merges.entry((*ix0, *ix1)).or_insert(*idx);
+        }
+
+        let whole_tokens = ignore_merges.then(|| {
+            vocab
+                .iter()
+                .map(|(&idx, bytes)| (bytes.clone(), idx))
+                .collect()
+        });
+
+        Ok(HuggingFaceTokenizer {
+            pattern,
+            compiled_pattern,
+            special_tokens,
+            inverse_special_tokens,
+            tables: TokenTables::new(&rank_merges, &rank_vocab),
+            decode_tables: TokenTables::new(&IndexMap::new(), &vocab),
+            rank_ids,
+            whole_tokens,
+            merges,
+            vocab,
+
+            chunk_cache: None,
+        })
+    }
+
+    /// Creates a tokenizer from a reader of a `tokenizer.json` file.
+    ///
+    /// # Errors
+    ///
+    /// Returns `MinbpeError::Io` if reading fails, and the errors of `try_from_bytes`.
+    pub fn try_from_reader<R: Read>(mut reader: R) -> Result<Self> {
+        let mut data = Vec::new();
+        reader.read_to_end(&mut data)?;
+        Self::try_from_bytes(&data)
+    }
+
+    /// Creates a tokenizer from a `tokenizer.json` file.
+    ///
+    /// # Errors
+    ///
+    /// Returns `MinbpeError::Io` if the file cannot be read, and the errors of `try_from_bytes`.
+    pub fn try_from_file<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let data = fs::read(path)?;
+        Self::try_from_bytes(&data)
+    }
+
+    pub fn pattern(&self) -> &str {
+        &self.pattern
+    }
+
+    /// Decodes like `Tokenizer::decode`. Being inherent, it is what `decode` resolves to even
+    /// when both `Tokenizer` and `RegexTokenizerTrait` are in scope.
+    ///
+    /// # Panics
+    ///
+    /// Panics on an unknown token id.
+    pub fn decode(&self, ids: &[Token]) -> String {
+        Tokenizer::decode(self, ids)
+    }
+
+    /// Sets the number of encoded chunks to cache, replacing the cache and its statistics. A
+    /// capacity of 0 disables the cache, which is the default.
+    pub fn set_chunk_cache_capacity(&mut self, capacity: usize) {
+        self.chunk_cache = NonZeroUsize::new(capacity).map(ChunkCache::new);
+    }
+}
+
+impl RegexTokenizerStruct {
+    /// Creates a tokenizer from the contents of the `encoder.json` and `vocab.bpe` files of the
+    /// original GPT-2 release, which HuggingFace calls `vocab.json` and `merges.txt`: a JSON
+    /// object mapping the byte-level text of each token to its id, and the merges, one
+    /// `"a b"` per line after a `#version` line. The split pattern is `GPT2_SPLIT_PATTERN`.
+    ///
+    /// A `RegexTokenizerStruct` identifies the bytes with their values and ranks merges by id,
+    /// so the ids are remapped: the bytes take their values, the token of the n-th merge the id
+    /// 256 + n, and `<|endoftext|>`, if it is in the encoder, the id following the merges. With
+    /// GPT-2's own files, this keeps the ids of the merged tokens and of `<|endoftext|>` and
+    /// only renumbers the bytes, which GPT-2 numbers in the order of `bytes_to_unicode`.
+    ///
+    /// # Errors
+    ///
+    /// Returns `MinbpeError::InvalidTokenizerJson` if a file is malformed, if a token or merge is
+    /// not byte-level, if a merge is listed twice or merges a token that no earlier merge
+    /// produces, or if the token it produces is not in the encoder.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use minbpe::{RegexTokenizerStruct, RegexTokenizerTrait};
+    ///
+    /// let encoder = r#"{"a": 64, "Ġ": 220, "Ġa": 257, "<|endoftext|>": 258}"#;
+    /// let vocab_bpe = "#version: 0.2\nĠ a\n";
+    /// let tokenizer =
+    ///     RegexTokenizerStruct::try_from_gpt2_bytes(encoder.as_bytes(), vocab_bpe.as_bytes())
+    ///         .unwrap();
+    /// assert_eq!(tokenizer.encode_ordinary("a a"), [97, 256]);
+    /// ```
+    pub fn try_from_gpt2_bytes(encoder: &[u8], vocab_bpe: &[u8]) -> Result<Self> {
+        let encoder: HashMap<String, Token> =
+            serde_json::from_slice(encoder).map_err(|e| invalid(format!("encoder.json: {}", e)))?;
+        let vocab_bpe =
+            std::str::from_utf8(vocab_bpe).map_err(|e| invalid(format!("vocab.bpe: {}", e)))?;
+
+        let char_to_byte = char_to_byte();
+        let mut ids: HashMap<Vec<u8>, Token> = (0..=255u8).map(|b| (vec![b], b as Token)).collect();
+        let mut merges: IndexMap<(Token, Token), Token> = IndexMap::new();
+        for (i, line) in vocab_bpe.lines().enumerate() {
+            if line.is_empty() || (i == 0 && line.starts_with("#version")) {
+                continue;
+            }
+            let malformed = |message: &str| {
+                invalid(format!(
+                    "vocab.bpe: {} at line {}: {:?}",
+                    message,
+                    i + 1,
+                    line
+                ))
+            };
+            let (left, right) = line
+                .split_once(' ')
+                .ok_or_else(|| malformed("malformed merge"))?;
+            if !encoder.contains_key(&format!("{}{}", left, right)) {
+                return Err(malformed("merged token not in encoder.json"));
+            }
+            let id_of = |token: &str| {
+                token_to_bytes(token, &char_to_byte)
+                    .and_then(|bytes| ids.get(&bytes).copied())
+                    .ok_or_else(|| malformed("merge of a token no earlier merge produces"))
+            };
+            let pair = (id_of(left)?, id_of(right)?);
+            let idx = 256 + merges.len() as Token;
+            if merges.insert(pair, idx).is_some() {
+                return Err(malformed("duplicate merge"));
+            }
+            let mut merged = token_to_bytes(left, &char_to_byte).unwrap_or_default();
+            merged.extend(token_to_bytes(right, &char_to_byte).unwrap_or_default());
+            ids.entry(merged).or_insert(idx);
+        }
+
+        let mut special_tokens = IndexMap::new();
+        if encoder.contains_key(GPT2_END_OF_TEXT) {
+            special_tokens.insert(GPT2_END_OF_TEXT.to_string(), 256 + merges.len() as Token);
+        }
+        let vocab = build_vocab(&special_tokens, &merges);
+        let mut tokenizer = RegexTokenizerStruct::try_new(GPT2_SPLIT_PATTERN.to_string())?;
+        tokenizer.set_special_tokens(special_tokens);
+        tokenizer.set_merges(merges);
+        tokenizer.set_vocab(vocab);
+        Ok(tokenizer)
+    }
+
+    /// Creates a tokenizer from the `encoder.json` and `vocab.bpe` files of GPT-2, see
+    /// `try_from_gpt2_bytes`.
+    ///
+    /// # Errors
+    ///
+    /// Returns `MinbpeError::Io` if a file cannot be read, and the errors of
+    /// `try_from_gpt2_bytes`.
+    pub fn try_from_gpt2_files<P: AsRef<Path>, Q: AsRef<Path>>(
+        encoder_path: P,
+        vocab_bpe_path: Q,
+    ) -> Result<Self> {
+        let encoder = fs::read(encoder_path)?;
+        let vocab_bpe = fs::read(vocab_bpe_path)?;
+        Self::try_from_gpt2_bytes(&encoder, &vocab_bpe)
+    }
+}
+
+impl Tokenizer for HuggingFaceTokenizer {
+    fn special_tokens(&self) -> &IndexMap<String, Token> {
+        &self.special_tokens
+    }
+
+    /// The merges, in order of rank, as ids of the model.
+    fn merges(&self) -> &IndexMap<(Token, Token), Token> {
+        &self.merges
+    }
+
+    fn vocab(&self) -> &IndexMap<Token, Vec<u8>> {
+        &self.vocab
+    }
+
+    fn split_pattern(&self) -> &str {
+        &self.pattern
+    }
+
+    fn token_bytes(&self, idx: Token) -> Option<Cow<'_, [u8]>> {
+        self.decode_tables
+            .token_bytes(idx)
+            .or_else(|| self.inverse_special_tokens.get(&idx).map(|t| t.as_bytes()))
+            .map(Cow::Borrowed)
+    }
+
+    fn try_encode(&self, text: &str) -> Result<Vec<Token>> {
+        RegexTokenizerTrait::try_encode(self, text)
+    }
+
+    /// Saves the tokenizer in the format of tiktoken, see `Tokenizer::try_save_tiktoken`.
+    ///
+    /// # Errors
+    ///
+    /// Returns `MinbpeError::MalformedRanks` if the ids of the merged tokens are not in the
+    /// order of the merges, or if the vocabulary has tokens no merge produces: tiktoken ranks
+    /// the tokens by id and would merge them in a different order. Returns the errors of
+    /// `Tokenizer::try_save_tiktoken` otherwise.
+    #[cfg(feature = "tiktoken")]
+    fn try_save_tiktoken(&self, dir: &Path, prefix: &str) -> Result<()> {
+        let in_order = self.vocab.len() == self.rank_ids.len()
+            && self.rank_ids[256..].windows(2).all(|ids| ids[0] < ids[1]);
+        if !in_order {
+            return Err(MinbpeError::MalformedRanks {
+                line: 0,
+                message: "the ids of the tokens are not in the order of the merges".to_string(),
+            });
+        }
+        crate::tiktoken::save_tiktoken(self, dir, prefix)
+    }
+}
+
+impl RegexTokenizerTrait for HuggingFaceTokenizer {
+    fn encode_chunk(&self, text_bytes: &[u8]) -> Vec<Token> {
+        if let Some(&idx) = self
+            .whole_tokens
+            .as_ref()
+            .and_then(|tokens| tokens.get(text_bytes))
+        {
+            return vec![idx];
+        }
+        <Self as RegexTokenizerTrait>::encode_chunk_inner(self, text_bytes)
+            .into_iter()
+            .map(|rank| self.rank_ids[rank as usize])
+            .collect()
+    }
+
+    fn compiled_pattern(&self) -> &Regex {
+        &self.compiled_pattern
+    }
+
+    /// The tables in the space of ranks, which `encode_chunk` maps to ids.
+    fn tables(&self) -> &TokenTables {
+        &self.tables
+    }
+
+    fn chunk_cache(&self) -> Option<&ChunkCache> {
+        self.chunk_cache.as_ref()
+    }
+
+    fn inverse_special_tokens(&self) -> &IndexMap<Token, String> {
+        &self.inverse_special_tokens
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_byte_to_char() {
+        let chars = byte_to_char();
+        assert_eq!(chars[b'a' as usize], 'a');
+        assert_eq!(chars[b' ' as usize], 'Ġ');
+        assert_eq!(chars[b'\n' as usize], 'Ċ');
+        assert_eq!(chars[0xE6], 'æ');
+        assert_eq!(char_to_byte().len(), 256);
+        assert_eq!(
+            token_to_bytes("Ġhi", &char_to_byte()),
+            Some(b" hi".to_vec())
+        );
+        assert_eq!(token_to_bytes("日", &char_to_byte()), None);
+    }
+
+    fn tokenizer_json(pre_tokenizer: &str, merges: &str) -> String {
+        let vocab: Vec<String> = byte_to_char()
+            .iter()
+            .enumerate()
+            .map(|(b, c)| format!("{:?}: {}", c.to_string(), b + 10))
+            .collect();
+        format!(
+            r#"{{"added_tokens": [{{"id": 0, "content": "<s>"}}], "normalizer": null,
+                "pre_tokenizer": {},
+                "model": {{"type": "BPE", "vocab": {{{}, "ab": 1, "abc": 2}}, "merges": {}}}}}"#,
+            pre_tokenizer,
+            vocab.join(", "),
+            merges
+        )
+    }
+
+    #[test]
+    fn test_try_from_bytes() {
+        let byte_level = r#"{"type": "ByteLevel", "add_prefix_space": false}"#;
+        let json = tokenizer_json(byte_level, r#"[["a", "b"], "ab c"]"#);
+        let tokenizer = HuggingFaceTokenizer::try_from_bytes(json.as_bytes()).unwrap();
+        assert_eq!(tokenizer.pattern(), GPT2_SPLIT_PATTERN);
+        assert_eq!(tokenizer.encode_ordinary("abcab ab"), [2, 1, 10 + 32, 1]);
+        assert_eq!(tokenizer.decode(&[0, 2, 1]), "<s>abcab");
+        assert_eq!(
+            tokenizer.merges(),
+            &IndexMap::from([((10 + 97, 10 + 98), 1), ((1, 10 + 99), 2)])
+        );
+
+        for (pre_tokenizer, merges) in [
+            (byte_level, r#"["a c"]"#),
+            (byte_level, r#"["ab"]"#),
+            (byte_level, r#"["a 日"]"#),
+            (r#"{"type": "ByteLevel", "add_prefix_space": true}"#, "[]"),
+            (r#"{"type": "Whitespace"}"#, "[]"),
+            ("null", "[]"),
+        ] {
+            let json = tokenizer_json(pre_tokenizer, merges);
+            assert!(matches!(
+                HuggingFaceTokenizer::try_from_bytes(json.as_bytes()),
+                Err(MinbpeError::InvalidTokenizerJson(_))
+            ));
+        }
+        assert!(matches!(
+            HuggingFaceTokenizer::try_from_bytes(b"{}"),
+            Err(MinbpeError::InvalidTokenizerJson(_))
+        ));
+    }
+
+    #[test]
+    fn test_write_tokenizer_json() {
+        let mut vocab: IndexMap<Token, Vec<u8>> =
+            (0..=255u8).map(|b| (b as Token, vec![b])).collect();
+        vocab.insert(256, b"ab".to_vec());
+        let merges = IndexMap::from([((97, 98), 256)]);
+        let special_tokens = IndexMap::from([("<s>".to_string(), 257)]);
+
+        let mut json = Vec::new();
+        write_tokenizer_json(&mut json, "", &special_tokens, &merges, &vocab).unwrap();
+        let tokenizer = HuggingFaceTokenizer::try_from_bytes(&json).unwrap();
+        assert_eq!(tokenizer.pattern(), WHOLE_TEXT_PATTERN);
+        assert_eq!(tokenizer.encode_ordinary("a ab\n"), [97, 32, 256, 10]);
+        assert_eq!(tokenizer.decode(&[257]), "<s>");
+
+        // A second token with the bytes "ab" cannot be written
+        vocab.insert(258, b"ab".to_vec());
+        assert!(matches!(
+            write_tokenizer_json(&mut Vec::new(), "", &special_tokens, &merges, &vocab),
+            Err(MinbpeError::InvalidTokenizerJson(_))
+        ));
+    }
+
+    #[cfg(feature = "tiktoken")]
+    #[test]
+    fn test_save_tiktoken() {
+        // With ids in the order of the merges, the ids are the ranks
+        let mut vocab: IndexMap<Token, Vec<u8>> =
+            (0..=255u8).map(|b| (b as Token, vec![b])).collect();
+        vocab.insert(256, b"ab".to_vec());
+        vocab.insert(257, b"abc".to_vec());
+        let merges = IndexMap::from([((97, 98), 256), ((256, 99), 257)]);
+        let special_tokens = IndexMap::from([("<s>".to_string(), 258)]);
+        let mut json = Vec::new();
+        write_tokenizer_json(&mut json, "", &special_tokens, &merges, &vocab).unwrap();
+        let tokenizer = HuggingFaceTokenizer::try_from_bytes(&json).unwrap();
+
+        let dir = tempfile::tempdir().unwrap();
+        tokenizer.try_save_tiktoken(dir.path(), "hf").unwrap();
+        let loaded = crate::TiktokenTokenizer::try_from_saved(dir.path(), "hf").unwrap();
+        assert_eq!(loaded.encode_ordinary("abc ab"), [257, 32, 256]);
+        assert_eq!(loaded.special_tokens(), &special_tokens);
+
+        // Otherwise tiktoken would merge in the wrong order
+        let byte_level = r#"{"type": "ByteLevel", "add_prefix_space": false}"#;
+        let json = tokenizer_json(byte_level, r#"[["a", "b"], "ab c"]"#);
+        let tokenizer = HuggingFaceTokenizer::try_from_bytes(json.as_bytes()).unwrap();
+        assert!(matches!(
+            tokenizer.try_save_tiktoken(dir.path(), "hf"),
+            Err(MinbpeError::MalformedRanks { line: 0, .. })
+        ));
+    }
+
+    #[test]
+    fn test_try_from_gpt2_bytes() {
+        // The bytes have GPT-2's ids, which the tokenizer replaces by their values
+        let chars = byte_to_char();
+        let mut encoder: HashMap<String, Token> = (0..=255u8)
+            .map(|b| (chars[b as usize].to_string(), 255 - b as Token))
+            .collect();
+        encoder.insert("Ġa".to_string(), 256);
+        encoder.insert("Ġaa".to_string(), 257);
+        encoder.insert("<|endoftext|>".to_string(), 258);
+        let encoder = serde_json::to_vec(&encoder).unwrap();
+
+        let vocab_bpe = "#version: 0.2\nĠ a\nĠa a\n";
+        let tokenizer =
+            RegexTokenizerStruct::try_from_gpt2_bytes(&encoder, vocab_bpe.as_bytes()).unwrap();
+        assert_eq!(crate::Saveable::pattern(&tokenizer), GPT2_SPLIT_PATTERN);
+        assert_eq!(
+            tokenizer.merges(),
+            &IndexMap::from([((32, 97), 256), ((256, 97), 257)])
+        );
+        assert_eq!(tokenizer.encode_ordinary("a aaa a"), [97, 257, 97, 256]);
+        assert_eq!(
+            tokenizer.special_tokens(),
+            &IndexMap::from([("<|endoftext|>".to_string(), 258)])
+        );
+        assert_eq!(
+            RegexTokenizerTrait::decode(&tokenizer, &[256, 258]),
+            " a<|endoftext|>"
+        );
+
+        for vocab_bpe in [
+            "#version: 0.2\nĠa\n",
+            "#version: 0.2\na Ġ\n",
+            "#version: 0.2\nĠa a\n",
+            "#version: 0.2\nĠ a\nĠ a\n",
+        ] {
+            assert!(matches!(
+                RegexTokenizerStruct::try_from_gpt2_bytes(&encoder, vocab_bpe.as_bytes()),
+                Err(MinbpeError::InvalidTokenizerJson(_))
+            ));
+        }
+        assert!(matches!(
+            RegexTokenizerStruct::try_from_gpt2_bytes(b"[]", b""),
+            Err(MinbpeError::InvalidTokenizerJson(_))
+        ));
+    }
+}
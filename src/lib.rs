@@ -1,14 +1,48 @@
 pub mod base;
 #[cfg(feature = "basic")]
 pub mod basic;
+mod bpe;
+pub mod cache;
+#[cfg(any(feature = "basic", feature = "regex"))]
+mod checkpoint;
+pub mod decode;
+pub mod error;
+#[cfg(feature = "gpt2")]
+pub mod gpt2;
 #[cfg(feature = "gpt4")]
 pub mod gpt4;
+#[cfg(feature = "huggingface")]
+pub mod huggingface;
+pub mod observer;
+pub mod offsets;
+#[cfg(feature = "p50k")]
+pub mod p50k;
+#[cfg(any(feature = "basic", feature = "regex"))]
+mod pair_trainer;
 #[cfg(feature = "regex")]
 pub mod regex;
+#[cfg(feature = "sentencepiece")]
+pub mod sentencepiece;
+pub mod streaming;
+pub mod tables;
+#[cfg(feature = "tiktoken")]
+pub mod tiktoken;
+pub mod trainer;
 
 pub mod test_common;
 
 pub use base::*;
+pub use cache::{CacheStats, ChunkCache};
+pub use decode::{DecodeOptions, UnknownIdPolicy, Utf8Policy};
+pub use error::{MinbpeError, Result};
+pub use observer::{MergeEvent, PrintObserver, SilentObserver, TrainingObserver, TrainingPhase};
+pub use offsets::TokenSpan;
+pub use streaming::StreamingDecoder;
+pub use tables::TokenTables;
+pub use trainer::{
+    CheckpointConfig, ChunkCounts, DocumentSplit, SpecialTokenPlacement, StopReason,
+    TrainingConfig, TrainingOutcome,
+};
 
 #[cfg(feature = "basic")]
 pub use basic::BasicTokenizer;
@@ -16,5 +50,20 @@ pub use basic::BasicTokenizer;
 #[cfg(feature = "regex")]
 pub use regex::{AllowedSpecial, RegexTokenizerStruct, RegexTokenizerTrait};
 
+#[cfg(feature = "tiktoken")]
+pub use tiktoken::{parse_tiktoken_ranks, TiktokenTokenizer};
+
+#[cfg(feature = "gpt2")]
+pub use gpt2::GPT2Tokenizer;
+
 #[cfg(feature = "gpt4")]
 pub use gpt4::GPT4Tokenizer;
+
+#[cfg(feature = "p50k")]
+pub use p50k::P50kTokenizer;
+
+#[cfg(feature = "huggingface")]
+pub use huggingface::HuggingFaceTokenizer;
+
+#[cfg(feature = "sentencepiece")]
+pub use sentencepiece::SentencePieceTokenizer;
@@ -0,0 +1,119 @@
+//! Contains the observer through which training reports its progress and can be cancelled,
+//! and the stock observers.
+
+use crate::{Count, Token};
+
+/// The phases of a training run, in the order they occur.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TrainingPhase {
+    /// The corpus is being split into chunks and the distinct chunks counted.
+    PreTokenizing,
+
+    /// The pairs of the distinct chunks are being counted.
+    CountingPairs,
+
+    /// Merges are being learned, one `on_merge` call each.
+    Merging,
+}
+
+/// A merge learned during training.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MergeEvent<'a> {
+    /// The 0-based index of the merge in the training run.
+    pub index: usize,
+
+    /// The number of merges the training run is configured to learn.
+    pub num_merges: usize,
+
+    /// The pair of tokens that was merged.
+    pub pair: (Token, Token),
+
+    /// The id of the token the pair was merged into.
+    pub new_id: Token,
+
+    /// The bytes of the new token.
+    pub bytes: &'a [u8],
+
+    /// The number of occurrences of the pair when it was merged.
+    pub count: Count,
+}
+
+/// Receives the progress of a training run and decides whether to cancel it.
+///
+/// All methods have a default implementation that does nothing, so an observer only implements
+/// what it needs.
+///
+/// # Examples
+///
+/// ```
+/// use minbpe::{BasicTokenizer, MergeEvent, Tokenizer, Trainable, TrainingConfig, TrainingObserver};
+///
+/// // Stops training right after merging a pair that occurred only once
+/// #[derive(Default)]
+/// struct StopOnRare {
+///     rare: bool,
+/// }
+///
+/// impl TrainingObserver for StopOnRare {
+///     fn on_merge(&mut self, merge: &MergeEvent) {
+///         self.rare = merge.count < 2;
+///     }
+///
+///     fn should_stop(&mut self) -> bool {
+///         self.rare
+///     }
+/// }
+///
+/// let mut tokenizer = BasicTokenizer::new();
+/// let config = TrainingConfig::new(256 + 10);
+/// tokenizer
+///     .train_with_observer("aaabdaaabac", &config, &mut StopOnRare::default())
+///     .unwrap();
+/// assert_eq!(tokenizer.merges().len(), 4);
+/// ```
+pub trait TrainingObserver {
+    /// Called when a training phase starts.
+    fn on_phase(&mut self, _phase: TrainingPhase) {}
+
+    /// Called after every merge.
+    fn on_merge(&mut self, _merge: &MergeEvent) {}
+
+    /// Called before every merge. Returning `true` ends training with the merges learned so far,
+    /// as if the vocabulary size had been reached (a final checkpoint is still written).
+    fn should_stop(&mut self) -> bool {
+        false
+    }
+}
+
+/// An observer that ignores the progress of training and never cancels it.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SilentObserver;
+
+impl TrainingObserver for SilentObserver {}
+
+/// An observer that prints every merge to stdout. This is what `verbose` training does.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct PrintObserver;
+
+impl TrainingObserver for PrintObserver {
+    fn on_merge(&mut self, merge: &MergeEvent) {
+        println!(
+            "merge {}/{}: {:?} -> {} ({:?}) had {} occurrences",
+            merge.index + 1,
+            merge.num_merges,
+            merge.pair,
+            merge.new_id,
+            merge.bytes,
+            merge.count
+        );
+    }
+}
+
+/// Returns the stock observer for the `verbose` flag of the training entry points.
+pub(crate) fn verbose_observer(verbose: bool) -> Box<dyn TrainingObserver> {
+    if verbose {
+        Box::new(PrintObserver)
+    } else {
+        Box::new(SilentObserver)
+    }
+}
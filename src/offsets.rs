@@ -0,0 +1,89 @@
+//! Contains `TokenSpan`, which locates an encoded token in the text it was encoded from.
+
+use std::ops::Range;
+
+use crate::Token;
+
+/// A token and where it came from in the encoded text.
+///
+/// Tokens may cover only part of a multi-byte UTF-8 character, e.g. an emoji split into several
+/// byte tokens. Their byte ranges then start or end inside the character, while their char
+/// ranges cover every character they overlap, so consecutive tokens can share a character.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TokenSpan {
+    pub id: Token,
+
+    /// The range of bytes of the token in the text.
+    pub bytes: Range<usize>,
+
+    /// The range of chars (Unicode scalar values) of the text that the token overlaps.
+    pub chars: Range<usize>,
+}
+
+/// Collects the spans of the tokens of a text, given in order, each with its byte length.
+#[cfg(any(feature = "basic", feature = "regex"))]
+pub(crate) struct SpanBuilder {
+    spans: Vec<TokenSpan>,
+}
+
+#[cfg(any(feature = "basic", feature = "regex"))]
+impl SpanBuilder {
+    pub(crate) fn new() -> Self {
+        SpanBuilder { spans: Vec::new() }
+    }
+
+    /// Adds the tokens of the part of the text starting at byte `start`, returning the end of
+    /// the last one.
+    pub(crate) fn push_part<F>(&mut self, start: usize, ids: &[Token], len: F) -> usize
+    where
+        F: Fn(Token) -> usize,
+    {
+        let mut end = start;
+        for &id in ids {
+            let start = end;
+            end += len(id);
+            self.spans.push(TokenSpan {
+                id,
+                bytes: start..end,
+                chars: 0..0,
+            });
+        }
+        end
+    }
+
+    /// Returns the spans, filling in their char ranges from `text`.
+    pub(crate) fn finish(mut self, text: &str) -> Vec<TokenSpan> {
+        let char_starts: Vec<usize> = text.char_indices().map(|(i, _)| i).collect();
+        for span in &mut self.spans {
+            // The char containing the first byte, up to the last char starting before the end
+            let first = char_starts.partition_point(|&c| c <= span.bytes.start) - 1;
+            let end = char_starts.partition_point(|&c| c < span.bytes.end);
+            span.chars = first..end;
+        }
+        self.spans
+    }
+}
+
+#[cfg(all(test, any(feature = "basic", feature = "regex")))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_char_ranges() {
+        // "é" is 2 bytes, split into two tokens
+        let text = "aé!";
+        let mut builder = SpanBuilder::new();
+        let end = builder.push_part(0, &[97, 195, 169], |_| 1);
+        assert_eq!(builder.push_part(end, &[33], |_| 1), 4);
+        let spans = builder.finish(text);
+
+        let ranges: Vec<_> = spans
+            .iter()
+            .map(|s| (s.bytes.clone(), s.chars.clone()))
+            .collect();
+        assert_eq!(
+            ranges,
+            [(0..1, 0..1), (1..2, 1..2), (2..3, 1..2), (3..4, 2..3)]
+        );
+    }
+}
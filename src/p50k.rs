@@ -0,0 +1,33 @@
+use indexmap::IndexMap;
+use lazy_static::lazy_static;
+
+use crate::regex::GPT2_SPLIT_PATTERN;
+use crate::tiktoken::{parse_tiktoken_ranks, tiktoken_preset};
+use crate::Token;
+
+lazy_static! {
+    static ref P50K_SPECIAL_TOKENS: IndexMap<&'static str, Token> = {
+        let mut map = IndexMap::new();
+        map.insert("<|endoftext|>", 50256);
+        map
+    };
+}
+
+lazy_static! {
+    static ref P50K_MERGEABLE_RANKS: IndexMap<Vec<u8>, Token> = {
+        // https://github.com/zurawiki/tiktoken-rs/blob/main/tiktoken-rs/assets/p50k_base.tiktoken
+        let p50k_base: &[u8] = include_bytes!("../assets/p50k_base.tiktoken");
+        parse_tiktoken_ranks(p50k_base).unwrap_or_else(|e| panic!("{}", e))
+    };
+}
+
+tiktoken_preset! {
+    /// The `p50k_base` tokenizer of the Codex models, `text-davinci-002` and `text-davinci-003`,
+    /// a `TiktokenTokenizer` with its bundled rank file, split pattern and special tokens. It
+    /// extends `r50k_base` with tokens for runs of spaces.
+    pub struct P50kTokenizer {
+        ranks: P50K_MERGEABLE_RANKS,
+        pattern: GPT2_SPLIT_PATTERN,
+        special_tokens: P50K_SPECIAL_TOKENS,
+    }
+}
@@ -0,0 +1,674 @@
+//! Contains the incremental pair-count trainer shared by the trainable tokenizers.
+//!
+//! Instead of recounting every pair and re-merging every chunk on each iteration, the
+//! trainer keeps the pair counts and an index from each pair to its occurrences up to date
+//! as merges are applied, and picks the next pair from a priority queue. It produces exactly
+//! the same merges as repeatedly calling `get_stats`/`get_max_entry`/`merge`, including the
+//! tie-breaking: among pairs with the highest count, the one occurring first wins.
+//!
+//! Words carry a weight, so a chunk that occurs many times in the corpus is stored and
+//! merged once, its pairs counting as many times as the chunk occurs. As long as distinct
+//! words are given in order of their first appearance, the tie-breaking is unchanged.
+
+use std::cmp::Ordering;
+use std::collections::{BTreeSet, BinaryHeap, HashMap, HashSet};
+use std::path::Path;
+
+use indexmap::IndexMap;
+#[cfg(feature = "parallel")]
+use rayon::prelude::*;
+
+use crate::checkpoint::{Checkpoint, Fingerprint};
+use crate::observer::{MergeEvent, TrainingObserver, TrainingPhase};
+use crate::trainer::{ChunkCounts, SpecialTokenPlacement, StopReason, TrainingConfig};
+use crate::{build_vocab, Count, MinbpeError, Result, Token, TrainingOutcome};
+
+impl TrainingConfig {
+    /// Returns the id of the first merge.
+    fn first_merge_id(&self) -> Token {
+        match self.special_token_placement {
+            SpecialTokenPlacement::AfterMerges => 256,
+            SpecialTokenPlacement::BeforeMerges => 256 + self.special_tokens.len() as Token,
+        }
+    }
+
+    /// Returns the number of merges allowed by `vocab_size` and `max_merges`.
+    fn merge_limit(&self) -> usize {
+        let num_merges = (self.vocab_size - 256) as usize - self.special_tokens.len();
+        self.max_merges
+            .map_or(num_merges, |max| max.min(num_merges))
+    }
+
+    /// Returns the reason for stopping after the `merge_limit()` merges allowed.
+    fn limit_reason(&self) -> StopReason {
+        if self.max_merges == Some(self.merge_limit()) {
+            StopReason::MaxMerges
+        } else {
+            StopReason::VocabSize
+        }
+    }
+
+    /// Checks that the vocabulary has room for the special tokens and that they are distinct.
+    fn validate(&self) -> Result<()> {
+        if self.vocab_size < 256 + self.special_tokens.len() as Token {
+            return Err(MinbpeError::InvalidVocabSize(self.vocab_size));
+        }
+        let mut seen = HashSet::new();
+        for token in &self.special_tokens {
+            if token.is_empty() || !seen.insert(token) {
+                return Err(MinbpeError::InvalidSpecialToken(token.clone()));
+            }
+        }
+        Ok(())
+    }
+
+    /// Assigns the ids of the special tokens of a run that learned `num_merges` merges.
+    fn special_token_ids(&self, num_merges: usize) -> IndexMap<String, Token> {
+        let first = match self.special_token_placement {
+            SpecialTokenPlacement::AfterMerges => 256 + num_merges as Token,
+            SpecialTokenPlacement::BeforeMerges => 256,
+        };
+        self.special_tokens
+            .iter()
+            .enumerate()
+            .map(|(i, token)| (token.clone(), first + i as Token))
+            .collect()
+    }
+}
+
+/// The result of training: the learned merges, the special tokens declared in the
+/// configuration with their ids, and the resulting vocabulary, special tokens included.
+pub(crate) struct Trained {
+    pub(crate) merges: IndexMap<(Token, Token), Token>,
+    pub(crate) special_tokens: IndexMap<String, Token>,
+    pub(crate) vocab: IndexMap<Token, Vec<u8>>,
+    pub(crate) outcome: TrainingOutcome,
+}
+
+impl Trained {
+    fn new(
+        merges: IndexMap<(Token, Token), Token>,
+        config: &TrainingConfig,
+        stop_reason: StopReason,
+    ) -> Self {
+        let special_tokens = config.special_token_ids(merges.len());
+        let vocab = build_vocab(&special_tokens, &merges);
+        let outcome = TrainingOutcome {
+            num_merges: merges.len(),
+            stop_reason,
+        };
+        Trained {
+            merges,
+            special_tokens,
+            vocab,
+            outcome,
+        }
+    }
+}
+
+/// Trains up to `config.vocab_size - 256` merges over `chunks`, less one per special token.
+/// `pattern` is the split pattern the chunks were split with, which checkpoints record.
+pub(crate) fn train_merges(
+    chunks: &ChunkCounts,
+    config: &TrainingConfig,
+    pattern: &str,
+    observer: &mut dyn TrainingObserver,
+) -> Result<Trained> {
+    config.validate()?;
+    let fingerprint = Fingerprint::new(pattern, &config.special_tokens, chunks);
+
+    // Input text preprocessing
+    observer.on_phase(TrainingPhase::CountingPairs);
+    let ids: Vec<(Vec<Token>, Count)> = chunks
+        .iter()
+        .map(|(chunk, count)| (chunk.iter().map(|&b| b as Token).collect(), count))
+        .collect();
+    let trainer = PairTrainer::new(ids);
+
+    run_merges(trainer, IndexMap::new(), config, &fingerprint, observer)
+}
+
+/// Continues the training run saved in the checkpoint at `path` up to `config.vocab_size`.
+///
+/// The merges are exactly those of an uninterrupted run: the checkpointed words keep their
+/// order and the order of their symbols, which is all the tie-breaking depends on. The
+/// checkpoint must have been written by a run with the split `pattern` and the special tokens
+/// of `config`.
+pub(crate) fn resume_merges(
+    path: &Path,
+    config: &TrainingConfig,
+    pattern: &str,
+    observer: &mut dyn TrainingObserver,
+) -> Result<Trained> {
+    config.validate()?;
+
+    let Checkpoint {
+        fingerprint,
+        mut merges,
+        words,
+    } = Checkpoint::read(path, config.first_merge_id())?;
+    fingerprint.check(pattern, &config.special_tokens)?;
+
+    // A run to a smaller vocabulary would have stopped earlier with a prefix of the merges. The
+    // checkpointed words are further merged than that, so there is nothing left to train.
+    if merges.len() > config.merge_limit() {
+        merges.truncate(config.merge_limit());
+        return Ok(Trained::new(merges, config, config.limit_reason()));
+    }
+
+    observer.on_phase(TrainingPhase::CountingPairs);
+    let trainer = PairTrainer::new(words);
+
+    run_merges(trainer, merges, config, &fingerprint, observer)
+}
+
+/// Merges the most common pairs of `trainer` until a stop criterion of `config` is met or
+/// `observer` stops training, following the `merges` already applied to it. Checkpoints are
+/// written with `fingerprint`.
+fn run_merges(
+    mut trainer: PairTrainer,
+    mut merges: IndexMap<(Token, Token), Token>,
+    config: &TrainingConfig,
+    fingerprint: &Fingerprint,
+    observer: &mut dyn TrainingObserver,
+) -> Result<Trained> {
+    let num_merges = config.merge_limit();
+    let first_id = config.first_merge_id();
+    let mut vocab = build_vocab(&IndexMap::new(), &merges);
+
+    // The corpus keeps its size in bytes while its size in tokens shrinks with every merge
+    let num_bytes: Count = trainer
+        .words()
+        .map(|(ids, weight)| ids.iter().map(|id| vocab[id].len() as Count).sum::<Count>() * weight)
+        .sum();
+
+    // Iteratively merge the most common pairs to create new tokens
+    observer.on_phase(TrainingPhase::Merging);
+    let stop_reason = loop {
+        let i = merges.len();
+        if i == num_merges {
+            break config.limit_reason();
+        }
+        // An empty corpus has no compression to speak of; it stops for lack of pairs instead
+        if let Some(target) = config.target_bytes_per_token {
+            let num_tokens = trainer.num_tokens();
+            if num_tokens > 0 && num_bytes as f64 >= target * num_tokens as f64 {
+                break StopReason::TargetBytesPerToken;
+            }
+        }
+        if observer.should_stop() {
+            break StopReason::Stopped;
+        }
+
+        // Find the pair with the highest count
+        let Some((pair, count)) = trainer.best_pair() else {
+            break StopReason::NoMorePairs;
+        };
+        if config.min_frequency.is_some_and(|min| count < min) {
+            break StopReason::MinFrequency;
+        }
+
+        // Mint a new token: assign it the next available id
+        let idx = first_id + i as Token;
+
+        // Replace all occurrences of pair in ids with idx
+        trainer.merge(pair, idx);
+
+        // Save the merge
+        merges.insert(pair, idx);
+        vocab.insert(
+            idx,
+            [vocab[&pair.0].clone(), vocab[&pair.1].clone()].concat(),
+        );
+
+        observer.on_merge(&MergeEvent {
+            index: i,
+            num_merges,
+            pair,
+            new_id: idx,
+            bytes: &vocab[&idx],
+            count,
+        });
+
+        if let Some(checkpoint) = &config.checkpoint {
+            if checkpoint.interval > 0 && (i + 1) % checkpoint.interval == 0 {
+                Checkpoint::write(&checkpoint.path, fingerprint, &merges, &trainer)?;
+            }
+        }
+    };
+
+    if let Some(checkpoint) = &config.checkpoint {
+        Checkpoint::write(&checkpoint.path, fingerprint, &merges, &trainer)?;
+    }
+
+    Ok(Trained::new(merges, config, stop_reason))
+}
+
+/// Marks a symbol that has been merged into its left neighbour.
+const REMOVED: Token = Token::MIN;
+
+/// Marks the absence of a previous or next symbol.
+const NONE: u32 = u32::MAX;
+
+/// The position of an occurrence of a pair: the word, and the index of the pair's left symbol
+/// within that word. Symbols keep their original index when merged, so ordering occurrences
+/// by position is the same as ordering them in the current (merged) sequence.
+type Position = (u32, u32);
+
+/// A word (chunk) as a doubly-linked list of symbols over fixed indices.
+#[derive(Default)]
+struct Word {
+    ids: Vec<Token>,
+    prev: Vec<u32>,
+    next: Vec<u32>,
+}
+
+impl Word {
+    fn new(ids: Vec<Token>) -> Self {
+        let len = ids.len() as u32;
+        let prev = (0..len)
+            .map(|i| if i == 0 { NONE } else { i - 1 })
+            .collect();
+        let next = (0..len)
+            .map(|i| if i + 1 == len { NONE } else { i + 1 })
+            .collect();
+        Word { ids, prev, next }
+    }
+
+    /// Merges the occurrences of `pair` at `positions` (sorted) in this word, the `w`-th one.
+    /// Returns the resulting changes to the pair occurrences, in the order they must be applied,
+    /// and the number of occurrences merged.
+    fn merge(
+        &mut self,
+        w: u32,
+        positions: &[u32],
+        pair: (Token, Token),
+        new_id: Token,
+    ) -> (Vec<Change>, Count) {
+        let mut changes = Vec::new();
+        let mut merged = 0;
+
+        for &pos in positions {
+            let p = pos as usize;
+
+            // Skip occurrences destroyed by merging an overlapping occurrence, e.g. the second
+            // (a, a) of "aaa"
+            if self.ids[p] != pair.0 {
+                continue;
+            }
+            let next = self.next[p];
+            if next == NONE || self.ids[next as usize] != pair.1 {
+                continue;
+            }
+
+            let before = self.prev[p];
+            let after = self.next[next as usize];
+
+            if before != NONE {
+                let id = self.ids[before as usize];
+                changes.push(Change::Remove((id, pair.0), (w, before)));
+                changes.push(Change::Add((id, new_id), (w, before)));
+            }
+            if after != NONE {
+                let id = self.ids[after as usize];
+                changes.push(Change::Remove((pair.1, id), (w, next)));
+                changes.push(Change::Add((new_id, id), (w, pos)));
+            }
+
+            // Merge the right symbol into the left one
+            self.ids[p] = new_id;
+            self.ids[next as usize] = REMOVED;
+            self.next[p] = after;
+            if after != NONE {
+                self.prev[after as usize] = pos;
+            }
+            merged += 1;
+        }
+
+        (changes, merged)
+    }
+}
+
+/// A change to the occurrences of a pair caused by a merge.
+enum Change {
+    Remove((Token, Token), Position),
+    Add((Token, Token), Position),
+}
+
+type PairCounts = HashMap<(Token, Token), Count>;
+type PairOccurrences = HashMap<(Token, Token), BTreeSet<Position>>;
+
+/// Counts the pairs of `words`, the first of which is the `offset`-th word overall.
+fn count_pairs(words: &[Word], weights: &[Count], offset: usize) -> (PairCounts, PairOccurrences) {
+    let mut counts = PairCounts::new();
+    let mut occurrences = PairOccurrences::new();
+    for (i, word) in words.iter().enumerate() {
+        let w = offset + i;
+        for (pos, pair) in word.ids.windows(2).enumerate() {
+            let pair = (pair[0], pair[1]);
+            *counts.entry(pair).or_insert(0) += weights[w];
+            occurrences
+                .entry(pair)
+                .or_default()
+                .insert((w as u32, pos as u32));
+        }
+    }
+    (counts, occurrences)
+}
+
+/// An entry of the priority queue. Entries are never updated in place: when a pair's count
+/// drops or its first occurrence moves, a stale entry stays in the queue and is refreshed
+/// when it reaches the top. This is sound because existing pairs can only lose occurrences.
+#[derive(PartialEq, Eq)]
+struct QueueEntry {
+    count: Count,
+    first: Position,
+    pair: (Token, Token),
+}
+
+impl Ord for QueueEntry {
+    fn cmp(&self, other: &Self) -> Ordering {
+        // Highest count first, then earliest first occurrence
+        self.count
+            .cmp(&other.count)
+            .then_with(|| other.first.cmp(&self.first))
+    }
+}
+
+impl PartialOrd for QueueEntry {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+pub(crate) struct PairTrainer {
+    words: Vec<Word>,
+    weights: Vec<Count>,
+    num_tokens: Count,
+    counts: PairCounts,
+    occurrences: PairOccurrences,
+    queue: BinaryHeap<QueueEntry>,
+}
+
+/// Number of words counted by one task when counting pairs in parallel.
+#[cfg(feature = "parallel")]
+const PARALLEL_COUNT_WORDS: usize = 4096;
+
+/// Minimum number of words affected by a merge for them to be merged in parallel.
+#[cfg(feature = "parallel")]
+const PARALLEL_MERGE_WORDS: usize = 256;
+
+impl PairTrainer {
+    /// Creates a trainer over `words`, each with the number of times it occurs. Pairs are only
+    /// counted within a word, never across two consecutive words.
+    pub(crate) fn new(words: Vec<(Vec<Token>, Count)>) -> Self {
+        let (words, weights): (Vec<Word>, Vec<Count>) = words
+            .into_iter()
+            .map(|(ids, weight)| (Word::new(ids), weight))
+            .unzip();
+
+        #[cfg(not(feature = "parallel"))]
+        let (counts, occurrences) = count_pairs(&words, &weights, 0);
+
+        // Shards are merged in a fixed order, and the result does not depend on it anyway:
+        // counts are sums and occurrences are sets.
+        #[cfg(feature = "parallel")]
+        let (counts, occurrences) = words
+            .par_chunks(PARALLEL_COUNT_WORDS)
+            .enumerate()
+            .map(|(i, shard)| count_pairs(shard, &weights, i * PARALLEL_COUNT_WORDS))
+            .reduce(
+                || (PairCounts::new(), PairOccurrences::new()),
+                |(mut counts, mut occurrences), (shard_counts, shard_occurrences)| {
+                    for (pair, count) in shard_counts {
+                        *counts.entry(pair).or_insert(0) += count;
+                    }
+                    for (pair, mut positions) in shard_occurrences {
+                        occurrences.entry(pair).or_default().append(&mut positions);
+                    }
+                    (counts, occurrences)
+                },
+            );
+
+        let num_tokens = words
+            .iter()
+            .zip(&weights)
+            .map(|(word, weight)| word.ids.len() as Count * weight)
+            .sum();
+
+        let queue = occurrences
+            .iter()
+            .map(|(&pair, positions)| QueueEntry {
+                count: counts[&pair],
+                first: *positions.first().unwrap(),
+                pair,
+            })
+            .collect();
+
+        PairTrainer {
+            words,
+            weights,
+            num_tokens,
+            counts,
+            occurrences,
+            queue,
+        }
+    }
+
+    /// Returns the pair with the highest count, and that count, without merging it.
+    /// Among pairs with the same count the one occurring first is returned.
+    pub(crate) fn best_pair(&mut self) -> Option<((Token, Token), Count)> {
+        while let Some(top) = self.queue.peek() {
+            let pair = top.pair;
+            let current = self
+                .occurrences
+                .get(&pair)
+                .map(|positions| (self.counts[&pair], *positions.first().unwrap()));
+
+            match current {
+                Some((count, first)) if count == top.count && first == top.first => {
+                    return Some((pair, count));
+                }
+                Some((count, first)) => {
+                    self.queue.pop();
+                    self.queue.push(QueueEntry { count, first, pair });
+                }
+                None => {
+                    self.queue.pop();
+                }
+            }
+        }
+        None
+    }
+
+    /// Replaces every (non-overlapping, left to right) occurrence of `pair` by `new_id`,
+    /// updating the counts and occurrences of the neighbouring pairs.
+    pub(crate) fn merge(&mut self, pair: (Token, Token), new_id: Token) {
+        let Some(positions) = self.occurrences.remove(&pair) else {
+            return;
+        };
+        self.counts.remove(&pair);
+
+        // Group the positions by word; both are in ascending order
+        let mut groups: Vec<(u32, Vec<u32>)> = Vec::new();
+        for (w, pos) in positions {
+            match groups.last_mut() {
+                Some((last, group)) if *last == w => group.push(pos),
+                _ => groups.push((w, vec![pos])),
+            }
+        }
+
+        let merged = self.merge_words(&groups, pair, new_id);
+
+        let mut created: HashSet<(Token, Token)> = HashSet::new();
+        for ((changes, count), (w, _)) in merged.into_iter().zip(&groups) {
+            self.num_tokens -= count * self.weights[*w as usize];
+            for change in changes {
+                match change {
+                    // Occurrences of the pair being merged were removed wholesale above
+                    Change::Remove(old, _) if old == pair => {}
+                    Change::Remove(old, position) => self.remove_occurrence(old, position),
+                    Change::Add(new, position) => {
+                        self.add_occurrence(new, position);
+                        created.insert(new);
+                    }
+                }
+            }
+        }
+
+        // Pairs involving `new_id` did not exist before this merge, so they are queued now that
+        // their counts and first occurrences are final for this merge.
+        for new in created {
+            if let Some(positions) = self.occurrences.get(&new) {
+                self.queue.push(QueueEntry {
+                    count: self.counts[&new],
+                    first: *positions.first().unwrap(),
+                    pair: new,
+                });
+            }
+        }
+    }
+
+    /// Merges `pair` in each word of `groups`, returning the changes of each word in order and
+    /// the number of occurrences merged in it.
+    fn merge_words(
+        &mut self,
+        groups: &[(u32, Vec<u32>)],
+        pair: (Token, Token),
+        new_id: Token,
+    ) -> Vec<(Vec<Change>, Count)> {
+        #[cfg(feature = "parallel")]
+        if groups.len() >= PARALLEL_MERGE_WORDS {
+            // Words are disjoint, so they are moved out, merged in parallel and moved back
+            let mut words: Vec<Word> = groups
+                .iter()
+                .map(|(w, _)| std::mem::take(&mut self.words[*w as usize]))
+                .collect();
+            let changes = words
+                .par_iter_mut()
+                .zip(groups.par_iter())
+                .map(|(word, (w, positions))| word.merge(*w, positions, pair, new_id))
+                .collect();
+            for (word, (w, _)) in words.into_iter().zip(groups) {
+                self.words[*w as usize] = word;
+            }
+            return changes;
+        }
+
+        groups
+            .iter()
+            .map(|(w, positions)| self.words[*w as usize].merge(*w, positions, pair, new_id))
+            .collect()
+    }
+
+    /// Returns the number of tokens of the corpus: the number of symbols of every word, times
+    /// its weight.
+    pub(crate) fn num_tokens(&self) -> Count {
+        self.num_tokens
+    }
+
+    pub(crate) fn num_words(&self) -> usize {
+        self.words.len()
+    }
+
+    /// Returns the current words, with merged symbols removed, and their weights.
+    pub(crate) fn words(&self) -> impl Iterator<Item = (Vec<Token>, Count)> + '_ {
+        self.words.iter().zip(&self.weights).map(|(word, &weight)| {
+            // The first symbol is never merged into a left neighbour, so it heads the list
+            let mut ids = Vec::new();
+            let mut pos = if word.ids.is_empty() { NONE } else { 0 };
+            while pos != NONE {
+                ids.push(word.ids[pos as usize]);
+                pos = word.next[pos as usize];
+            }
+            (ids, weight)
+        })
+    }
+
+    fn add_occurrence(&mut self, pair: (Token, Token), position: Position) {
+        *self.counts.entry(pair).or_insert(0) += self.weights[position.0 as usize];
+        self.occurrences.entry(pair).or_default().insert(position);
+    }
+
+    fn remove_occurrence(&mut self, pair: (Token, Token), position: Position) {
+        if let Some(positions) = self.occurrences.get_mut(&pair) {
+            if positions.remove(&position) {
+                if positions.is_empty() {
+                    self.occurrences.remove(&pair);
+                    self.counts.remove(&pair);
+                } else {
+                    *self.counts.get_mut(&pair).unwrap() -= self.weights[position.0 as usize];
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{get_max_entry, get_stats, merge, update_stats};
+    use indexmap::IndexMap;
+
+    #[test]
+    fn test_overlapping_pairs() {
+        // (97, 97) occurs three times (overlapping) in "aaaa" but merges twice
+        let mut trainer = PairTrainer::new(vec![(vec![97, 97, 97, 97], 1)]);
+        assert_eq!(trainer.best_pair(), Some(((97, 97), 3)));
+        trainer.merge((97, 97), 256);
+        assert_eq!(trainer.best_pair(), Some(((256, 256), 1)));
+        trainer.merge((256, 256), 257);
+        assert_eq!(trainer.best_pair(), None);
+    }
+
+    #[test]
+    fn test_matches_get_max_entry() {
+        let text = "aaabdaaabac aaabdaaabac abracadabra";
+        let mut ids: Vec<Token> = text.bytes().map(|b| b as Token).collect();
+        let mut trainer = PairTrainer::new(vec![(ids.clone(), 1)]);
+
+        for idx in 256..270 {
+            let stats = get_stats(&ids);
+            let expected = get_max_entry(&stats).map(|(pair, count)| (*pair, *count));
+            assert_eq!(trainer.best_pair(), expected);
+            let Some((pair, _)) = expected else { break };
+            ids = merge(&ids, pair, idx);
+            trainer.merge(pair, idx);
+        }
+    }
+
+    #[test]
+    fn test_weighted_words() {
+        // "ab" occurring three times outweighs "cd" and "de" occurring twice each
+        let mut trainer = PairTrainer::new(vec![(vec![99, 100, 101], 2), (vec![97, 98], 3)]);
+        assert_eq!(trainer.best_pair(), Some(((97, 98), 3)));
+        trainer.merge((97, 98), 256);
+        assert_eq!(trainer.best_pair(), Some(((99, 100), 2)));
+    }
+
+    #[test]
+    fn test_many_words_match_get_max_entry() {
+        // Enough distinct words to shard the counting and merging with the `parallel` feature
+        let mut words: Vec<Vec<Token>> = (0..5000u32)
+            .map(|i| {
+                format!("{} {}", i * 7919 % 10007, i % 13)
+                    .bytes()
+                    .map(|b| b as Token)
+                    .collect()
+            })
+            .collect();
+        let mut trainer = PairTrainer::new(words.iter().map(|w| (w.clone(), 1)).collect());
+
+        for idx in 256..300 {
+            let mut stats = IndexMap::new();
+            for word in &words {
+                update_stats(word, &mut stats);
+            }
+            let expected = get_max_entry(&stats).map(|(pair, count)| (*pair, *count));
+            assert_eq!(trainer.best_pair(), expected);
+            let Some((pair, _)) = expected else { break };
+            words = words.iter().map(|word| merge(word, pair, idx)).collect();
+            trainer.merge(pair, idx);
+        }
+    }
+}
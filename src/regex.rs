@@ -1,9 +1,21 @@
 use fancy_regex::Regex;
 use indexmap::IndexMap;
+#[cfg(feature = "parallel")]
+use rayon::prelude::*;
+use std::borrow::Cow;
 use std::collections::HashSet;
-
-use crate::{get_max_entry, Loadable, Saveable, Trainable};
-use crate::{get_stats, merge, update_stats, Token, Tokenizer};
+use std::num::NonZeroUsize;
+use std::path::Path;
+use std::sync::OnceLock;
+
+use crate::base::{check_special_token_ids, map_batch, parallel_methods};
+use crate::observer::TrainingObserver;
+use crate::offsets::{SpanBuilder, TokenSpan};
+use crate::pair_trainer::{resume_merges, train_merges, Trained};
+use crate::trainer::{TrainingConfig, TrainingOutcome};
+use crate::{CacheStats, ChunkCache};
+use crate::{ChunkCounts, Loadable, Saveable, Token, TokenTables, Tokenizer, Trainable};
+use crate::{MinbpeError, Result};
 
 /// The main GPT text split patterns, see
 /// https://github.com/openai/tiktoken/blob/main/tiktoken_ext/openai_public.py
@@ -12,6 +24,11 @@ pub const GPT2_SPLIT_PATTERN: &str =
 
 pub const GPT4_SPLIT_PATTERN: &str = r"'(?i:[sdmt]|ll|ve|re)|[^\r\n\p{L}\p{N}]?+\p{L}+|\p{N}{1,3}| ?[^\s\p{L}\p{N}]++[\r\n]*|\s*[\r\n]|\s+(?!\S)|\s+";
 
+/// A split pattern making the whole text a single chunk, which is how formats that require a
+/// pattern represent a tokenizer without one.
+#[cfg(any(feature = "huggingface", feature = "tiktoken"))]
+pub(crate) const WHOLE_TEXT_PATTERN: &str = r"(?s).+";
+
 /// Specifies how to handle special tokens during encoding.
 ///
 /// This enum is used to control the behavior of the `encode_special` function
@@ -57,40 +74,47 @@ pub enum AllowedSpecial {
     Set(HashSet<String>),
 }
 
+/// Minimum number of chunks encoded by one task when encoding in parallel.
+#[cfg(feature = "parallel")]
+const PARALLEL_ENCODE_CHUNKS: usize = 256;
+
+/// Tokenizers splitting text into chunks with a regex before applying the merges to each chunk.
 pub trait RegexTokenizerTrait: Tokenizer {
+    /// Applies the merges to the bytes of a chunk, lowest merge index first.
     fn encode_chunk_inner(&self, text_bytes: &[u8]) -> Vec<Token> {
-        let merges = self.merges();
-        let mut ids: Vec<Token> = text_bytes.iter().map(|&b| b as Token).collect();
-        while ids.len() >= 2 {
-            // Find the pair with the lowest merge index
-            let stats = get_stats(&ids);
-
-            let pair_opt = stats
-                .keys()
-                .filter_map(|&pair| merges.get(&pair).map(|_| pair))
-                .min_by_key(|&pair| merges[&pair]);
-
-            match pair_opt {
-                None => break, // If there are no more merges available, break
-                Some(pair) => {
-                    // Otherwise, merge the best pair (lowest merge index)
-                    let idx = merges[&pair];
-                    ids = merge(&ids, pair, idx);
-                }
-            };
-        }
-        ids
+        self.tables().encode_bytes(text_bytes)
     }
 
     fn encode_chunk(&self, text_bytes: &[u8]) -> Vec<Token> {
         self.encode_chunk_inner(text_bytes)
     }
 
+    /// The cache of encoded chunks, if the tokenizer has one.
+    fn chunk_cache(&self) -> Option<&ChunkCache> {
+        None
+    }
+
+    /// Returns the statistics of the cache of encoded chunks, if the tokenizer has one.
+    fn cache_stats(&self) -> Option<CacheStats> {
+        self.chunk_cache().map(ChunkCache::stats)
+    }
+
+    /// Encodes a chunk, looking it up in the cache of encoded chunks first if there is one.
+    fn encode_chunk_cached(&self, text_bytes: &[u8]) -> Vec<Token> {
+        match self.chunk_cache() {
+            Some(cache) => cache.get_or_insert_with(text_bytes, |bytes| self.encode_chunk(bytes)),
+            None => self.encode_chunk(text_bytes),
+        }
+    }
+
     // fn pattern(&self) -> &str;
     // fn set_pattern(&mut self, pattern: &str);
 
     fn compiled_pattern(&self) -> &Regex;
 
+    /// The lookup tables compiled from the merges and vocabulary, used to encode and decode.
+    fn tables(&self) -> &TokenTables;
+
     // fn special_tokens(&self) -> &IndexMap<String, Token>;
     // fn set_special_tokens(&mut self, special_tokens: IndexMap<String, Token>);
 
@@ -106,77 +130,218 @@ pub trait RegexTokenizerTrait: Tokenizer {
     // fn decode(&self, ids: &[Token]) -> String;
     // fn encode(&self, text: &str) -> Vec<Token>;
 
+    /// Decodes like `Tokenizer::try_decode`, which all tokenizers share.
+    fn try_decode(&self, ids: &[Token]) -> Result<String> {
+        Tokenizer::try_decode(self, ids)
+    }
+
+    /// # Panics
+    ///
+    /// Panics if `try_decode` returns an error, i.e. on an invalid token id.
     fn decode(&self, ids: &[Token]) -> String {
-        let mut part_bytes = Vec::new();
-        for &idx in ids {
-            if let Some(bytes) = self.vocab().get(&idx) {
-                part_bytes.extend_from_slice(bytes);
-            } else if let Some(special_token) = self.inverse_special_tokens().get(&idx) {
-                part_bytes.extend_from_slice(special_token.as_bytes());
-            } else {
-                panic!("Invalid token id: {}", idx);
+        RegexTokenizerTrait::try_decode(self, ids).unwrap_or_else(|e| panic!("{}", e))
+    }
+
+    parallel_methods! {
+        fn try_encode(&self, text: &str) -> Result<Vec<Token>> {
+            self.try_encode_special(text, AllowedSpecial::NoneRaise)
+        }
+
+        /// # Panics
+        ///
+        /// Panics if `try_encode` returns an error, e.g. if the text contains a special token.
+        fn encode(&self, text: &str) -> Vec<Token> {
+            self.encode_special(text, AllowedSpecial::NoneRaise)
+        }
+
+        /// Encoding that ignores any special tokens.
+        ///
+        /// # Errors
+        ///
+        /// Returns `MinbpeError::RegexBacktrackLimit` if the split pattern exceeds its backtracking limit.
+        fn try_encode_ordinary(&self, text: &str) -> Result<Vec<Token>> {
+            #[cfg(feature = "parallel")]
+            {
+                // Chunks are encoded independently, so they are encoded in parallel and concatenated
+                // in their original order.
+                let mut text_chunks: Vec<&str> = Vec::new();
+                for m in self.compiled_pattern().find_iter(text) {
+                    text_chunks.push(m?.as_str());
+                }
+                let chunk_ids: Vec<Vec<Token>> = text_chunks
+                    .par_iter()
+                    .with_min_len(PARALLEL_ENCODE_CHUNKS)
+                    .map(|chunk| self.encode_chunk_cached(chunk.as_bytes()))
+                    .collect();
+                Ok(chunk_ids.concat())
+            }
+
+            #[cfg(not(feature = "parallel"))]
+            {
+                let mut ids = Vec::new();
+                for m in self.compiled_pattern().find_iter(text) {
+                    let chunk = m?.as_str();
+                    let chunk_ids = self.encode_chunk_cached(chunk.as_bytes());
+                    ids.extend(chunk_ids);
+                }
+                Ok(ids)
             }
         }
-        String::from_utf8_lossy(&part_bytes).into_owned()
-    }
 
-    fn encode(&self, text: &str) -> Vec<Token> {
-        self.encode_special(text, AllowedSpecial::NoneRaise)
-    }
+        /// Encoding that ignores any special tokens.
+        ///
+        /// # Panics
+        ///
+        /// Panics if `try_encode_ordinary` returns an error.
+        fn encode_ordinary(&self, text: &str) -> Vec<Token> {
+            self.try_encode_ordinary(text)
+                .unwrap_or_else(|e| panic!("{}", e))
+        }
 
-    /// Encoding that ignores any special tokens.
-    fn encode_ordinary(&self, text: &str) -> Vec<Token> {
-        let text_chunks: Vec<&str> = self
-            .compiled_pattern()
-            .find_iter(text)
-            .map(|m| {
-                let matched = m.unwrap();
-                &text[matched.start()..matched.end()]
-            })
-            .collect();
-        let mut ids = Vec::new();
-        for chunk in text_chunks {
-            let chunk_bytes = chunk.as_bytes();
-            let chunk_ids = self.encode_chunk(chunk_bytes);
-            ids.extend(chunk_ids);
+        /// Encodes the given text into token IDs, handling special tokens.
+        ///
+        /// Unlike `encode_ordinary`, this function handles special tokens based on the `allowed_special` parameter.
+        ///
+        /// # Arguments
+        ///
+        /// * `text` - The text to encode.
+        /// * `allowed_special` - Specifies how to handle special tokens. It can be one of the following:
+        ///   - `AllowedSpecial::All`: Allow all special tokens.
+        ///   - `AllowedSpecial::None`: Ignore all special tokens.
+        ///   - `AllowedSpecial::NoneRaise`: Raise an error if any special token is encountered in the text.
+        ///     This is the default behavior of the `tiktoken` library.
+        ///   - `AllowedSpecial::Set(HashSet<String>)`: A custom set of allowed special tokens.
+        ///
+        /// # Errors
+        ///
+        /// Returns `MinbpeError::DisallowedSpecialToken` if `allowed_special` is set to
+        /// `AllowedSpecial::NoneRaise` and any special token is encountered in the text, or a regex
+        /// error if matching fails.
+        fn try_encode_special(
+            &self,
+            text: &str,
+            allowed_special: AllowedSpecial,
+        ) -> Result<Vec<Token>> {
+            let matcher = SpecialMatcher::new(self, allowed_special)?;
+            let mut ids = Vec::new();
+            matcher.encode_into(self, text, &mut ids, |part| self.try_encode_ordinary(part))?;
+            Ok(ids)
+        }
+
+        /// Encodes the given text into token IDs, handling special tokens, see `try_encode_special`.
+        ///
+        /// # Panics
+        ///
+        /// Panics if `allowed_special` is set to `AllowedSpecial::NoneRaise` and any special token is encountered in the text.
+        fn encode_special(&self, text: &str, allowed_special: AllowedSpecial) -> Vec<Token> {
+            self.try_encode_special(text, allowed_special)
+                .unwrap_or_else(|e| panic!("{}", e))
         }
-        ids
     }
 
-    /// Encodes the given text into token IDs, handling special tokens.
-    ///
-    /// Unlike `encode_ordinary`, this function handles special tokens based on the `allowed_special` parameter.
+    /// Encodes the given text like `try_encode_special`, also returning where each token is in
+    /// the text.
     ///
-    /// # Arguments
+    /// # Errors
     ///
-    /// * `text` - The text to encode.
-    /// * `allowed_special` - Specifies how to handle special tokens. It can be one of the following:
-    ///   - `AllowedSpecial::All`: Allow all special tokens.
-    ///   - `AllowedSpecial::None`: Ignore all special tokens.
-    ///   - `AllowedSpecial::NoneRaise`: Raise an error if any special token is encountered in the text.
-    ///     This is the default behavior of the `tiktoken` library.
-    ///   - `AllowedSpecial::Set(HashSet<String>)`: A custom set of allowed special tokens.
+    /// Returns the errors of `try_encode_special`.
+    fn try_encode_with_offsets(
+        &self,
+        text: &str,
+        allowed_special: AllowedSpecial,
+    ) -> Result<Vec<TokenSpan>> {
+        let matcher = SpecialMatcher::new(self, allowed_special)?;
+        let mut spans = SpanBuilder::new();
+        matcher.split(self, text, |start, part, special| {
+            if let Some(idx) = special {
+                spans.push_part(start, &[idx], |_| part.len());
+                return Ok(());
+            }
+            // Text the split pattern does not match is not encoded, so chunks are located by
+            // their matches
+            for m in self.compiled_pattern().find_iter(part) {
+                let m = m?;
+                let ids = self.encode_chunk_cached(m.as_str().as_bytes());
+                spans.push_part(start + m.start(), &ids, |idx| {
+                    self.token_bytes(idx).map_or(0, |bytes| bytes.len())
+                });
+            }
+            Ok(())
+        })?;
+        Ok(spans.finish(text))
+    }
+
+    /// Encodes the given text with the offsets of its tokens, see `try_encode_with_offsets`.
     ///
     /// # Panics
     ///
-    /// Panics if `allowed_special` is set to `AllowedSpecial::NoneRaise` and any special token is encountered in the text.
-    fn encode_special(&self, text: &str, allowed_special: AllowedSpecial) -> Vec<Token> {
+    /// Panics if `try_encode_with_offsets` returns an error.
+    fn encode_with_offsets(&self, text: &str, allowed_special: AllowedSpecial) -> Vec<TokenSpan> {
+        self.try_encode_with_offsets(text, allowed_special)
+            .unwrap_or_else(|e| panic!("{}", e))
+    }
+
+    parallel_methods! {
+        /// Encodes every text of a batch, handling special tokens as `try_encode_special` does, in
+        /// parallel with the `parallel` feature. The ids are returned in the order of the texts.
+        ///
+        /// The pattern matching the allowed special tokens is built once for the whole batch, and
+        /// each worker encodes into a buffer it reuses across texts.
+        ///
+        /// # Errors
+        ///
+        /// Returns an error if any text cannot be encoded, see `try_encode_special`.
+        fn try_encode_special_batch(
+            &self,
+            texts: &[&str],
+            allowed_special: AllowedSpecial,
+        ) -> Result<Vec<Vec<Token>>> {
+            let matcher = SpecialMatcher::new(self, allowed_special)?;
+            map_batch(texts, Vec::new, |ids: &mut Vec<Token>, text| {
+                ids.clear();
+                matcher.encode_into(self, text, ids, |part| self.try_encode_ordinary(part))?;
+                Ok(ids.clone())
+            })
+        }
+
+        /// Encodes every text of a batch, handling special tokens, see `try_encode_special_batch`.
+        ///
+        /// # Panics
+        ///
+        /// Panics if `try_encode_special_batch` returns an error.
+        fn encode_special_batch(
+            &self,
+            texts: &[&str],
+            allowed_special: AllowedSpecial,
+        ) -> Vec<Vec<Token>> {
+            self.try_encode_special_batch(texts, allowed_special)
+                .unwrap_or_else(|e| panic!("{}", e))
+        }
+    }
+}
+
+/// The special tokens allowed when encoding and the pattern splitting text on them, built once
+/// from an `AllowedSpecial` for all the texts it encodes.
+struct SpecialMatcher {
+    /// Whether a special token in the text is an error, see `AllowedSpecial::NoneRaise`.
+    raise: bool,
+    special: IndexMap<String, Token>,
+    pattern: Option<Regex>,
+}
+
+impl SpecialMatcher {
+    fn new<T: RegexTokenizerTrait + ?Sized>(
+        tokenizer: &T,
+        allowed_special: AllowedSpecial,
+    ) -> Result<Self> {
+        let raise = matches!(allowed_special, AllowedSpecial::NoneRaise);
         let special = match allowed_special {
-            AllowedSpecial::All => self.special_tokens().clone(),
-            AllowedSpecial::None => IndexMap::new(),
-            AllowedSpecial::NoneRaise => {
-                assert!(
-                    self.special_tokens()
-                        .keys()
-                        .all(|token| !text.contains(token)),
-                    "Special token found in text"
-                );
-                IndexMap::new()
-            }
+            AllowedSpecial::All => tokenizer.special_tokens().clone(),
+            AllowedSpecial::None | AllowedSpecial::NoneRaise => IndexMap::new(),
             AllowedSpecial::Set(special_tokens) => {
                 let mut special = IndexMap::new();
                 for token in special_tokens {
-                    if let Some(&idx) = self.special_tokens().get(&token) {
+                    if let Some(&idx) = tokenizer.special_tokens().get(&token) {
                         special.insert(token, idx);
                     }
                 }
@@ -184,43 +349,81 @@ pub trait RegexTokenizerTrait: Tokenizer {
             }
         };
 
-        if special.is_empty() {
-            return self.encode_ordinary(text);
-        }
+        let pattern = if special.is_empty() {
+            None
+        } else {
+            let special_pattern = "(".to_string()
+                + &special
+                    .keys()
+                    .map(|k| regex::escape(k))
+                    .collect::<Vec<String>>()
+                    .join("|")
+                + ")";
+            Some(Regex::new(&special_pattern)?)
+        };
 
-        let special_pattern = "(".to_string()
-            + &special
+        Ok(SpecialMatcher {
+            raise,
+            special,
+            pattern,
+        })
+    }
+
+    /// Splits `text` on the allowed special tokens, calling `f` with the byte offset of every
+    /// part and the id of the special token it is, if it is one. Empty parts are skipped.
+    fn split<'t, T, F>(&self, tokenizer: &T, text: &'t str, mut f: F) -> Result<()>
+    where
+        T: RegexTokenizerTrait + ?Sized,
+        F: FnMut(usize, &'t str, Option<Token>) -> Result<()>,
+    {
+        if self.raise {
+            if let Some(token) = tokenizer
+                .special_tokens()
                 .keys()
-                .map(|k| regex::escape(k))
-                .collect::<Vec<String>>()
-                .join("|")
-            + ")";
+                .find(|token| text.contains(token.as_str()))
+            {
+                return Err(MinbpeError::DisallowedSpecialToken(token.clone()));
+            }
+        }
 
-        let re = fancy_regex::Regex::new(&special_pattern).unwrap();
         let mut last_end = 0;
-        let mut special_chunks = Vec::new();
-        for m in re.find_iter(text) {
-            let m = m.unwrap();
-            // Push the text between matches
-            special_chunks.push(&text[last_end..m.start()]);
-            // Push the matched text
-            special_chunks.push(&text[m.start()..m.end()]);
-            last_end = m.end();
+        if let Some(pattern) = &self.pattern {
+            for m in pattern.find_iter(text) {
+                let m = m?;
+                // The text between matches, then the matched special token
+                if m.start() > last_end {
+                    f(last_end, &text[last_end..m.start()], None)?;
+                }
+                f(m.start(), m.as_str(), Some(self.special[m.as_str()]))?;
+                last_end = m.end();
+            }
         }
-        let remaining = &text[last_end..];
-        if !remaining.is_empty() {
-            special_chunks.push(remaining);
+        if last_end < text.len() {
+            f(last_end, &text[last_end..], None)?;
         }
+        Ok(())
+    }
 
-        let mut ids = Vec::new();
-        for part in special_chunks {
-            if let Some(&idx) = special.get(part) {
-                ids.push(idx);
-            } else {
-                ids.extend(self.encode_ordinary(part));
+    /// Appends the ids of `text` to `ids`, encoding the parts that are not special tokens with
+    /// `encode_ordinary`.
+    fn encode_into<T, F>(
+        &self,
+        tokenizer: &T,
+        text: &str,
+        ids: &mut Vec<Token>,
+        encode_ordinary: F,
+    ) -> Result<()>
+    where
+        T: RegexTokenizerTrait + ?Sized,
+        F: Fn(&str) -> Result<Vec<Token>>,
+    {
+        self.split(tokenizer, text, |_, part, special| {
+            match special {
+                Some(idx) => ids.push(idx),
+                None => ids.extend(encode_ordinary(part)?),
             }
-        }
-        ids
+            Ok(())
+        })
     }
 }
 
@@ -268,6 +471,9 @@ pub struct RegexTokenizerStruct {
     inverse_special_tokens: IndexMap<Token, String>,
     merges: IndexMap<(Token, Token), Token>,
     vocab: IndexMap<Token, Vec<u8>>,
+    /// Built from the merges and vocabulary when first used, see `tables`.
+    tables: OnceLock<TokenTables>,
+    chunk_cache: Option<ChunkCache>,
 }
 
 impl Default for RegexTokenizerStruct {
@@ -277,22 +483,50 @@ impl Default for RegexTokenizerStruct {
 }
 
 impl RegexTokenizerStruct {
-    fn make(pattern: String) -> Self {
-        let compiled_pattern = Regex::new(&pattern).unwrap();
+    fn make(pattern: String) -> Result<Self> {
+        let compiled_pattern = Regex::new(&pattern)?;
 
-        RegexTokenizerStruct {
+        Ok(RegexTokenizerStruct {
             pattern,
             compiled_pattern,
             special_tokens: IndexMap::new(),
             inverse_special_tokens: IndexMap::new(),
             merges: IndexMap::new(),
             vocab: IndexMap::new(),
-        }
+            tables: OnceLock::new(),
+            chunk_cache: None,
+        })
     }
 
+    /// # Panics
+    ///
+    /// Panics if `pattern` is not a valid regex, see `try_new`.
     pub fn new(pattern: String) -> Self {
+        Self::make(pattern).unwrap_or_else(|e| panic!("{}", e))
+    }
+
+    /// # Errors
+    ///
+    /// Returns `MinbpeError::InvalidRegex` if `pattern` is not a valid regex.
+    pub fn try_new(pattern: String) -> Result<Self> {
         Self::make(pattern)
     }
+
+    /// Sets the number of encoded chunks to cache, replacing the cache and its statistics. A
+    /// capacity of 0 disables the cache, which is the default.
+    pub fn set_chunk_cache_capacity(&mut self, capacity: usize) {
+        self.chunk_cache = NonZeroUsize::new(capacity).map(ChunkCache::new);
+    }
+
+    /// Drops the lookup tables, which `tables` builds again when next used, and clears the cache
+    /// of encoded chunks after the merges or vocabulary changed. Setting both the merges and the
+    /// vocabulary thus builds the tables once.
+    fn reset_tables(&mut self) {
+        self.tables = OnceLock::new();
+        if let Some(cache) = &self.chunk_cache {
+            cache.clear();
+        }
+    }
 }
 
 impl Tokenizer for RegexTokenizerStruct {
@@ -308,86 +542,72 @@ impl Tokenizer for RegexTokenizerStruct {
         &self.vocab
     }
 
-    fn decode(&self, ids: &[Token]) -> String {
-        // Forwarding to the default implementation provided by RegexTokenizerTrait
-        <Self as RegexTokenizerTrait>::decode(self, ids)
+    fn split_pattern(&self) -> &str {
+        &self.pattern
+    }
+
+    fn token_bytes(&self, idx: Token) -> Option<Cow<'_, [u8]>> {
+        self.tables()
+            .token_bytes(idx)
+            .or_else(|| self.inverse_special_tokens.get(&idx).map(|t| t.as_bytes()))
+            .map(Cow::Borrowed)
     }
 
-    fn encode(&self, text: &str) -> Vec<Token> {
+    fn try_encode(&self, text: &str) -> Result<Vec<Token>> {
         // Forwarding to the default implementation provided by RegexTokenizerTrait
-        <Self as RegexTokenizerTrait>::encode(self, text)
+        <Self as RegexTokenizerTrait>::try_encode(self, text)
     }
 }
 
-impl Trainable for RegexTokenizerStruct {
-    fn train(&mut self, text: &str, vocab_size: Token, verbose: bool) {
-        assert!(vocab_size >= 256, "Vocab size must be at least 256");
-        let num_merges = vocab_size - 256;
+impl RegexTokenizerStruct {
+    /// Saves the result of training as instance variables. The special tokens are only replaced
+    /// if the training configuration declares some; the ones kept must not have the id of a
+    /// learned merge, or the tokenizer is left unchanged.
+    fn save_trained(
+        &mut self,
+        trained: Trained,
+        config: &TrainingConfig,
+    ) -> Result<TrainingOutcome> {
+        if config.special_tokens.is_empty() {
+            check_special_token_ids(&self.special_tokens, &trained.merges)?;
+        }
+        self.merges = trained.merges;
+        self.vocab = trained.vocab;
+        self.reset_tables();
+        if !config.special_tokens.is_empty() {
+            self.set_special_tokens(trained.special_tokens);
+        }
+        Ok(trained.outcome)
+    }
+}
 
+impl Trainable for RegexTokenizerStruct {
+    fn pre_tokenize(&self, text: &str, chunks: &mut ChunkCounts) -> Result<()> {
         // Split the text into chunks
-        let text_chunks: Vec<&str> = self
-            .compiled_pattern()
-            .find_iter(text)
-            .map(|m| {
-                let matched = m.unwrap();
-                &text[matched.start()..matched.end()]
-            })
-            .collect();
-
-        // Input text preprocessing
-        let mut ids: Vec<Vec<Token>> = text_chunks
-            .iter()
-            .map(|chunk| chunk.as_bytes().iter().map(|b| *b as Token).collect())
-            .collect();
-
-        // Iteratively merge the most common pairs to create new tokens
-        let mut merges: IndexMap<(Token, Token), Token> = IndexMap::new();
-        let mut vocab: IndexMap<Token, Vec<u8>> =
-            (0..256).map(|idx| (idx, vec![idx as u8])).collect();
-
-        for i in 0..num_merges {
-            // Count the number of times every consecutive pair appears
-            let mut stats = IndexMap::new();
-            for chunk_ids in &ids {
-                update_stats(chunk_ids, &mut stats);
-            }
-
-            // Find the pair with the highest count
-            let pair = get_max_entry(&stats).unwrap().0;
-
-            // Mint a new token: assign it the next available id
-            let idx = 256 + i;
-
-            // Replace all occurrences of pair in ids with idx
-            ids = ids
-                .iter()
-                .map(|chunk_ids| merge(chunk_ids, *pair, idx))
-                .collect();
-
-            // Save the merge
-            merges.insert(*pair, idx);
-            vocab.insert(
-                idx,
-                [vocab[&pair.0].clone(), vocab[&pair.1].clone()].concat(),
-            );
-
-            // Prints
-            if verbose {
-                println!(
-                    "merge {}/{}: {:?} -> {} ({:?}) had {} occurrences",
-                    i + 1,
-                    num_merges,
-                    pair,
-                    idx,
-                    vocab[&idx],
-                    stats[pair]
-                );
-            }
+        for m in self.compiled_pattern().find_iter(text) {
+            chunks.add(m?.as_str().as_bytes());
         }
+        Ok(())
+    }
 
-        // Save instance variables
-        self.merges = merges;
-        self.vocab = vocab; // FIXME: vs. build_vocab(&self.special_tokens, &self.merges);
+    fn train_chunks_with_config(
+        &mut self,
+        chunks: &ChunkCounts,
+        config: &TrainingConfig,
+        observer: &mut dyn TrainingObserver,
+    ) -> Result<TrainingOutcome> {
+        let trained = train_merges(chunks, config, &self.pattern, observer)?;
+        self.save_trained(trained, config)
+    }
+
+    fn resume_training(
+        &mut self,
+        checkpoint: &Path,
+        config: &TrainingConfig,
+        observer: &mut dyn TrainingObserver,
+    ) -> Result<TrainingOutcome> {
+        let trained = resume_merges(checkpoint, config, &self.pattern, observer)?;
+        self.save_trained(trained, config)
     }
 }
 
@@ -398,9 +618,10 @@ impl Saveable for RegexTokenizerStruct {
 }
 
 impl Loadable for RegexTokenizerStruct {
-    fn set_pattern(&mut self, pattern: &str) {
+    fn try_set_pattern(&mut self, pattern: &str) -> Result<()> {
+        self.compiled_pattern = Regex::new(pattern)?;
         self.pattern = pattern.to_string();
-        self.compiled_pattern = Regex::new(pattern).unwrap();
+        Ok(())
     }
 
     fn set_special_tokens(&mut self, special_tokens: IndexMap<String, Token>) {
@@ -413,10 +634,12 @@ impl Loadable for RegexTokenizerStruct {
 
     fn set_merges(&mut self, merges: IndexMap<(Token, Token), Token>) {
         self.merges = merges;
+        self.reset_tables();
     }
 
     fn set_vocab(&mut self, vocab: IndexMap<Token, Vec<u8>>) {
         self.vocab = vocab;
+        self.reset_tables();
     }
 }
 
@@ -425,6 +648,15 @@ impl RegexTokenizerTrait for RegexTokenizerStruct {
         &self.compiled_pattern
     }
 
+    fn tables(&self) -> &TokenTables {
+        self.tables
+            .get_or_init(|| TokenTables::new(&self.merges, &self.vocab))
+    }
+
+    fn chunk_cache(&self) -> Option<&ChunkCache> {
+        self.chunk_cache.as_ref()
+    }
+
     fn inverse_special_tokens(&self) -> &IndexMap<Token, String> {
         &self.inverse_special_tokens
     }
@@ -492,4 +724,32 @@ mod tests {
         // This should panic
         let _ = tokenizer.encode_special(text, AllowedSpecial::NoneRaise);
     }
+
+    #[test]
+    fn test_try_encode_special_raise() {
+        let mut tokenizer = RegexTokenizerStruct::default();
+        let text = "Hello, world! <|endofext|>";
+
+        let special_tokens = IndexMap::from([("<|endofext|>".to_string(), 100257)]);
+        tokenizer.set_special_tokens(special_tokens);
+
+        match tokenizer.try_encode_special(text, AllowedSpecial::NoneRaise) {
+            Err(MinbpeError::DisallowedSpecialToken(token)) => assert_eq!(token, "<|endofext|>"),
+            other => panic!("Expected DisallowedSpecialToken, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_try_new_invalid_regex() {
+        let result = RegexTokenizerStruct::try_new("(unclosed".to_string());
+        assert!(matches!(result, Err(MinbpeError::InvalidRegex(_))));
+    }
+
+    #[test]
+    fn test_try_decode_unknown_id() {
+        let mut tokenizer = RegexTokenizerStruct::default();
+        tokenizer.train("Hello, world!", 256 + 3, false);
+        let result = RegexTokenizerTrait::try_decode(&tokenizer, &[104, 105, 1000]);
+        assert!(matches!(result, Err(MinbpeError::UnknownTokenId(1000))));
+    }
 }
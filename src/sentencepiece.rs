@@ -0,0 +1,849 @@
+//! Contains `SentencePieceTokenizer`, which loads the BPE models of SentencePiece `.model`
+//! files, such as those of Llama 2 or Mistral, without the SentencePiece library.
+//!
+//! A `.model` file is a `ModelProto` protocol buffer, which is read field by field. Its pieces
+//! are text in which `▁` (U+2581) stands for a space, and they are ranked by a score rather
+//! than listed as merges: the normalized text is split into characters, and the adjacent pair
+//! whose concatenation is the piece with the highest score is merged, the leftmost on a tie,
+//! until no pair is a piece. A character that is no piece is encoded as its UTF-8 bytes with the
+//! `<0xNN>` byte pieces if the model has byte fallback, and as the unknown piece otherwise.
+
+use std::borrow::Cow;
+use std::cmp::Ordering;
+use std::collections::{BinaryHeap, HashMap};
+use std::fs;
+use std::io::Read;
+use std::path::Path;
+
+use indexmap::IndexMap;
+
+use crate::decode::bytes_to_string;
+use crate::{DecodeOptions, MinbpeError, Result, Token, Tokenizer, UnknownIdPolicy};
+
+const SPACE_SYMBOL: &str = "\u{2581}";
+
+fn invalid(message: impl Into<String>) -> MinbpeError {
+    MinbpeError::InvalidSentencePieceModel(message.into())
+}
+
+/// The value of a protocol buffer field, by wire type. Groups are not supported.
+enum Value<'a> {
+    Varint(u64),
+    Fixed64,
+    Bytes(&'a [u8]),
+    Fixed32(u32),
+}
+
+/// Reads the fields of an encoded protocol buffer message in order.
+struct ProtoReader<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> ProtoReader<'a> {
+    fn new(data: &'a [u8]) -> Self {
+        ProtoReader { data }
+    }
+
+    fn varint(&mut self) -> Result<u64> {
+        let mut value = 0;
+        for shift in (0..64).step_by(7) {
+            let (&byte, rest) = self
+                .data
+                .split_first()
+                .ok_or_else(|| invalid("truncated varint"))?;
+            self.data = rest;
+            value |= u64::from(byte & 0x7F) << shift;
+            if byte & 0x80 == 0 {
+                return Ok(value);
+            }
+        }
+        Err(invalid("varint longer than 10 bytes"))
+    }
+
+    fn take(&mut self, len: usize) -> Result<&'a [u8]> {
+        if len > self.data.len() {
+            return Err(invalid("truncated field"));
+        }
+        let (bytes, rest) = self.data.split_at(len);
+        self.data = rest;
+        Ok(bytes)
+    }
+
+    /// Returns the number and value of the next field, or `None` at the end of the message.
+    fn next_field(&mut self) -> Result<Option<(u64, Value<'a>)>> {
+        if self.data.is_empty() {
+            return Ok(None);
+        }
+        let key = self.varint()?;
+        let value = match key & 7 {
+            0 => Value::Varint(self.varint()?),
+            1 => {
+                self.take(8)?;
+                Value::Fixed64
+            }
+            2 => {
+                let len = usize::try_from(self.varint()?)
+                    .map_err(|_| invalid("field length does not fit in memory"))?;
+                Value::Bytes(self.take(len)?)
+            }
+            5 => Value::Fixed32(u32::from_le_bytes(self.take(4)?.try_into().unwrap())),
+            wire_type => return Err(invalid(format!("unsupported wire type {}", wire_type))),
+        };
+        Ok(Some((key >> 3, value)))
+    }
+}
+
+fn wrong_type(message_type: &str, field: u64) -> MinbpeError {
+    invalid(format!(
+        "{} field {} has the wrong wire type",
+        message_type, field
+    ))
+}
+
+/// The type of a piece, as in `SentencePiece.Type`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum PieceType {
+    Normal,
+    Unknown,
+    Control,
+    UserDefined,
+    Unused,
+    Byte,
+}
+
+impl PieceType {
+    fn from_proto(value: u64) -> Option<Self> {
+        match value {
+            1 => Some(PieceType::Normal),
+            2 => Some(PieceType::Unknown),
+            3 => Some(PieceType::Control),
+            4 => Some(PieceType::UserDefined),
+            5 => Some(PieceType::Unused),
+            6 => Some(PieceType::Byte),
+            _ => None,
+        }
+    }
+}
+
+struct Piece {
+    text: String,
+    score: f32,
+    kind: PieceType,
+}
+
+fn parse_piece(data: &[u8]) -> Result<Piece> {
+    let mut piece = Piece {
+        text: String::new(),
+        score: 0.0,
+        kind: PieceType::Normal,
+    };
+    let mut reader = ProtoReader::new(data);
+    while let Some((field, value)) = reader.next_field()? {
+        match (field, value) {
+            (1, Value::Bytes(text)) => {
+                piece.text = std::str::from_utf8(text)
+                    .map_err(|e| invalid(format!("piece is not UTF-8: {}", e)))?
+                    .to_string();
+            }
+            (2, Value::Fixed32(bits)) => piece.score = f32::from_bits(bits),
+            (3, Value::Varint(kind)) => {
+                piece.kind = PieceType::from_proto(kind)
+                    .ok_or_else(|| invalid(format!("unknown piece type {}", kind)))?;
+            }
+            (1..=3, _) => return Err(wrong_type("SentencePiece", field)),
+            _ => {}
+        }
+    }
+    Ok(piece)
+}
+
+/// The parts of a `ModelProto` the tokenizer reads; the others are skipped.
+struct ModelProto {
+    pieces: Vec<Piece>,
+    model_type: u64,
+    byte_fallback: bool,
+    treat_whitespace_as_suffix: bool,
+    normalizer_name: String,
+    precompiled_charsmap: bool,
+    add_dummy_prefix: bool,
+    remove_extra_whitespaces: bool,
+    escape_whitespaces: bool,
+}
+
+const MODEL_TYPE_BPE: u64 = 2;
+
+impl ModelProto {
+    fn parse(data: &[u8]) -> Result<Self> {
+        // The defaults of sentencepiece_model.proto
+        let mut model = ModelProto {
+            pieces: Vec::new(),
+            model_type: 1,
+            byte_fallback: false,
+            treat_whitespace_as_suffix: false,
+            normalizer_name: String::new(),
+            precompiled_charsmap: false,
+            add_dummy_prefix: true,
+            remove_extra_whitespaces: true,
+            escape_whitespaces: true,
+        };
+        let mut reader = ProtoReader::new(data);
+        while let Some((field, value)) = reader.next_field()? {
+            match (field, value) {
+                (1, Value::Bytes(piece)) => model.pieces.push(parse_piece(piece)?),
+                (2, Value::Bytes(trainer_spec)) => model.parse_trainer_spec(trainer_spec)?,
+                (3, Value::Bytes(normalizer_spec)) => {
+                    model.parse_normalizer_spec(normalizer_spec)?
+                }
+                (1..=3, _) => return Err(wrong_type("ModelProto", field)),
+                _ => {}
+            }
+        }
+        Ok(model)
+    }
+
+    fn parse_trainer_spec(&mut self, data: &[u8]) -> Result<()> {
+        let mut reader = ProtoReader::new(data);
+        while let Some((field, value)) = reader.next_field()? {
+            match (field, value) {
+                (3, Value::Varint(model_type)) => self.model_type = model_type,
+                (24, Value::Varint(suffix)) => self.treat_whitespace_as_suffix = suffix != 0,
+                (35, Value::Varint(byte_fallback)) => self.byte_fallback = byte_fallback != 0,
+                (3 | 24 | 35, _) => return Err(wrong_type("TrainerSpec", field)),
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+
+    fn parse_normalizer_spec(&mut self, data: &[u8]) -> Result<()> {
+        let mut reader = ProtoReader::new(data);
+        while let Some((field, value)) = reader.next_field()? {
+            match (field, value) {
+                (1, Value::Bytes(name)) => {
+                    self.normalizer_name = String::from_utf8_lossy(name).into_owned();
+                }
+                (2, Value::Bytes(charsmap)) => self.precompiled_charsmap = !charsmap.is_empty(),
+                (3, Value::Varint(flag)) => self.add_dummy_prefix = flag != 0,
+                (4, Value::Varint(flag)) => self.remove_extra_whitespaces = flag != 0,
+                (5, Value::Varint(flag)) => self.escape_whitespaces = flag != 0,
+                (1..=5, _) => return Err(wrong_type("NormalizerSpec", field)),
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Returns the byte of a byte piece such as `<0x0A>`.
+fn parse_byte_piece(text: &str) -> Option<u8> {
+    let hex = text.strip_prefix("<0x")?.strip_suffix('>')?;
+    if hex.len() != 2 {
+        return None;
+    }
+    u8::from_str_radix(hex, 16).ok()
+}
+
+/// A symbol of the text being segmented: the bytes `start..end`, which are empty once the
+/// symbol is merged into the one on its left.
+struct Symbol {
+    start: usize,
+    end: usize,
+    prev: Option<usize>,
+    next: Option<usize>,
+    /// Whether the symbol is a user-defined piece, which is never merged.
+    frozen: bool,
+}
+
+/// A pair of adjacent symbols whose concatenation, `len` bytes long, is a piece with `score`.
+/// The greatest candidate has the highest score, then the leftmost position.
+struct Candidate {
+    score: f32,
+    left: usize,
+    right: usize,
+    len: usize,
+}
+
+impl Ord for Candidate {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.score
+            .total_cmp(&other.score)
+            .then_with(|| other.left.cmp(&self.left))
+    }
+}
+
+impl PartialOrd for Candidate {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl PartialEq for Candidate {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Candidate {}
+
+/// The state of segmenting one normalized text, as in SentencePiece's `bpe::Model::Encode`.
+struct Segmentation<'t> {
+    text: &'t str,
+    symbols: Vec<Symbol>,
+    agenda: BinaryHeap<Candidate>,
+    /// The two pieces each unused piece was merged from, which it is split back into.
+    unused_splits: HashMap<&'t str, (&'t str, &'t str)>,
+}
+
+impl<'t> Segmentation<'t> {
+    fn piece(&self, idx: usize) -> &'t str {
+        &self.text[self.symbols[idx].start..self.symbols[idx].end]
+    }
+
+    fn push_pair(&mut self, tokenizer: &SentencePieceTokenizer, left: usize, right: usize) {
+        if self.symbols[left].frozen || self.symbols[right].frozen {
+            return;
+        }
+        let merged = &self.text[self.symbols[left].start..self.symbols[right].end];
+        let Some(&(idx, score)) = tokenizer.pieces.get(merged) else {
+            return;
+        };
+        self.agenda.push(Candidate {
+            score,
+            left,
+            right,
+            len: merged.len(),
+        });
+        if tokenizer.kinds[idx as usize] == PieceType::Unused {
+            self.unused_splits
+                .insert(merged, (self.piece(left), self.piece(right)));
+        }
+    }
+}
+
+/// Tokenizer for the BPE models of SentencePiece `.model` files.
+///
+/// The control pieces (e.g. `<s>` and `</s>`) and the unknown piece (`<unk>`) are the special
+/// tokens: they are never produced by encoding, and decode to their text. The normal,
+/// user-defined and unused pieces and the byte pieces form the vocabulary, with `▁` decoded as
+/// a space. Like SentencePiece, decoding drops the space the normalizer adds in front of the
+/// text (`add_dummy_prefix`) from the first token that is not special, so that decoding an
+/// encoded text returns it; `token_bytes` keeps it, and `token_bytes_at` drops it from the first
+/// token so that `StreamingDecoder` decodes as `decode` does. Only the `identity` normalization
+/// is supported, which only changes spaces: as in SentencePiece, other whitespace such as tabs
+/// and newlines is kept as it is.
+///
+/// The model has no merges, so `merges` is empty. Implements `Tokenizer` but not `Trainable`,
+/// `Saveable` or `Loadable`.
+///
+/// # Examples
+///
+/// ```no_run
+/// use minbpe::{SentencePieceTokenizer, Tokenizer};
+///
+/// let tokenizer = SentencePieceTokenizer::try_from_file("tokenizer.model").unwrap();
+/// let ids = tokenizer.encode("hello world");
+/// assert_eq!(tokenizer.decode(&ids), "hello world");
+/// ```
+pub struct SentencePieceTokenizer {
+    special_tokens: IndexMap<String, Token>,
+    inverse_special_tokens: IndexMap<Token, String>,
+    merges: IndexMap<(Token, Token), Token>,
+    vocab: IndexMap<Token, Vec<u8>>,
+
+    /// The type of each piece, by id.
+    kinds: Vec<PieceType>,
+    /// The id and score of the pieces symbols can merge into: the normal, user-defined and
+    /// unused pieces.
+    pieces: HashMap<String, (Token, f32)>,
+    /// The user-defined pieces, longest first. They are never split or merged.
+    user_defined: Vec<String>,
+    /// The id of each byte, if unknown characters fall back to bytes.
+    byte_ids: Option<[Token; 256]>,
+    unk_id: Token,
+
+    add_dummy_prefix: bool,
+    remove_extra_whitespaces: bool,
+    escape_whitespaces: bool,
+}
+
+impl SentencePieceTokenizer {
+    /// Creates a tokenizer from the contents of a `.model` file.
+    ///
+    /// # Errors
+    ///
+    /// Returns `MinbpeError::InvalidSentencePieceModel` if the protocol buffer is malformed, if
+    /// the model is not a BPE model, uses a normalization other than `identity` or a character
+    /// map, or treats whitespace as a suffix, if a piece is declared twice, if there is not
+    /// exactly one unknown piece, or if a byte piece is malformed or missing with byte fallback.
+    pub fn try_from_bytes(data: &[u8]) -> Result<Self> {
+        let model = ModelProto::parse(data)?;
+        if model.model_type != MODEL_TYPE_BPE {
+            return Err(invalid(format!(
+                "model type {} is not BPE",
+                model.model_type
+            )));
+        }
+        // Other normalizations map characters, such as tabs and newlines to spaces, with a
+        // precompiled character map, which is not supported
+        let identity = matches!(model.normalizer_name.as_str(), "" | "identity");
+        if !identity || model.precompiled_charsmap {
+            return Err(invalid(format!(
+                "unsupported normalization {:?}, only \"identity\" is supported",
+                model.normalizer_name
+            )));
+        }
+        if model.treat_whitespace_as_suffix {
+            return Err(invalid("whitespace as a suffix is not supported"));
+        }
+
+        let mut special_tokens = IndexMap::new();
+        let mut vocab = IndexMap::new();
+        let mut kinds = Vec::with_capacity(model.pieces.len());
+        let mut pieces = HashMap::new();
+        let mut user_defined = Vec::new();
+        let mut byte_ids = [None; 256];
+        let mut unk_id = None;
+        let mut seen = HashMap::new();
+        for (i, piece) in model.pieces.into_iter().enumerate() {
+            let idx = Token::try_from(i).map_err(|_| invalid("too many pieces"))?;
+            if seen.insert(piece.text.clone(), idx).is_some() {
+                return Err(invalid(format!("piece {:?} is declared twice", piece.text)));
+            }
+            kinds.push(piece.kind);
+            match piece.kind {
+                PieceType::Unknown | PieceType::Control => {
+                    if piece.kind == PieceType::Unknown && unk_id.replace(idx).is_some() {
+                        return Err(invalid("more than one unknown piece"));
+                    }
+                    special_tokens.insert(piece.text, idx);
+                }
+                PieceType::Byte => {
+                    let b = parse_byte_piece(&piece.text)
+                        .ok_or_else(|| invalid(format!("malformed byte piece {:?}", piece.text)))?;
+                    byte_ids[b as usize] = Some(idx);
+                    vocab.insert(idx, vec![b]);
+                }
+                PieceType::Normal | PieceType::UserDefined | PieceType::Unused => {
+                    vocab.insert(idx, piece.text.replace(SPACE_SYMBOL, " ").into_bytes());
+                    if piece.kind == PieceType::UserDefined {
+                        user_defined.push(piece.text.clone());
+                    }
+                    pieces.insert(piece.text, (idx, piece.score));
+                }
+            }
+        }
+        let unk_id = unk_id.ok_or_else(|| invalid("no unknown piece"))?;
+        user_defined.sort_by_key(|piece| std::cmp::Reverse(piece.len()));
+
+        let byte_ids = if model.byte_fallback {
+            let mut ids = [0; 256];
+            for (b, idx) in byte_ids.iter().enumerate() {
+                ids[b] =
+                    idx.ok_or_else(|| invalid(format!("byte piece <0x{:02X}> is missing", b)))?;
+            }
+            Some(ids)
+        } else {
+            None
+        };
+
+        Ok(SentencePieceTokenizer {
+            inverse_special_tokens: special_tokens
+                .iter()
+                .map(|(k, &v)| (v, k.clone()))
+                .collect(),
+            special_tokens,
+            merges: IndexMap::new(),
+            vocab,
+            kinds,
+            pieces,
+            user_defined,
+            byte_ids,
+            unk_id,
+            add_dummy_prefix: model.add_dummy_prefix,
+            remove_extra_whitespaces: model.remove_extra_whitespaces,
+            escape_whitespaces: model.escape_whitespaces,
+        })
+    }
+
+    /// Creates a tokenizer from a reader of a `.model` file.
+    ///
+    /// # Errors
+    ///
+    /// Returns `MinbpeError::Io` if reading fails, and the errors of `try_from_bytes`.
+    pub fn try_from_reader<R: Read>(mut reader: R) -> Result<Self> {
+        let mut data = Vec::new();
+        reader.read_to_end(&mut data)?;
+        Self::try_from_bytes(&data)
+    }
+
+    /// Creates a tokenizer from a `.model` file.
+    ///
+    /// # Errors
+    ///
+    /// Returns `MinbpeError::Io` if the file cannot be read, and the errors of `try_from_bytes`.
+    pub fn try_from_file<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let data = fs::read(path)?;
+        Self::try_from_bytes(&data)
+    }
+
+    /// Normalizes text like SentencePiece's `identity` normalization: optionally removes
+    /// leading, trailing and repeated spaces, adds a space in front and replaces the spaces
+    /// with `▁`.
+    fn normalize(&self, text: &str) -> String {
+        let space = if self.escape_whitespaces {
+            SPACE_SYMBOL
+        } else {
+            " "
+        };
+        let text = if self.remove_extra_whitespaces {
+            text.trim_start_matches(' ')
+        } else {
+            text
+        };
+        if text.is_empty() {
+            return String::new();
+        }
+
+        let mut normalized = String::with_capacity(text.len() + space.len());
+        if self.add_dummy_prefix {
+            normalized.push_str(space);
+        }
+        let mut prev_space = false;
+        for c in text.chars() {
+            if c == ' ' {
+                if !(prev_space && self.remove_extra_whitespaces) {
+                    normalized.push_str(space);
+                }
+                prev_space = true;
+            } else {
+                normalized.push(c);
+                prev_space = false;
+            }
+        }
+        if self.remove_extra_whitespaces {
+            while normalized.ends_with(space) {
+                normalized.truncate(normalized.len() - space.len());
+            }
+        }
+        normalized
+    }
+
+    /// Splits normalized text into characters and user-defined pieces, and merges them into
+    /// pieces by score.
+    fn segment<'t>(&self, text: &'t str) -> Segmentation<'t> {
+        let mut segmentation = Segmentation {
+            text,
+            symbols: Vec::new(),
+            agenda: BinaryHeap::new(),
+            unused_splits: HashMap::new(),
+        };
+        let symbols = &mut segmentation.symbols;
+        let mut start = 0;
+        while start < text.len() {
+            let rest = &text[start..];
+            let (len, frozen) = match self.user_defined.iter().find(|p| rest.starts_with(*p)) {
+                Some(piece) => (piece.len(), true),
+                None => (rest.chars().next().unwrap().len_utf8(), false),
+            };
+            let idx = symbols.len();
+            if let Some(prev) = idx.checked_sub(1) {
+                symbols[prev].next = Some(idx);
+            }
+            symbols.push(Symbol {
+                start,
+                end: start + len,
+                prev: idx.checked_sub(1),
+                next: None,
+                frozen,
+            });
+            start += len;
+        }
+
+        for right in 1..segmentation.symbols.len() {
+            segmentation.push_pair(self, right - 1, right);
+        }
+        while let Some(top) = segmentation.agenda.pop() {
+            let symbols = &mut segmentation.symbols;
+            let (left, right) = (&symbols[top.left], &symbols[top.right]);
+            // The candidate is stale if either symbol was merged since
+            if left.start == left.end
+                || right.start == right.end
+                || left.end - left.start + right.end - right.start != top.len
+            {
+                continue;
+            }
+            let next = right.next;
+            symbols[top.left].end = symbols[top.right].end;
+            symbols[top.left].next = next;
+            symbols[top.right].end = symbols[top.right].start;
+            if let Some(next) = next {
+                symbols[next].prev = Some(top.left);
+            }
+
+            if let Some(prev) = symbols[top.left].prev {
+                segmentation.push_pair(self, prev, top.left);
+            }
+            if let Some(next) = next {
+                segmentation.push_pair(self, top.left, next);
+            }
+        }
+        segmentation
+    }
+
+    /// Appends the ids of a segmented piece: an unused piece is split back into the pieces it
+    /// was merged from, and a piece that is not in the model falls back to bytes, or to the
+    /// unknown piece if it does not follow another unknown piece.
+    fn push_piece(
+        &self,
+        piece: &str,
+        segmentation: &Segmentation,
+        ids: &mut Vec<Token>,
+        prev_unknown: &mut bool,
+    ) {
+        if let Some(&(left, right)) = segmentation.unused_splits.get(piece) {
+            self.push_piece(left, segmentation, ids, prev_unknown);
+            self.push_piece(right, segmentation, ids, prev_unknown);
+            return;
+        }
+        match (self.pieces.get(piece), &self.byte_ids) {
+            (Some(&(idx, _)), _) => {
+                ids.push(idx);
+                *prev_unknown = false;
+            }
+            (None, Some(byte_ids)) => {
+                ids.extend(piece.bytes().map(|b| byte_ids[b as usize]));
+                *prev_unknown = false;
+            }
+            (None, None) => {
+                if !*prev_unknown {
+                    ids.push(self.unk_id);
+                }
+                *prev_unknown = true;
+            }
+        }
+    }
+
+    /// Returns the bytes of the vocabulary token `idx` without the dummy prefix space if it is
+    /// the first token that is not special. Byte pieces are kept as they are.
+    fn strip_dummy_prefix<'t>(&self, idx: Token, token: &'t [u8], first: bool) -> &'t [u8] {
+        match token.strip_prefix(b" ") {
+            Some(rest)
+                if first
+                    && self.add_dummy_prefix
+                    && self.kinds[idx as usize] != PieceType::Byte =>
+            {
+                rest
+            }
+            _ => token,
+        }
+    }
+
+    /// Concatenates the bytes of the tokens, dropping the dummy prefix space of the first token
+    /// that is not special, and special tokens if `skip_special`.
+    fn decode_pieces(
+        &self,
+        ids: &[Token],
+        skip_special: bool,
+        skip_unknown: bool,
+    ) -> Result<Vec<u8>> {
+        let mut bytes = Vec::new();
+        let mut first = true;
+        for &idx in ids {
+            if let Some(token) = self.inverse_special_tokens.get(&idx) {
+                if !skip_special {
+                    bytes.extend_from_slice(token.as_bytes());
+                }
+                continue;
+            }
+            let token = match self.vocab.get(&idx) {
+                Some(token) => self.strip_dummy_prefix(idx, token, first),
+                None if skip_unknown => continue,
+                None => return Err(MinbpeError::UnknownTokenId(idx)),
+            };
+            first = false;
+            bytes.extend_from_slice(token);
+        }
+        Ok(bytes)
+    }
+}
+
+impl Tokenizer for SentencePieceTokenizer {
+    fn special_tokens(&self) -> &IndexMap<String, Token> {
+        &self.special_tokens
+    }
+
+    /// Always empty, as SentencePiece ranks pieces by score rather than by merges.
+    fn merges(&self) -> &IndexMap<(Token, Token), Token> {
+        &self.merges
+    }
+
+    fn vocab(&self) -> &IndexMap<Token, Vec<u8>> {
+        &self.vocab
+    }
+
+    fn token_bytes(&self, idx: Token) -> Option<Cow<'_, [u8]>> {
+        self.vocab
+            .get(&idx)
+            .map(|bytes| bytes.as_slice())
+            .or_else(|| self.inverse_special_tokens.get(&idx).map(|t| t.as_bytes()))
+            .map(Cow::Borrowed)
+    }
+
+    /// Drops the dummy prefix space of the first token that is not special, as decoding does.
+    fn token_bytes_at(&self, idx: Token, first: bool) -> Option<Cow<'_, [u8]>> {
+        match self.vocab.get(&idx) {
+            Some(token) => Some(Cow::Borrowed(self.strip_dummy_prefix(idx, token, first))),
+            None => self.token_bytes(idx),
+        }
+    }
+
+    /// Encodes text like SentencePiece, without adding `<s>` or `</s>`. Never fails.
+    fn try_encode(&self, text: &str) -> Result<Vec<Token>> {
+        let normalized = self.normalize(text);
+        let segmentation = self.segment(&normalized);
+
+        let mut ids = Vec::new();
+        let mut prev_unknown = false;
+        let mut symbol = (!segmentation.symbols.is_empty()).then_some(0);
+        while let Some(idx) = symbol {
+            let piece = segmentation.piece(idx);
+            self.push_piece(piece, &segmentation, &mut ids, &mut prev_unknown);
+            symbol = segmentation.symbols[idx].next;
+        }
+        Ok(ids)
+    }
+
+    fn try_decode_bytes(&self, ids: &[Token]) -> Result<Vec<u8>> {
+        self.decode_pieces(ids, false, false)
+    }
+
+    fn try_decode_with(&self, ids: &[Token], options: &DecodeOptions) -> Result<String> {
+        let skip_unknown = options.unknown_ids == UnknownIdPolicy::Skip;
+        let bytes = self.decode_pieces(ids, options.skip_special_tokens, skip_unknown)?;
+        bytes_to_string(bytes, options.utf8)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn varint(mut n: u64) -> Vec<u8> {
+        let mut out = Vec::new();
+        while n >= 0x80 {
+            out.push(n as u8 | 0x80);
+            n >>= 7;
+        }
+        out.push(n as u8);
+        out
+    }
+
+    fn field(number: u64, data: &[u8]) -> Vec<u8> {
+        [
+            varint(number << 3 | 2),
+            varint(data.len() as u64),
+            data.to_vec(),
+        ]
+        .concat()
+    }
+
+    fn flag(number: u64, value: u64) -> Vec<u8> {
+        [varint(number << 3), varint(value)].concat()
+    }
+
+    /// Encodes a model with the given pieces, the BPE model type and no byte fallback.
+    fn model(pieces: &[(&str, f32, u64)], normalizer: &[u8]) -> Vec<u8> {
+        let mut data = Vec::new();
+        for &(text, score, kind) in pieces {
+            let mut piece = field(1, text.as_bytes());
+            piece.push(2 << 3 | 5);
+            piece.extend_from_slice(&score.to_le_bytes());
+            piece.extend(flag(3, kind));
+            data.extend(field(1, &piece));
+        }
+        data.extend(field(2, &flag(3, MODEL_TYPE_BPE)));
+        data.extend(field(3, normalizer));
+        data
+    }
+
+    const PIECES: &[(&str, f32, u64)] = &[
+        ("<unk>", 0.0, 2),
+        ("▁", -1.0, 1),
+        ("a", -2.0, 1),
+        ("b", -3.0, 1),
+        ("▁a", 0.0, 1),
+    ];
+
+    #[test]
+    fn test_proto_reader() {
+        let data = [field(1, b"hi"), flag(300, 150), vec![0x15, 1, 2, 3, 4]].concat();
+        let mut reader = ProtoReader::new(&data);
+        assert!(matches!(
+            reader.next_field(),
+            Ok(Some((1, Value::Bytes(b"hi"))))
+        ));
+        assert!(matches!(
+            reader.next_field(),
+            Ok(Some((300, Value::Varint(150))))
+        ));
+        assert!(matches!(
+            reader.next_field(),
+            Ok(Some((2, Value::Fixed32(0x04030201))))
+        ));
+        assert!(matches!(reader.next_field(), Ok(None)));
+
+        // truncated, and a group
+        assert!(ProtoReader::new(&[0x0A, 3, b'h']).next_field().is_err());
+        assert!(ProtoReader::new(&[0x0B]).next_field().is_err());
+        assert_eq!(parse_byte_piece("<0x0A>"), Some(b'\n'));
+        assert_eq!(parse_byte_piece("<0x0A0>"), None);
+    }
+
+    #[test]
+    fn test_normalize_and_unknown() {
+        // The default normalizer spec removes extra whitespaces
+        let tokenizer = SentencePieceTokenizer::try_from_bytes(&model(PIECES, &[])).unwrap();
+        assert_eq!(tokenizer.normalize("  a  b "), "▁a▁b");
+        assert_eq!(tokenizer.normalize("   "), "");
+        // Only spaces are whitespace to the identity normalization
+        assert_eq!(tokenizer.normalize("\ta\n b "), "▁\ta\n▁b");
+        assert_eq!(tokenizer.encode("  a  b "), [4, 1, 3]);
+
+        // Without byte fallback, consecutive unknown characters are one unknown piece
+        assert_eq!(tokenizer.encode("axyb"), [4, 0, 3]);
+        assert_eq!(tokenizer.decode(&[4, 0, 3]), "a<unk>b");
+
+        let normalizer = [flag(3, 0), flag(4, 0), flag(5, 0)].concat();
+        let tokenizer =
+            SentencePieceTokenizer::try_from_bytes(&model(PIECES, &normalizer)).unwrap();
+        assert_eq!(tokenizer.normalize(" a  b "), " a  b ");
+        assert_eq!(tokenizer.decode(&[2, 1]), "a ");
+    }
+
+    #[test]
+    fn test_unsupported_models() {
+        let nfkc = [field(1, b"nmt_nfkc"), field(2, &[1, 2, 3])].concat();
+        assert!(matches!(
+            SentencePieceTokenizer::try_from_bytes(&model(PIECES, &nfkc)),
+            Err(MinbpeError::InvalidSentencePieceModel(_))
+        ));
+        // A normalization other than identity is rejected even without a character map
+        let nfkc = field(1, b"nmt_nfkc");
+        assert!(matches!(
+            SentencePieceTokenizer::try_from_bytes(&model(PIECES, &nfkc)),
+            Err(MinbpeError::InvalidSentencePieceModel(message)) if message.contains("identity")
+        ));
+        let identity = field(1, b"identity");
+        assert!(SentencePieceTokenizer::try_from_bytes(&model(PIECES, &identity)).is_ok());
+
+        let unigram = [model(PIECES, &[]), field(2, &flag(3, 1))].concat();
+        assert!(SentencePieceTokenizer::try_from_bytes(&unigram).is_err());
+
+        let byte_fallback = [model(PIECES, &[]), field(2, &flag(35, 1))].concat();
+        assert!(SentencePieceTokenizer::try_from_bytes(&byte_fallback).is_err());
+
+        assert!(SentencePieceTokenizer::try_from_bytes(&model(&PIECES[1..], &[])).is_err());
+        let twice = [PIECES, &PIECES[1..2]].concat();
+        assert!(SentencePieceTokenizer::try_from_bytes(&model(&twice, &[])).is_err());
+    }
+}
@@ -0,0 +1,125 @@
+//! Contains `StreamingDecoder`, which decodes tokens one at a time as they are generated.
+
+use crate::{MinbpeError, Result, Token, Tokenizer};
+
+/// Decodes a stream of tokens into text incrementally, e.g. while a language model generates
+/// them.
+///
+/// Decoding tokens one at a time with `decode` produces replacement characters whenever a token
+/// ends in the middle of a multi-byte UTF-8 character. The streaming decoder instead buffers the
+/// bytes of an incomplete character until the tokens completing it arrive, so it only ever
+/// returns complete text. Bytes that can never become valid UTF-8 are replaced with U+FFFD, as
+/// `decode` does.
+///
+/// # Examples
+///
+/// ```
+/// use minbpe::{BasicTokenizer, StreamingDecoder, Trainable};
+///
+/// let mut tokenizer = BasicTokenizer::new();
+/// tokenizer.train("hello world", 256 + 4, false);
+///
+/// // "é" is encoded as the two byte tokens 0xC3 and 0xA9
+/// let mut decoder = StreamingDecoder::new(&tokenizer);
+/// assert_eq!(decoder.push(0xC3), "");
+/// assert_eq!(decoder.push(0xA9), "é");
+/// assert_eq!(decoder.push(0xC3), "");
+/// assert_eq!(decoder.flush(), "\u{FFFD}");
+/// ```
+pub struct StreamingDecoder<'a, T: Tokenizer + ?Sized> {
+    tokenizer: &'a T,
+
+    /// The bytes of an incomplete character at the end of the stream so far.
+    pending: Vec<u8>,
+
+    /// Whether no token other than the special tokens was pushed yet, see
+    /// `Tokenizer::token_bytes_at`.
+    first: bool,
+}
+
+impl<'a, T: Tokenizer + ?Sized> StreamingDecoder<'a, T> {
+    pub fn new(tokenizer: &'a T) -> Self {
+        StreamingDecoder {
+            tokenizer,
+            pending: Vec::new(),
+            first: true,
+        }
+    }
+
+    /// Adds a token to the stream, returning the text it completes, which may be empty.
+    ///
+    /// # Errors
+    ///
+    /// Returns `MinbpeError::UnknownTokenId` if the tokenizer has no token `idx`. The stream is
+    /// left unchanged.
+    pub fn try_push(&mut self, idx: Token) -> Result<String> {
+        let bytes = self
+            .tokenizer
+            .token_bytes_at(idx, self.first)
+            .ok_or(MinbpeError::UnknownTokenId(idx))?;
+        self.pending.extend_from_slice(&bytes);
+        if self.first
+            && !self
+                .tokenizer
+                .special_tokens()
+                .values()
+                .any(|&id| id == idx)
+        {
+            self.first = false;
+        }
+
+        let mut text = String::new();
+        let mut rest = &self.pending[..];
+        loop {
+            match std::str::from_utf8(rest) {
+                Ok(valid) => {
+                    text.push_str(valid);
+                    rest = &[];
+                    break;
+                }
+                Err(e) => {
+                    let (valid, after) = rest.split_at(e.valid_up_to());
+                    // `valid_up_to` guarantees that the prefix is valid UTF-8
+                    text.push_str(std::str::from_utf8(valid).unwrap());
+                    match e.error_len() {
+                        // An invalid sequence, which no later byte can complete
+                        Some(len) => {
+                            text.push(char::REPLACEMENT_CHARACTER);
+                            rest = &after[len..];
+                        }
+                        // An incomplete character at the end, kept until more bytes arrive
+                        None => {
+                            rest = after;
+                            break;
+                        }
+                    }
+                }
+            }
+        }
+        self.pending = rest.to_vec();
+        Ok(text)
+    }
+
+    /// Adds a token to the stream, see `try_push`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `try_push` returns an error, i.e. on an unknown token id.
+    pub fn push(&mut self, idx: Token) -> String {
+        self.try_push(idx).unwrap_or_else(|e| panic!("{}", e))
+    }
+
+    /// Ends the stream, returning the bytes of an incomplete character, if any, as U+FFFD. The
+    /// decoder can then start a new stream.
+    pub fn flush(&mut self) -> String {
+        let text = String::from_utf8_lossy(&self.pending).into_owned();
+        self.pending.clear();
+        self.first = true;
+        text
+    }
+
+    /// Returns whether bytes of an incomplete character are waiting for the next tokens.
+    pub fn has_pending(&self) -> bool {
+        !self.pending.is_empty()
+    }
+}
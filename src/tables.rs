@@ -0,0 +1,130 @@
+//! Contains the compiled, read-only lookup tables that the encode and decode paths use instead
+//! of the `IndexMap`s of a tokenizer.
+//!
+//! The `IndexMap`s remain the source of truth (they are what is trained, saved, loaded and
+//! returned by `Tokenizer::merges` and `Tokenizer::vocab`), and the tables are rebuilt from them
+//! after they change.
+
+use indexmap::IndexMap;
+use rustc_hash::FxHashMap;
+
+use crate::bpe::apply_merges;
+use crate::Token;
+
+/// Token ids above this many times the vocabulary size are looked up in a hash map rather than
+/// indexed, so that a few large special token ids do not blow up the dense table.
+const DENSE_FACTOR: usize = 2;
+
+/// Compiled lookup tables for the merges and vocabulary of a tokenizer.
+#[derive(Debug, Clone, Default)]
+pub struct TokenTables {
+    /// The id each pair merges into, which is also its rank.
+    merges: FxHashMap<(Token, Token), Token>,
+
+    /// The bytes of each token, indexed by id.
+    dense: Vec<Option<Box<[u8]>>>,
+
+    /// The bytes of the tokens whose ids are negative or too large to index.
+    sparse: FxHashMap<Token, Box<[u8]>>,
+
+    /// The id of every token that merging its own bytes produces, so that a chunk which is a
+    /// whole token is encoded without merging.
+    whole: FxHashMap<Box<[u8]>, Token>,
+}
+
+impl TokenTables {
+    /// Compiles the tables for `merges` and `vocab`.
+    pub fn new(merges: &IndexMap<(Token, Token), Token>, vocab: &IndexMap<Token, Vec<u8>>) -> Self {
+        let mut tables = TokenTables {
+            merges: merges.iter().map(|(&pair, &idx)| (pair, idx)).collect(),
+            ..Default::default()
+        };
+
+        let dense_len = vocab.len() * DENSE_FACTOR + 256;
+        for (&idx, bytes) in vocab {
+            let bytes: Box<[u8]> = bytes.as_slice().into();
+            match usize::try_from(idx) {
+                Ok(i) if i < dense_len => {
+                    if tables.dense.len() <= i {
+                        tables.dense.resize(i + 1, None);
+                    }
+                    tables.dense[i] = Some(bytes);
+                }
+                _ => {
+                    tables.sparse.insert(idx, bytes);
+                }
+            }
+        }
+
+        // Only tokens that encoding would produce from their bytes can short-circuit encoding:
+        // e.g. a special token, or a token not reachable through the merges, cannot.
+        let mut whole = FxHashMap::default();
+        for (&idx, bytes) in vocab {
+            if bytes.is_empty() || whole.contains_key(bytes.as_slice()) {
+                continue;
+            }
+            if tables.encode_with_merges(bytes) == [idx] {
+                whole.insert(bytes.as_slice().into(), idx);
+            }
+        }
+        tables.whole = whole;
+
+        tables
+    }
+
+    /// Returns the id `pair` merges into, if any.
+    pub fn merge(&self, pair: (Token, Token)) -> Option<Token> {
+        self.merges.get(&pair).copied()
+    }
+
+    /// Returns the bytes of token `idx`, if it is in the vocabulary.
+    pub fn token_bytes(&self, idx: Token) -> Option<&[u8]> {
+        match usize::try_from(idx) {
+            Ok(i) if i < self.dense.len() => self.dense[i].as_deref(),
+            _ => self.sparse.get(&idx).map(|bytes| &bytes[..]),
+        }
+    }
+
+    /// Encodes the bytes of a chunk by applying the merges, lowest rank first.
+    pub fn encode_bytes(&self, bytes: &[u8]) -> Vec<Token> {
+        if let Some(&idx) = self.whole.get(bytes) {
+            return vec![idx];
+        }
+        self.encode_with_merges(bytes)
+    }
+
+    fn encode_with_merges(&self, bytes: &[u8]) -> Vec<Token> {
+        let ids: Vec<Token> = bytes.iter().map(|&b| b as Token).collect();
+        apply_merges(&ids, |pair| self.merge(pair))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::build_vocab;
+
+    #[test]
+    fn test_tables() {
+        let merges = IndexMap::from([((97, 97), 256), ((256, 97), 257), ((97, 98), 258)]);
+        let special_tokens = IndexMap::from([("<|end|>".to_string(), 100257)]);
+        let mut vocab = build_vocab(&special_tokens, &merges);
+        vocab.insert(-1, b"negative".to_vec());
+        // A token the merges cannot produce
+        vocab.insert(300, b"ba".to_vec());
+        let tables = TokenTables::new(&merges, &vocab);
+
+        assert_eq!(tables.token_bytes(97), Some(&b"a"[..]));
+        assert_eq!(tables.token_bytes(257), Some(&b"aaa"[..]));
+        assert_eq!(tables.token_bytes(100257), Some(&b"<|end|>"[..]));
+        assert_eq!(tables.token_bytes(-1), Some(&b"negative"[..]));
+        assert_eq!(tables.token_bytes(259), None);
+
+        assert_eq!(tables.encode_bytes(b"aaab"), [257, 98]);
+        assert_eq!(tables.encode_bytes(b"ab"), [258]);
+        assert_eq!(tables.encode_bytes(b"<|end|>").len(), 7);
+        assert!(tables.whole.contains_key(&b"aa"[..]));
+        assert!(!tables.whole.contains_key(&b"<|end|>"[..]));
+        assert_eq!(tables.encode_bytes(b"ba"), [98, 97]);
+    }
+}
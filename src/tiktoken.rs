@@ -0,0 +1,810 @@
+//! Contains `TiktokenTokenizer`, a byte-level BPE tokenizer for vocabularies in the tiktoken
+//! format: a rank file listing every token (base64-encoded) with its rank, a split pattern and a
+//! map of special tokens.
+//!
+//! The rank file does not list the merges, so they are recovered by running BPE on each token
+//! with the tokens of lower rank. Tokens are identified by their ranks, which makes the ids of
+//! the single bytes a permutation of 0..=255: bytes are shuffled by that permutation before
+//! encoding and unshuffled after decoding.
+//!
+//! It also contains the writer behind `Tokenizer::save_tiktoken`, which saves a vocabulary as a
+//! rank file with a JSON sidecar holding the split pattern and the special tokens, and the
+//! `tiktoken_preset!` macro that defines the bundled tokenizers such as `GPT4Tokenizer`.
+
+use std::borrow::Cow;
+use std::collections::HashSet;
+use std::fs;
+use std::io::{Read, Write};
+use std::num::NonZeroUsize;
+use std::path::Path;
+
+use base64::{engine::general_purpose, Engine as _};
+use fancy_regex::Regex;
+use indexmap::IndexMap;
+use serde::{Deserialize, Serialize};
+
+use crate::base::check_special_token_ids;
+use crate::{ChunkCache, MinbpeError, RegexTokenizerTrait, Result, Token, TokenTables, Tokenizer};
+
+fn malformed_ranks(line: usize, message: &str) -> MinbpeError {
+    MinbpeError::MalformedRanks {
+        line,
+        message: message.to_string(),
+    }
+}
+
+/// Parses the contents of a tiktoken rank file, with one `<base64 token> <rank>` line per token.
+/// Empty lines are ignored.
+///
+/// # Errors
+///
+/// Returns `MinbpeError::MalformedRanks` if a line cannot be parsed, or if a token or a rank
+/// appears twice.
+pub fn parse_tiktoken_ranks(data: &[u8]) -> Result<IndexMap<Vec<u8>, Token>> {
+    let mut ranks = IndexMap::new();
+    let mut seen = HashSet::new();
+    for (i, line) in data.split(|&b| b == b'\n').enumerate() {
+        let line_number = i + 1;
+        let line = line.strip_suffix(b"\r").unwrap_or(line);
+        if line.is_empty() {
+            continue;
+        }
+        let line = std::str::from_utf8(line)
+            .map_err(|_| malformed_ranks(line_number, "line is not valid UTF-8"))?;
+        let (token, rank) = line
+            .split_once(' ')
+            .ok_or_else(|| malformed_ranks(line_number, "expected a token and a rank"))?;
+        let token = general_purpose::STANDARD
+            .decode(token)
+            .map_err(|e| malformed_ranks(line_number, &format!("invalid base64: {}", e)))?;
+        let rank: Token = rank
+            .parse()
+            .ok()
+            .filter(|&rank| rank >= 0)
+            .ok_or_else(|| malformed_ranks(line_number, &format!("invalid rank {:?}", rank)))?;
+        if !seen.insert(rank) {
+            return Err(malformed_ranks(
+                line_number,
+                &format!("rank {} appears twice", rank),
+            ));
+        }
+        if ranks.insert(token, rank).is_some() {
+            return Err(malformed_ranks(line_number, "token appears twice"));
+        }
+    }
+    Ok(ranks)
+}
+
+/// Writes `vocab` as a tiktoken rank file, each token with its id as its rank, in order of id.
+/// The special tokens are left out, as tiktoken lists them separately.
+pub(crate) fn write_tiktoken_ranks<W: Write>(
+    mut writer: W,
+    vocab: &IndexMap<Token, Vec<u8>>,
+    special_tokens: &IndexMap<String, Token>,
+) -> Result<()> {
+    let is_special: HashSet<Token> = special_tokens.values().copied().collect();
+    let mut tokens: Vec<(Token, &Vec<u8>)> = vocab
+        .iter()
+        .filter(|(idx, _)| !is_special.contains(idx))
+        .map(|(&idx, bytes)| (idx, bytes))
+        .collect();
+    tokens.sort_by_key(|&(idx, _)| idx);
+
+    if let Some(&(idx, _)) = tokens.first().filter(|&&(idx, _)| idx < 0) {
+        return Err(malformed_ranks(0, &format!("invalid rank {}", idx)));
+    }
+    // tiktoken requires the bytes to have the ranks 0 to 255
+    let bytes_first = tokens.len() >= 256
+        && tokens[..256]
+            .iter()
+            .enumerate()
+            .all(|(i, &(idx, bytes))| idx == i as Token && bytes.len() == 1);
+    if !bytes_first {
+        return Err(malformed_ranks(
+            0,
+            "the ids 0 to 255 are not those of single bytes",
+        ));
+    }
+
+    let mut seen = HashSet::new();
+    for (idx, bytes) in tokens {
+        if !seen.insert(bytes) {
+            return Err(malformed_ranks(
+                0,
+                &format!("token of rank {} appears twice", idx),
+            ));
+        }
+        writeln!(
+            writer,
+            "{} {}",
+            general_purpose::STANDARD.encode(bytes),
+            idx
+        )?;
+    }
+    Ok(())
+}
+
+/// Saves `tokenizer` in the format of tiktoken, see `Tokenizer::try_save_tiktoken`.
+pub(crate) fn save_tiktoken<T: Tokenizer + ?Sized>(
+    tokenizer: &T,
+    dir: &Path,
+    prefix: &str,
+) -> Result<()> {
+    let vocab: IndexMap<Token, Vec<u8>> = tokenizer
+        .vocab()
+        .keys()
+        .map(|&idx| {
+            (
+                idx,
+                tokenizer.token_bytes(idx).unwrap_or_default().into_owned(),
+            )
+        })
+        .collect();
+    let mut ranks_file =
+        std::io::BufWriter::new(fs::File::create(dir.join(format!("{}.tiktoken", prefix)))?);
+    write_tiktoken_ranks(&mut ranks_file, &vocab, tokenizer.special_tokens())?;
+    ranks_file.flush()?;
+
+    let pattern = match tokenizer.split_pattern() {
+        "" => crate::regex::WHOLE_TEXT_PATTERN,
+        pattern => pattern,
+    };
+    let sidecar = TiktokenSidecar {
+        name: prefix.to_string(),
+        pat_str: pattern.to_string(),
+        special_tokens: tokenizer.special_tokens().clone(),
+    };
+    let mut sidecar_file = fs::File::create(dir.join(format!("{}.json", prefix)))?;
+    serde_json::to_writer_pretty(&mut sidecar_file, &sidecar).map_err(std::io::Error::from)?;
+    writeln!(sidecar_file)?;
+    Ok(())
+}
+
+/// The sidecar of a rank file saved by `Tokenizer::save_tiktoken`. The fields are named after
+/// the arguments of tiktoken's `Encoding`, which takes them along with the ranks.
+#[derive(Serialize, Deserialize)]
+pub(crate) struct TiktokenSidecar {
+    pub(crate) name: String,
+    pub(crate) pat_str: String,
+    pub(crate) special_tokens: IndexMap<String, Token>,
+}
+
+/// Splits `token` into the parts that BPE with the tokens of rank below `max_rank` produces.
+fn bpe(mergeable_ranks: &IndexMap<Vec<u8>, Token>, token: &[u8], max_rank: Token) -> Vec<Vec<u8>> {
+    let mut parts: Vec<Vec<u8>> = token.iter().map(|&b| vec![b]).collect();
+    loop {
+        let mut min_idx = None;
+        let mut min_rank = None;
+        for (i, pair) in parts.windows(2).enumerate() {
+            let rank = mergeable_ranks.get(&[pair[0].clone(), pair[1].clone()].concat());
+            if let Some(&rank) = rank {
+                if min_rank.is_none() || Some(rank) < min_rank {
+                    min_idx = Some(i);
+                    min_rank = Some(rank);
+                }
+            }
+        }
+        let Some(min_rank) = min_rank else {
+            break;
+        };
+        if min_rank >= max_rank {
+            break;
+        }
+        let min_idx = min_idx.unwrap();
+        parts[min_idx] = [parts[min_idx].clone(), parts[min_idx + 1].clone()].concat();
+        parts.remove(min_idx + 1);
+    }
+    parts
+}
+
+/// Recovers the merge producing each multi-byte token, in order of rank.
+fn recover_merges(
+    mergeable_ranks: &IndexMap<Vec<u8>, Token>,
+) -> Result<IndexMap<(Token, Token), Token>> {
+    let mut tokens: Vec<(&Vec<u8>, Token)> = mergeable_ranks
+        .iter()
+        .filter(|(token, _)| token.len() > 1)
+        .map(|(token, &rank)| (token, rank))
+        .collect();
+    tokens.sort_by_key(|&(_, rank)| rank);
+
+    let mut merges = IndexMap::new();
+    for (token, rank) in tokens {
+        let pair = bpe(mergeable_ranks, token, rank);
+        if pair.len() != 2 {
+            return Err(malformed_ranks(
+                0,
+                &format!("token of rank {} is not the merge of two tokens", rank),
+            ));
+        }
+        let ix0 = mergeable_ranks[&pair[0]];
+        let ix1 = mergeable_ranks[&pair[1]];
+        merges.insert((ix0, ix1), rank);
+    }
+    Ok(merges)
+}
+
+/// Tokenizer for vocabularies in the tiktoken format, such as OpenAI's `cl100k_base`.
+///
+/// Implements `Tokenizer` and `RegexTokenizerTrait` but not `Trainable`, `Saveable` or
+/// `Loadable`.
+///
+/// # Examples
+///
+/// ```
+/// use base64::{engine::general_purpose::STANDARD, Engine as _};
+/// use indexmap::IndexMap;
+/// use minbpe::regex::GPT2_SPLIT_PATTERN;
+/// use minbpe::{RegexTokenizerTrait, TiktokenTokenizer};
+///
+/// // The 256 bytes, then "ab" and "abc"
+/// let mut ranks = String::new();
+/// for b in 0..=255u8 {
+///     ranks += &format!("{} {}\n", STANDARD.encode([b]), b);
+/// }
+/// ranks += &format!("{} 256\n{} 257\n", STANDARD.encode("ab"), STANDARD.encode("abc"));
+///
+/// let special_tokens = IndexMap::from([("<|endoftext|>".to_string(), 258)]);
+/// let tokenizer =
+///     TiktokenTokenizer::try_from_bytes(ranks.as_bytes(), GPT2_SPLIT_PATTERN, special_tokens)
+///         .unwrap();
+/// assert_eq!(tokenizer.encode_ordinary("abcab"), [257, 256]);
+/// assert_eq!(tokenizer.decode(&[257, 256, 258]), "abcab<|endoftext|>");
+/// ```
+pub struct TiktokenTokenizer {
+    pattern: String,
+    compiled_pattern: Regex,
+    special_tokens: IndexMap<String, Token>,
+    inverse_special_tokens: IndexMap<Token, String>,
+    merges: IndexMap<(Token, Token), Token>,
+    vocab: IndexMap<Token, Vec<u8>>,
+
+    tables: TokenTables,
+    /// The bytes of the tokens as decoded, i.e. with the byte shuffle undone. It has no merges.
+    decode_tables: TokenTables,
+
+    byte_shuffle: [u8; 256],
+
+    chunk_cache: Option<ChunkCache>,
+}
+
+impl TiktokenTokenizer {
+    /// Creates a tokenizer from the ranks of its tokens, as returned by `parse_tiktoken_ranks`.
+    ///
+    /// # Errors
+    ///
+    /// Returns `MinbpeError::MalformedRanks` if the 256 bytes do not have the ranks 0 to 255 or a
+    /// token is not the merge of two tokens of lower rank, `MinbpeError::InvalidRegex` if
+    /// `pattern` is not a valid regex, and `MinbpeError::SpecialTokenIdCollision` if a special
+    /// token has the rank of a token or the id of another special token.
+    pub fn try_new(
+        mergeable_ranks: &IndexMap<Vec<u8>, Token>,
+        pattern: &str,
+        special_tokens: IndexMap<String, Token>,
+    ) -> Result<Self> {
+        let compiled_pattern = Regex::new(pattern)?;
+
+        let mut byte_shuffle = [0u8; 256];
+        let mut seen = [false; 256];
+        for b in 0..=255u8 {
+            let rank = mergeable_ranks
+                .get(&vec![b])
+                .copied()
+                .filter(|&rank| rank <= u8::MAX as Token)
+                .ok_or_else(|| {
+                    malformed_ranks(0, &format!("byte {} does not have a rank below 256", b))
+                })?;
+            if std::mem::replace(&mut seen[rank as usize], true) {
+                return Err(malformed_ranks(
+                    0,
+                    &format!("rank {} is the rank of two bytes", rank),
+                ));
+            }
+            byte_shuffle[b as usize] = rank as u8;
+        }
+
+        let merges = recover_merges(mergeable_ranks)?;
+        let mut vocab = IndexMap::new();
+        let mut decoded_vocab = IndexMap::new();
+        let mut ranked: Vec<(&Vec<u8>, Token)> = mergeable_ranks
+            .iter()
+            .map(|(token, &rank)| (token, rank))
+            .collect();
+        ranked.sort_by_key(|&(_, rank)| rank);
+        for (token, rank) in ranked {
+            let shuffled = token.iter().map(|&b| byte_shuffle[b as usize]).collect();
+            if vocab.insert(rank, shuffled).is_some() {
+                return Err(malformed_ranks(
+                    0,
+                    &format!("rank {} is the rank of two tokens", rank),
+                ));
+            }
+            decoded_vocab.insert(rank, token.clone());
+        }
+
+        // Every rank other than those of the bytes is the id of a merge
+        check_special_token_ids(&special_tokens, &merges)?;
+        let inverse_special_tokens = special_tokens
+            .iter()
+            .map(|(k, &v)| (v, k.clone()))
+            .collect();
+
+        Ok(TiktokenTokenizer {
+            pattern: pattern.to_string(),
+            compiled_pattern,
+            special_tokens,
+            inverse_special_tokens,
+            tables: TokenTables::new(&merges, &vocab),
+            decode_tables: TokenTables::new(&IndexMap::new(), &decoded_vocab),
+            merges,
+            vocab,
+
+            byte_shuffle,
+
+            chunk_cache: None,
+        })
+    }
+
+    /// Creates a tokenizer from the ranks of its tokens, see `try_new`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `try_new` returns an error.
+    pub fn new(
+        mergeable_ranks: &IndexMap<Vec<u8>, Token>,
+        pattern: &str,
+        special_tokens: IndexMap<String, Token>,
+    ) -> Self {
+        Self::try_new(mergeable_ranks, pattern, special_tokens).unwrap_or_else(|e| panic!("{}", e))
+    }
+
+    /// Creates a tokenizer from the contents of a tiktoken rank file.
+    ///
+    /// # Errors
+    ///
+    /// Returns the errors of `parse_tiktoken_ranks` and `try_new`.
+    pub fn try_from_bytes(
+        data: &[u8],
+        pattern: &str,
+        special_tokens: IndexMap<String, Token>,
+    ) -> Result<Self> {
+        let mergeable_ranks = parse_tiktoken_ranks(data)?;
+        Self::try_new(&mergeable_ranks, pattern, special_tokens)
+    }
+
+    /// Creates a tokenizer from a reader of a tiktoken rank file.
+    ///
+    /// # Errors
+    ///
+    /// Returns `MinbpeError::Io` if reading fails, and the errors of `try_from_bytes`.
+    pub fn try_from_reader<R: Read>(
+        mut reader: R,
+        pattern: &str,
+        special_tokens: IndexMap<String, Token>,
+    ) -> Result<Self> {
+        let mut data = Vec::new();
+        reader.read_to_end(&mut data)?;
+        Self::try_from_bytes(&data, pattern, special_tokens)
+    }
+
+    /// Creates a tokenizer from a tiktoken rank file.
+    ///
+    /// # Errors
+    ///
+    /// Returns `MinbpeError::Io` if the file cannot be read, and the errors of `try_from_bytes`.
+    pub fn try_from_file<P: AsRef<Path>>(
+        path: P,
+        pattern: &str,
+        special_tokens: IndexMap<String, Token>,
+    ) -> Result<Self> {
+        let data = fs::read(path)?;
+        Self::try_from_bytes(&data, pattern, special_tokens)
+    }
+
+    /// Creates a tokenizer from the files saved by `Tokenizer::save_tiktoken`:
+    /// `dir/prefix.tiktoken` and its sidecar `dir/prefix.json`.
+    ///
+    /// # Errors
+    ///
+    /// Returns `MinbpeError::Io` if a file cannot be read, `MinbpeError::MalformedRanks` if the
+    /// sidecar is malformed, and the errors of `try_from_bytes`.
+    pub fn try_from_saved(dir: &Path, prefix: &str) -> Result<Self> {
+        let sidecar = fs::read(dir.join(format!("{}.json", prefix)))?;
+        let sidecar: TiktokenSidecar = serde_json::from_slice(&sidecar)
+            .map_err(|e| malformed_ranks(0, &format!("invalid sidecar: {}", e)))?;
+        let data = fs::read(dir.join(format!("{}.tiktoken", prefix)))?;
+        Self::try_from_bytes(&data, &sidecar.pat_str, sidecar.special_tokens)
+    }
+
+    pub fn pattern(&self) -> &str {
+        &self.pattern
+    }
+
+    /// Decodes like `Tokenizer::decode`. Being inherent, it is what `decode` resolves to even
+    /// when both `Tokenizer` and `RegexTokenizerTrait` are in scope.
+    ///
+    /// # Panics
+    ///
+    /// Panics on an unknown token id.
+    pub fn decode(&self, ids: &[Token]) -> String {
+        Tokenizer::decode(self, ids)
+    }
+
+    /// Sets the number of encoded chunks to cache, replacing the cache and its statistics. A
+    /// capacity of 0 disables the cache, which is the default.
+    pub fn set_chunk_cache_capacity(&mut self, capacity: usize) {
+        self.chunk_cache = NonZeroUsize::new(capacity).map(ChunkCache::new);
+    }
+
+    /// Adds special tokens, replacing those with the same names.
+    ///
+    /// # Errors
+    ///
+    /// Returns `MinbpeError::SpecialTokenIdCollision` if a special token would have the rank of
+    /// a token or the id of another special token, in which case the tokenizer is left
+    /// unchanged.
+    pub fn try_register_special_tokens(&mut self, tokens: &IndexMap<String, Token>) -> Result<()> {
+        let mut special_tokens = self.special_tokens.clone();
+        special_tokens.extend(tokens.iter().map(|(k, &v)| (k.clone(), v)));
+        check_special_token_ids(&special_tokens, &self.merges)?;
+
+        self.inverse_special_tokens = special_tokens
+            .iter()
+            .map(|(k, v)| (*v, k.clone()))
+            .collect();
+        self.special_tokens = special_tokens;
+        Ok(())
+    }
+
+    /// Adds special tokens, replacing those with the same names, see
+    /// `try_register_special_tokens`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `try_register_special_tokens` returns an error.
+    pub fn register_special_tokens(&mut self, tokens: &IndexMap<String, Token>) {
+        self.try_register_special_tokens(tokens)
+            .unwrap_or_else(|e| panic!("{}", e))
+    }
+}
+
+impl Tokenizer for TiktokenTokenizer {
+    fn special_tokens(&self) -> &IndexMap<String, Token> {
+        &self.special_tokens
+    }
+
+    fn merges(&self) -> &IndexMap<(Token, Token), Token> {
+        &self.merges
+    }
+
+    fn vocab(&self) -> &IndexMap<Token, Vec<u8>> {
+        &self.vocab
+    }
+
+    fn split_pattern(&self) -> &str {
+        &self.pattern
+    }
+
+    fn token_bytes(&self, idx: Token) -> Option<Cow<'_, [u8]>> {
+        self.decode_tables
+            .token_bytes(idx)
+            .or_else(|| self.inverse_special_tokens.get(&idx).map(|t| t.as_bytes()))
+            .map(Cow::Borrowed)
+    }
+
+    fn try_encode(&self, text: &str) -> Result<Vec<Token>> {
+        RegexTokenizerTrait::try_encode(self, text)
+    }
+}
+
+impl RegexTokenizerTrait for TiktokenTokenizer {
+    fn encode_chunk(&self, text_bytes: &[u8]) -> Vec<Token> {
+        let text_bytes: Vec<u8> = text_bytes
+            .iter()
+            .map(|&b| self.byte_shuffle[b as usize])
+            .collect();
+        <Self as RegexTokenizerTrait>::encode_chunk_inner(self, &text_bytes)
+    }
+
+    fn compiled_pattern(&self) -> &Regex {
+        &self.compiled_pattern
+    }
+
+    fn tables(&self) -> &TokenTables {
+        &self.tables
+    }
+
+    fn chunk_cache(&self) -> Option<&ChunkCache> {
+        self.chunk_cache.as_ref()
+    }
+
+    fn inverse_special_tokens(&self) -> &IndexMap<Token, String> {
+        &self.inverse_special_tokens
+    }
+}
+
+/// Defines `$name`, a tokenizer wrapping the `TiktokenTokenizer` built from the bundled ranks
+/// `$ranks`, the split pattern `$pattern` and the special tokens `$special_tokens`, both ranks
+/// and special tokens being lazy statics of the calling module.
+#[cfg(any(feature = "gpt2", feature = "gpt4", feature = "p50k"))]
+macro_rules! tiktoken_preset {
+    (
+        $(#[$attr:meta])*
+        pub struct $name:ident {
+            ranks: $ranks:ident,
+            pattern: $pattern:expr,
+            special_tokens: $special_tokens:ident $(,)?
+        }
+    ) => {
+        $(#[$attr])*
+        ///
+        /// Implements `Tokenizer` but not `Trainable`, `Saveable` or `Loadable`, because it cannot
+        /// be trained, loaded or saved.
+        pub struct $name {
+            inner: $crate::tiktoken::TiktokenTokenizer,
+        }
+
+        impl Default for $name {
+            fn default() -> Self {
+                Self::new()
+            }
+        }
+
+        impl $name {
+            /// This method may be called before any other method in this module, in case you want to ensure all the
+            /// lazy static initializations are done before any other operation.
+            pub fn initialize() {
+                let _ = &*$ranks;
+            }
+
+            pub fn new() -> Self {
+                let special_tokens = $special_tokens
+                    .iter()
+                    .map(|(&k, &v)| (k.to_string(), v))
+                    .collect::<::indexmap::IndexMap<String, $crate::Token>>();
+                let inner =
+                    $crate::tiktoken::TiktokenTokenizer::new(&$ranks, $pattern, special_tokens);
+                $name { inner }
+            }
+
+            /// Decodes like `Tokenizer::decode`. Being inherent, it is what `decode` resolves to
+            /// even when both `Tokenizer` and `RegexTokenizerTrait` are in scope.
+            ///
+            /// # Panics
+            ///
+            /// Panics on an unknown token id.
+            pub fn decode(&self, ids: &[$crate::Token]) -> String {
+                self.inner.decode(ids)
+            }
+
+            /// Sets the number of encoded chunks to cache, replacing the cache and its
+            /// statistics. A capacity of 0 disables the cache, which is the default.
+            pub fn set_chunk_cache_capacity(&mut self, capacity: usize) {
+                self.inner.set_chunk_cache_capacity(capacity);
+            }
+
+            /// Adds special tokens, replacing those with the same names.
+            ///
+            /// # Errors
+            ///
+            /// Returns the errors of `TiktokenTokenizer::try_register_special_tokens`.
+            pub fn try_register_special_tokens(
+                &mut self,
+                tokens: &::indexmap::IndexMap<String, $crate::Token>,
+            ) -> $crate::Result<()> {
+                self.inner.try_register_special_tokens(tokens)
+            }
+
+            /// Adds special tokens, replacing those with the same names.
+            ///
+            /// # Panics
+            ///
+            /// Panics if `try_register_special_tokens` returns an error.
+            pub fn register_special_tokens(
+                &mut self,
+                tokens: &::indexmap::IndexMap<String, $crate::Token>,
+            ) {
+                self.inner.register_special_tokens(tokens);
+            }
+        }
+
+        impl $crate::Tokenizer for $name {
+            fn special_tokens(&self) -> &::indexmap::IndexMap<String, $crate::Token> {
+                $crate::Tokenizer::special_tokens(&self.inner)
+            }
+
+            fn merges(
+                &self,
+            ) -> &::indexmap::IndexMap<($crate::Token, $crate::Token), $crate::Token> {
+                $crate::Tokenizer::merges(&self.inner)
+            }
+
+            fn vocab(&self) -> &::indexmap::IndexMap<$crate::Token, Vec<u8>> {
+                $crate::Tokenizer::vocab(&self.inner)
+            }
+
+            fn split_pattern(&self) -> &str {
+                $crate::Tokenizer::split_pattern(&self.inner)
+            }
+
+            fn token_bytes(&self, idx: $crate::Token) -> Option<::std::borrow::Cow<'_, [u8]>> {
+                $crate::Tokenizer::token_bytes(&self.inner, idx)
+            }
+
+            fn try_encode(&self, text: &str) -> $crate::Result<Vec<$crate::Token>> {
+                $crate::RegexTokenizerTrait::try_encode(self, text)
+            }
+        }
+
+        impl $crate::RegexTokenizerTrait for $name {
+            fn encode_chunk(&self, text_bytes: &[u8]) -> Vec<$crate::Token> {
+                $crate::RegexTokenizerTrait::encode_chunk(&self.inner, text_bytes)
+            }
+
+            fn compiled_pattern(&self) -> &::fancy_regex::Regex {
+                $crate::RegexTokenizerTrait::compiled_pattern(&self.inner)
+            }
+
+            fn tables(&self) -> &$crate::TokenTables {
+                $crate::RegexTokenizerTrait::tables(&self.inner)
+            }
+
+            fn chunk_cache(&self) -> Option<&$crate::ChunkCache> {
+                $crate::RegexTokenizerTrait::chunk_cache(&self.inner)
+            }
+
+            fn inverse_special_tokens(&self) -> &::indexmap::IndexMap<$crate::Token, String> {
+                $crate::RegexTokenizerTrait::inverse_special_tokens(&self.inner)
+            }
+        }
+    };
+}
+
+#[cfg(any(feature = "gpt2", feature = "gpt4", feature = "p50k"))]
+pub(crate) use tiktoken_preset;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ranks_file(extra: &[&str]) -> String {
+        let mut data: String = (0..=255u8)
+            .map(|b| format!("{} {}\n", general_purpose::STANDARD.encode([b]), b))
+            .collect();
+        for (i, token) in extra.iter().enumerate() {
+            data += &format!("{} {}\n", general_purpose::STANDARD.encode(token), 256 + i);
+        }
+        data
+    }
+
+    #[test]
+    fn test_parse_tiktoken_ranks() {
+        let ranks = parse_tiktoken_ranks(b"YQ== 0\r\n\nYWI= 5\n").unwrap();
+        assert_eq!(
+            ranks,
+            IndexMap::from([(b"a".to_vec(), 0), (b"ab".to_vec(), 5)])
+        );
+
+        for (data, line) in [
+            (&b"YQ== 0\nYWI=\n"[..], 2),
+            (b"YQ== 0\n!!! 1\n", 2),
+            (b"YQ== -1\n", 1),
+            (b"YQ== 0\nYg== 0\n", 2),
+            (b"YQ== 0\nYQ== 1\n", 2),
+        ] {
+            assert!(matches!(
+                parse_tiktoken_ranks(data),
+                Err(MinbpeError::MalformedRanks { line: l, .. }) if l == line
+            ));
+        }
+    }
+
+    #[test]
+    fn test_recovers_merges() {
+        let data = ranks_file(&["ab", "cd", "abcd", "abc"]);
+        let tokenizer = TiktokenTokenizer::try_from_bytes(
+            data.as_bytes(),
+            crate::regex::GPT4_SPLIT_PATTERN,
+            IndexMap::new(),
+        )
+        .unwrap();
+        assert_eq!(
+            tokenizer.merges(),
+            &IndexMap::from([
+                ((97, 98), 256),
+                ((99, 100), 257),
+                ((256, 257), 258),
+                ((256, 99), 259)
+            ])
+        );
+        assert_eq!(tokenizer.encode_ordinary("abcd abc"), [258, 32, 259]);
+
+        // "bcd" is not the merge of two tokens
+        let data = ranks_file(&["ab", "bcd"]);
+        assert!(matches!(
+            TiktokenTokenizer::try_from_bytes(data.as_bytes(), "", IndexMap::new()),
+            Err(MinbpeError::MalformedRanks { line: 0, .. })
+        ));
+        let data = ranks_file(&[]).replacen("AA== 0\n", "", 1);
+        assert!(matches!(
+            TiktokenTokenizer::try_from_bytes(data.as_bytes(), "", IndexMap::new()),
+            Err(MinbpeError::MalformedRanks { line: 0, .. })
+        ));
+        assert!(matches!(
+            TiktokenTokenizer::try_from_bytes(
+                ranks_file(&["ab"]).as_bytes(),
+                "",
+                IndexMap::from([("<|end|>".to_string(), 256)])
+            ),
+            Err(MinbpeError::SpecialTokenIdCollision { id: 256, .. })
+        ));
+    }
+
+    #[test]
+    fn test_special_token_id_collisions() {
+        let data = ranks_file(&["ab"]);
+        let special_tokens =
+            IndexMap::from([("<|start|>".to_string(), 300), ("<|end|>".to_string(), 300)]);
+        assert!(matches!(
+            TiktokenTokenizer::try_from_bytes(data.as_bytes(), "", special_tokens),
+            Err(MinbpeError::SpecialTokenIdCollision { id: 300, .. })
+        ));
+
+        let special_tokens = IndexMap::from([("<|end|>".to_string(), 300)]);
+        let mut tokenizer =
+            TiktokenTokenizer::try_from_bytes(data.as_bytes(), "", special_tokens).unwrap();
+        for (token, id) in [("<|start|>", 300), ("<|ab|>", 256), ("<|a|>", 97)] {
+            assert!(matches!(
+                tokenizer.try_register_special_tokens(&IndexMap::from([(token.to_string(), id)])),
+                Err(MinbpeError::SpecialTokenIdCollision { id: e, .. }) if e == id
+            ));
+        }
+        assert_eq!(tokenizer.special_tokens().len(), 1);
+
+        // Replacing a special token frees its id
+        tokenizer.register_special_tokens(&IndexMap::from([
+            ("<|end|>".to_string(), 301),
+            ("<|start|>".to_string(), 300),
+        ]));
+        assert_eq!(tokenizer.decode(&[300, 256, 301]), "<|start|>ab<|end|>");
+    }
+
+    #[test]
+    fn test_write_tiktoken_ranks() {
+        let mut vocab: IndexMap<Token, Vec<u8>> =
+            (0..=255u8).rev().map(|b| (b as Token, vec![b])).collect();
+        vocab.insert(256, b"ab".to_vec());
+        vocab.insert(257, b"<|end|>".to_vec());
+        let special_tokens = IndexMap::from([("<|end|>".to_string(), 257)]);
+
+        let mut data = Vec::new();
+        write_tiktoken_ranks(&mut data, &vocab, &special_tokens).unwrap();
+        let data = String::from_utf8(data).unwrap();
+        assert_eq!(data.lines().count(), 257);
+        assert_eq!(data.lines().nth(97), Some("YQ== 97"));
+        assert_eq!(data.lines().last(), Some("YWI= 256"));
+        let ranks = parse_tiktoken_ranks(data.as_bytes()).unwrap();
+        assert_eq!(ranks[&b"ab"[..]], 256);
+
+        // Two tokens with the same bytes, a negative id, a byte without the id of its value and
+        // a multi-byte token among the bytes
+        let mut bad_vocabs = vec![];
+        for (idx, bytes) in [(258, &b"ab"[..]), (-1, b"c")] {
+            let mut vocab = vocab.clone();
+            vocab.insert(idx, bytes.to_vec());
+            bad_vocabs.push(vocab);
+        }
+        let mut missing_byte = vocab.clone();
+        missing_byte.shift_remove(&5);
+        bad_vocabs.push(missing_byte);
+        let mut merged_byte = vocab.clone();
+        merged_byte.insert(3, b"cd".to_vec());
+        bad_vocabs.push(merged_byte);
+        for vocab in bad_vocabs {
+            assert!(matches!(
+                write_tiktoken_ranks(&mut Vec::new(), &vocab, &special_tokens),
+                Err(MinbpeError::MalformedRanks { line: 0, .. })
+            ));
+        }
+    }
+}
@@ -0,0 +1,231 @@
+//! Contains the configuration of training runs, their outcome, and the table of chunk counts
+//! that training runs over. The trainer itself is in `pair_trainer`.
+
+use std::path::PathBuf;
+
+use indexmap::IndexMap;
+
+use crate::{Count, Token};
+
+/// The distinct chunks of a training corpus, with the number of times each one occurs.
+///
+/// Chunks are the units that pairs are counted within: a pair is never counted across two
+/// chunks. They are kept in order of first appearance, which preserves the tie-breaking
+/// between pairs with the same count. Memory use is proportional to the number and size of
+/// distinct chunks rather than to the size of the corpus.
+///
+/// # Examples
+///
+/// ```
+/// use minbpe::ChunkCounts;
+///
+/// let mut chunks = ChunkCounts::new();
+/// chunks.add(b" the");
+/// chunks.add(b" cat");
+/// chunks.add(b" the");
+/// assert_eq!(chunks.len(), 2);
+/// assert_eq!(chunks.iter().next(), Some((&b" the"[..], 2)));
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ChunkCounts {
+    chunks: IndexMap<Vec<u8>, Count>,
+}
+
+impl ChunkCounts {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds one occurrence of `chunk`.
+    pub fn add(&mut self, chunk: &[u8]) {
+        self.add_count(chunk, 1);
+    }
+
+    /// Adds `count` occurrences of `chunk`.
+    pub fn add_count(&mut self, chunk: &[u8], count: Count) {
+        if let Some(existing) = self.chunks.get_mut(chunk) {
+            *existing += count;
+        } else {
+            self.chunks.insert(chunk.to_vec(), count);
+        }
+    }
+
+    /// Returns the number of distinct chunks.
+    pub fn len(&self) -> usize {
+        self.chunks.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.chunks.is_empty()
+    }
+
+    /// Iterates over the distinct chunks and their counts, in order of first appearance.
+    pub fn iter(&self) -> impl Iterator<Item = (&[u8], Count)> {
+        self.chunks
+            .iter()
+            .map(|(chunk, &count)| (chunk.as_slice(), count))
+    }
+}
+
+/// How `Trainable::train_from_files` splits files into documents.
+///
+/// Files are always streamed, one document at a time, so a document must be small enough to be
+/// held in memory. To train on a whole file as one document, pass its text to
+/// `Trainable::train_from_iter`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DocumentSplit {
+    /// Every line (including its line terminator) is a document.
+    Lines,
+
+    /// Documents are separated by a delimiter, which is not part of any document. For example
+    /// `"\n\n"` splits a text file into paragraphs, and `"<|endoftext|>"` a file of
+    /// concatenated documents. The delimiter must not be empty.
+    Delimiter(String),
+
+    /// Every non-blank line is a JSON object, and the document is the string in its member
+    /// `field`, without the JSON syntax around it.
+    #[cfg(feature = "jsonl")]
+    JsonLines { field: String },
+}
+
+/// Where and how often training writes checkpoints, see `Trainable::resume_training`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CheckpointConfig {
+    /// The checkpoint file. It is replaced atomically, so a crash while writing it leaves the
+    /// previous checkpoint intact.
+    pub path: PathBuf,
+
+    /// Number of merges between two checkpoints. A checkpoint is also written when training
+    /// ends.
+    pub interval: usize,
+}
+
+/// Where the ids of the special tokens declared in a `TrainingConfig` are placed.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum SpecialTokenPlacement {
+    /// The special tokens take the ids following the last merge, as in the GPT tokenizers.
+    #[default]
+    AfterMerges,
+
+    /// The special tokens take the ids following the byte tokens, from 256, and the merges
+    /// the ids following the special tokens. The ids of the special tokens then do not depend
+    /// on the number of merges.
+    BeforeMerges,
+}
+
+/// Configuration of a training run.
+///
+/// Training stops at the first of the stop criteria to be met: the vocabulary size, and the
+/// optional `max_merges`, `min_frequency` and `target_bytes_per_token`. It also stops when no
+/// pair is left to merge.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TrainingConfig {
+    /// The size of the vocabulary to train, including the 256 byte tokens and the special
+    /// tokens.
+    pub vocab_size: Token,
+
+    /// The special tokens of the trained tokenizer, in order of their ids. Their text is never
+    /// trained on: documents are split on them before being pre-tokenized. If empty, the
+    /// special tokens of the tokenizer are kept, and training fails with
+    /// `MinbpeError::SpecialTokenIdCollision` if a merge takes the id of one of them.
+    pub special_tokens: Vec<String>,
+
+    /// Where the ids of the special tokens are placed.
+    pub special_token_placement: SpecialTokenPlacement,
+
+    /// The maximum number of merges.
+    pub max_merges: Option<usize>,
+
+    /// The minimum number of occurrences of a pair for it to be merged.
+    pub min_frequency: Option<Count>,
+
+    /// The average number of bytes per token of the training corpus at which to stop.
+    pub target_bytes_per_token: Option<f64>,
+
+    /// Whether to periodically write checkpoints, and where.
+    pub checkpoint: Option<CheckpointConfig>,
+}
+
+impl TrainingConfig {
+    /// Returns the configuration for training a vocabulary of size `vocab_size` without special
+    /// tokens, without other stop criteria and without checkpoints.
+    pub fn new(vocab_size: Token) -> Self {
+        TrainingConfig {
+            vocab_size,
+            special_tokens: Vec::new(),
+            special_token_placement: SpecialTokenPlacement::AfterMerges,
+            max_merges: None,
+            min_frequency: None,
+            target_bytes_per_token: None,
+            checkpoint: None,
+        }
+    }
+}
+
+/// Splits documents on the special tokens of a `TrainingConfig`, so that their text is never
+/// trained on.
+pub(crate) struct SpecialTokenSplitter {
+    pattern: Option<regex::Regex>,
+}
+
+impl SpecialTokenSplitter {
+    pub(crate) fn new(config: &TrainingConfig) -> Self {
+        if config.special_tokens.is_empty() {
+            return SpecialTokenSplitter { pattern: None };
+        }
+
+        // Longer tokens first, so that a token wins over its prefixes
+        let mut tokens: Vec<&String> = config.special_tokens.iter().collect();
+        tokens.sort_by_key(|token| std::cmp::Reverse(token.len()));
+        let pattern = tokens
+            .iter()
+            .map(|token| regex::escape(token))
+            .collect::<Vec<String>>()
+            .join("|");
+        let pattern =
+            regex::Regex::new(&pattern).expect("Escaped special tokens are a valid regex");
+        SpecialTokenSplitter {
+            pattern: Some(pattern),
+        }
+    }
+
+    /// Returns the parts of `text` between the special tokens.
+    pub(crate) fn split<'a>(&'a self, text: &'a str) -> Box<dyn Iterator<Item = &'a str> + 'a> {
+        match &self.pattern {
+            Some(pattern) => Box::new(pattern.split(text)),
+            None => Box::new(std::iter::once(text)),
+        }
+    }
+}
+
+/// Why a training run stopped.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StopReason {
+    /// The vocabulary reached `vocab_size` tokens.
+    VocabSize,
+
+    /// `max_merges` merges were learned.
+    MaxMerges,
+
+    /// The most common pair occurs fewer than `min_frequency` times.
+    MinFrequency,
+
+    /// The training corpus is encoded in `target_bytes_per_token` bytes per token or more.
+    TargetBytesPerToken,
+
+    /// No pair is left to merge: every chunk is a single token.
+    NoMorePairs,
+
+    /// The `TrainingObserver` stopped training.
+    Stopped,
+}
+
+/// The result of a training run.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TrainingOutcome {
+    /// The number of merges learned, including those of a resumed checkpoint.
+    pub num_merges: usize,
+
+    /// Why training stopped.
+    pub stop_reason: StopReason,
+}
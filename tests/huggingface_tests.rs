@@ -0,0 +1,206 @@
+#[cfg(all(test, feature = "huggingface"))]
+mod tests {
+    use std::fs::File;
+
+    use minbpe::test_common::{unpack, LLAMA_TEXT, SPECIAL_TOKENS};
+    use minbpe::{AllowedSpecial, HuggingFaceTokenizer, RegexTokenizerTrait, Token, Tokenizer};
+    use minbpe::{Loadable, RegexTokenizerStruct, Saveable, Trainable};
+    use tempfile::tempdir;
+
+    // tests/tokenizer.json is a byte-level BPE model in the layout of Llama 3: a regex Split
+    // (the GPT-4 pattern) then ByteLevel. The bytes have the ids 1 to 256 in the order of GPT-2's
+    // bytes_to_unicode, and the merges produce ids in the reverse order of their ranks. The
+    // expected ids were computed with a reference implementation of HuggingFace's BPE.
+    const FIXTURE: &str = "tests/tokenizer.json";
+
+    #[test]
+    fn test_huggingface_fixture() {
+        let tokenizer = HuggingFaceTokenizer::try_from_file(FIXTURE).unwrap();
+
+        for (text, expected) in [
+            ("the and", &[84, 273, 267][..]),
+            ("hello world", &[273, 76, 76, 79, 260]),
+            (
+                "in the world, and the other",
+                &[271, 272, 260, 12, 267, 272, 221, 79, 84, 273, 82],
+            ),
+            (
+                "日本 café zz",
+                &[258, 163, 251, 106, 221, 67, 65, 70, 257, 221, 90, 90],
+            ),
+            (" the  the\n", &[272, 221, 272, 199]),
+        ] {
+            let ids = tokenizer.encode_ordinary(text);
+            assert_eq!(ids, expected, "{:?}", text);
+            assert_eq!(tokenizer.decode(&ids), text);
+        }
+
+        let special_tokens: Vec<(&str, Token)> = tokenizer
+            .special_tokens()
+            .iter()
+            .map(|(k, &v)| (k.as_str(), v))
+            .collect();
+        assert_eq!(special_tokens, [("<|endoftext|>", 0), ("<|pad|>", 301)]);
+        assert_eq!(
+            tokenizer.encode_special("the<|endoftext|>", AllowedSpecial::All),
+            [84, 273, 0]
+        );
+        assert_eq!(tokenizer.decode(&[301, 272]), "<|pad|> the");
+
+        // 256 bytes, 18 merges and a token no merge produces
+        assert_eq!(tokenizer.vocab().len(), 275);
+        assert_eq!(tokenizer.merges().len(), 18);
+        assert_eq!(tokenizer.vocab()[&300], b"zz");
+        assert_eq!(tokenizer.decode(&[300]), "zz");
+
+        let spans = tokenizer.encode_with_offsets("café", AllowedSpecial::None);
+        assert_eq!(spans.last().unwrap().bytes, 3..5);
+        assert_eq!(spans.last().unwrap().chars, 3..4);
+    }
+
+    #[test]
+    fn test_huggingface_ignore_merges() {
+        // With ignore_merges, a chunk that is in the vocabulary is encoded as that token
+        let json = std::fs::read_to_string(FIXTURE).unwrap();
+        let tokenizer = HuggingFaceTokenizer::try_from_bytes(json.as_bytes()).unwrap();
+        assert_eq!(tokenizer.encode_ordinary("zz"), [90, 90]);
+
+        let json = json.replace(r#""ignore_merges": false"#, r#""ignore_merges": true"#);
+        let tokenizer = HuggingFaceTokenizer::try_from_bytes(json.as_bytes()).unwrap();
+        assert_eq!(tokenizer.encode_ordinary("zz"), [300]);
+        assert_eq!(tokenizer.encode_ordinary(" the"), [272]);
+
+        let tokenizer = HuggingFaceTokenizer::try_from_reader(File::open(FIXTURE).unwrap());
+        assert!(tokenizer.is_ok());
+    }
+
+    #[test]
+    fn test_save_hf_json_round_trip() {
+        let taylorswift = unpack("FILE:../tests/taylorswift.txt").unwrap();
+        let (train_text, text) = (&taylorswift[..20000], &taylorswift[20000..30000]);
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("tokenizer.json");
+
+        let mut regex = RegexTokenizerStruct::default();
+        regex.train(train_text, 256 + 300, false);
+        regex.set_special_tokens(SPECIAL_TOKENS.clone());
+        regex.save_hf_json(&path);
+
+        let imported = HuggingFaceTokenizer::try_from_file(&path).unwrap();
+        assert_eq!(imported.pattern(), regex.pattern());
+        assert_eq!(imported.merges(), regex.merges());
+        assert_eq!(imported.special_tokens(), regex.special_tokens());
+        assert_eq!(imported.encode_ordinary(text), regex.encode_ordinary(text));
+        let ids = regex.encode_special(LLAMA_TEXT, AllowedSpecial::All);
+        assert_eq!(
+            imported.encode_special(LLAMA_TEXT, AllowedSpecial::All),
+            ids
+        );
+        assert_eq!(imported.decode(&ids), LLAMA_TEXT);
+    }
+
+    // Without a pattern, the whole text is a single chunk
+    #[cfg(feature = "basic")]
+    #[test]
+    fn test_save_hf_json_basic_round_trip() {
+        let taylorswift = unpack("FILE:../tests/taylorswift.txt").unwrap();
+        let (train_text, text) = (&taylorswift[..20000], &taylorswift[20000..30000]);
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("tokenizer.json");
+
+        let mut basic = minbpe::BasicTokenizer::new();
+        basic.train(train_text, 256 + 100, false);
+        basic.save_hf_json(&path);
+
+        let imported = HuggingFaceTokenizer::try_from_file(&path).unwrap();
+        assert_eq!(imported.vocab(), basic.vocab());
+        assert_eq!(
+            imported.encode_ordinary(text),
+            Tokenizer::encode(&basic, text)
+        );
+    }
+
+    // The encoder.json and vocab.bpe of the GPT-2 release, written from r50k_base, which holds
+    // the same tokens with the same ids
+    #[cfg(feature = "gpt2")]
+    fn gpt2_files() -> (Vec<u8>, String) {
+        // GPT-2's bytes_to_unicode
+        let mut chars = Vec::new();
+        let mut n = 0;
+        for b in 0..=255u8 {
+            chars.push(if matches!(b, b'!'..=b'~' | 0xA1..=0xAC | 0xAE..=0xFF) {
+                b as char
+            } else {
+                n += 1;
+                char::from_u32(255 + n).unwrap()
+            });
+        }
+        let text = |bytes: &[u8]| -> String { bytes.iter().map(|&b| chars[b as usize]).collect() };
+
+        let r50k_base = minbpe::GPT2Tokenizer::new();
+        // The vocabulary holds the bytes of the tokens shuffled, token_bytes the actual ones
+        let token_text = |idx: Token| text(&r50k_base.token_bytes(idx).unwrap());
+        let mut encoder: indexmap::IndexMap<String, Token> = r50k_base
+            .vocab()
+            .keys()
+            .map(|&idx| (token_text(idx), idx))
+            .collect();
+        encoder.insert("<|endoftext|>".to_string(), 50256);
+        let mut merges: Vec<(&(Token, Token), &Token)> = r50k_base.merges().iter().collect();
+        merges.sort_by_key(|&(_, &idx)| idx);
+        let mut vocab_bpe = "#version: 0.2\n".to_string();
+        for (&(left, right), _) in merges {
+            vocab_bpe.push_str(&format!("{} {}\n", token_text(left), token_text(right)));
+        }
+        (serde_json::to_vec(&encoder).unwrap(), vocab_bpe)
+    }
+
+    // The GPT-2 files load into a tokenizer that encodes like r50k_base, up to the ids of the
+    // bytes
+    #[cfg(feature = "gpt2")]
+    #[test]
+    fn test_gpt2_files() {
+        let (encoder, vocab_bpe) = gpt2_files();
+        let dir = tempdir().unwrap();
+        let (encoder_path, vocab_bpe_path) = (
+            dir.path().join("encoder.json"),
+            dir.path().join("vocab.bpe"),
+        );
+        std::fs::write(&encoder_path, encoder).unwrap();
+        std::fs::write(&vocab_bpe_path, vocab_bpe).unwrap();
+        let tokenizer =
+            RegexTokenizerStruct::try_from_gpt2_files(&encoder_path, &vocab_bpe_path).unwrap();
+        assert_eq!(tokenizer.merges().len(), 50000);
+        assert_eq!(tokenizer.encode_ordinary("hello world"), [31373, 995]);
+        assert_eq!(
+            tokenizer.encode_special("hello<|endoftext|>", AllowedSpecial::All),
+            [31373, 50256]
+        );
+        assert_eq!(
+            RegexTokenizerTrait::decode(&tokenizer, &[31373, 995, 50256]),
+            "hello world<|endoftext|>"
+        );
+
+        let r50k_base = minbpe::GPT2Tokenizer::new();
+        let mut byte_ids = [0; 256];
+        for idx in 0..256 {
+            byte_ids[r50k_base.token_bytes(idx).unwrap()[0] as usize] = idx;
+        }
+        for text in minbpe::test_common::TEST_STRINGS {
+            let text = unpack(text).unwrap();
+            let ids = tokenizer.encode_ordinary(&text);
+            let r50k_ids: Vec<Token> = ids
+                .iter()
+                .map(|&idx| {
+                    if idx < 256 {
+                        byte_ids[idx as usize]
+                    } else {
+                        idx
+                    }
+                })
+                .collect();
+            assert_eq!(r50k_ids, r50k_base.encode_ordinary(&text));
+            assert_eq!(RegexTokenizerTrait::decode(&tokenizer, &ids), text);
+        }
+    }
+}
@@ -0,0 +1,103 @@
+#[cfg(all(test, feature = "sentencepiece"))]
+mod tests {
+    use std::fs::File;
+
+    use minbpe::{DecodeOptions, SentencePieceTokenizer, StreamingDecoder, Token, Tokenizer};
+
+    // tests/sentencepiece.model is a BPE model in the layout of Llama 2: <unk>, <s> and </s>,
+    // the 256 byte pieces, a user-defined <mask>, merged pieces scored in decreasing order, an
+    // unused ▁xy, and single characters; byte fallback, and the identity normalization with
+    // add_dummy_prefix but not remove_extra_whitespaces. The expected ids were computed with a
+    // reference implementation of SentencePiece's BPE.
+    const FIXTURE: &str = "tests/sentencepiece.model";
+
+    #[test]
+    fn test_sentencepiece_fixture() {
+        let tokenizer = SentencePieceTokenizer::try_from_file(FIXTURE).unwrap();
+
+        for (text, expected) in [
+            ("the and world", &[265, 267, 272][..]),
+            ("hello world", &[277, 272]),
+            (
+                "  two  spaces ",
+                &[278, 260, 300, 297, 278, 302, 115, 293, 307, 282, 290],
+            ),
+            // byte fallback
+            ("日本!", &[290, 233, 154, 168, 233, 159, 175, 36]),
+            ("the\nworld", &[265, 13, 300, 268, 269]),
+            // of the pairs with equal scores, the leftmost is merged
+            ("bcd", &[290, 287, 296]),
+            // user-defined pieces are never merged
+            ("<mask>the", &[290, 259, 292, 261]),
+            // the unused ▁xy is merged into ▁xyz, or split back into ▁x y
+            ("xyz xy", &[281, 280, 304]),
+            ("café", &[286]),
+            // control pieces in the text are not special
+            ("<s>", &[290, 310, 302, 311]),
+            ("", &[]),
+        ] {
+            let ids = tokenizer.encode(text);
+            assert_eq!(ids, expected, "{:?}", text);
+            assert_eq!(tokenizer.decode(&ids), text);
+        }
+
+        let special_tokens: Vec<(&str, Token)> = tokenizer
+            .special_tokens()
+            .iter()
+            .map(|(k, &v)| (k.as_str(), v))
+            .collect();
+        assert_eq!(special_tokens, [("<unk>", 0), ("<s>", 1), ("</s>", 2)]);
+        assert_eq!(tokenizer.decode(&[1, 277, 2]), "<s>hello</s>");
+        let options = DecodeOptions {
+            skip_special_tokens: true,
+            ..Default::default()
+        };
+        assert_eq!(
+            tokenizer.try_decode_with(&[1, 277, 2], &options).unwrap(),
+            "hello"
+        );
+
+        // the byte pieces, <mask>, the merged pieces, the unused piece and the characters
+        assert_eq!(tokenizer.vocab().len(), 309);
+        assert!(tokenizer.merges().is_empty());
+        assert_eq!(tokenizer.vocab()[&277], b" hello");
+        assert_eq!(tokenizer.vocab()[&13], b"\n");
+        assert_eq!(tokenizer.decode_bytes(&[277, 272]), b"hello world");
+        assert_eq!(tokenizer.decode(&[233, 154]), "\u{FFFD}");
+        assert!(tokenizer.try_decode(&[312]).is_err());
+
+        let tokenizer = SentencePieceTokenizer::try_from_reader(File::open(FIXTURE).unwrap());
+        assert!(tokenizer.is_ok());
+    }
+
+    #[test]
+    fn test_sentencepiece_streaming() {
+        let tokenizer = SentencePieceTokenizer::try_from_file(FIXTURE).unwrap();
+
+        // The dummy prefix is dropped from the first token that is not special only
+        let mut decoder = StreamingDecoder::new(&tokenizer);
+        for text in ["hello world", "  two  spaces ", "日本!", "<mask>the", ""] {
+            let mut ids = vec![1];
+            ids.extend(tokenizer.encode(text));
+            ids.push(2);
+            let mut streamed: String = ids.iter().map(|&idx| decoder.push(idx)).collect();
+            streamed.push_str(&decoder.flush());
+            assert_eq!(streamed, tokenizer.decode(&ids), "{:?}", text);
+            assert_eq!(streamed, format!("<s>{}</s>", text));
+        }
+        assert_eq!(tokenizer.token_bytes(277).unwrap().as_ref(), b" hello");
+        assert_eq!(
+            tokenizer.token_bytes_at(277, true).unwrap().as_ref(),
+            b"hello"
+        );
+    }
+
+    #[test]
+    fn test_sentencepiece_invalid() {
+        let data = std::fs::read(FIXTURE).unwrap();
+        for len in [1, 100, data.len() - 1] {
+            assert!(SentencePieceTokenizer::try_from_bytes(&data[..len]).is_err());
+        }
+        assert!(SentencePieceTokenizer::try_from_bytes(b"").is_err());
+    }
+}
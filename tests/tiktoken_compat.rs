@@ -3,14 +3,18 @@ mod tests {
     use std::collections::HashSet;
 
     use lazy_static::lazy_static;
-    use minbpe::{GPT4Tokenizer, RegexTokenizerTrait, Token};
+    use minbpe::{GPT2Tokenizer, GPT4Tokenizer, P50kTokenizer, RegexTokenizerTrait, Token};
     use proptest::prelude::*;
-    use tiktoken_rs::{cl100k_base, CoreBPE};
+    use tiktoken_rs::{cl100k_base, p50k_base, r50k_base, CoreBPE};
 
     lazy_static! {
         static ref SPECIAL_TOKENS: HashSet<&'static str> = HashSet::new();
         static ref TIKTOKEN_ENC: CoreBPE = cl100k_base().unwrap();
         static ref GPT4_TOKENIZER: GPT4Tokenizer = GPT4Tokenizer::default();
+        static ref R50K_ENC: CoreBPE = r50k_base().unwrap();
+        static ref GPT2_TOKENIZER: GPT2Tokenizer = GPT2Tokenizer::default();
+        static ref P50K_ENC: CoreBPE = p50k_base().unwrap();
+        static ref P50K_TOKENIZER: P50kTokenizer = P50kTokenizer::default();
     }
 
     fn test_one(s: &str) {
@@ -24,16 +28,141 @@ mod tests {
         assert_eq!(tiktoken_tokens, gpt4_tokenizer_tokens);
     }
 
+    // Every way of decoding with GPT4Tokenizer must round-trip and agree with tiktoken
+    fn test_decode_one(s: &str) {
+        let tiktoken_ids = TIKTOKEN_ENC.encode_with_special_tokens(s);
+        let ids: Vec<Token> = tiktoken_ids.iter().map(|&id| id as Token).collect();
+        let tiktoken_decoded = TIKTOKEN_ENC.decode(tiktoken_ids).unwrap();
+
+        let tokenizer = &*GPT4_TOKENIZER;
+        assert_eq!(tokenizer.decode(&ids), s);
+        assert_eq!(minbpe::Tokenizer::decode(tokenizer, &ids), s);
+        assert_eq!(RegexTokenizerTrait::decode(tokenizer, &ids), s);
+        assert_eq!(
+            minbpe::Tokenizer::decode_bytes(tokenizer, &ids),
+            s.as_bytes()
+        );
+        assert_eq!(tokenizer.decode(&ids), tiktoken_decoded);
+    }
+
+    #[test]
+    fn test_decode_special_tokens() {
+        test_decode_one("<|endoftext|>Hello<|fim_prefix|> 👋🏽 日本語<|endofprompt|>");
+        let ids = GPT4_TOKENIZER.encode_special("x<|endoftext|>", minbpe::AllowedSpecial::All);
+        assert_eq!(ids.last(), Some(&100257));
+        assert_eq!(GPT4_TOKENIZER.decode(&ids), "x<|endoftext|>");
+
+        // register_special_tokens_x is kept as an alias of register_special_tokens
+        let mut tokenizer = GPT4Tokenizer::new();
+        let tokens = indexmap::IndexMap::from([("<|a|>".to_string(), 100300)]);
+        tokenizer.register_special_tokens(&tokens);
+        let tokens = indexmap::IndexMap::from([("<|b|>".to_string(), 100301)]);
+        tokenizer.register_special_tokens_x(&tokens);
+        let ids = tokenizer.encode_special("<|a|>x<|b|>", minbpe::AllowedSpecial::All);
+        assert_eq!(ids.first(), Some(&100300));
+        assert_eq!(ids.last(), Some(&100301));
+        assert_eq!(tokenizer.decode(&ids), "<|a|>x<|b|>");
+    }
+
     #[test]
     fn test_high_char() {
         test_one("\u{1e01b}%SΣ");
     }
 
+    // A preset must encode like tiktoken, with and without special tokens, and decode back
+    fn test_preset_one<T: RegexTokenizerTrait + Sync>(enc: &CoreBPE, tokenizer: &T, s: &str) {
+        let tiktoken_ids: Vec<Token> = enc
+            .encode_ordinary(s)
+            .iter()
+            .map(|&id| id as Token)
+            .collect();
+        assert_eq!(tokenizer.encode_ordinary(s), tiktoken_ids);
+
+        let tiktoken_ids: Vec<Token> = enc
+            .encode_with_special_tokens(s)
+            .iter()
+            .map(|&id| id as Token)
+            .collect();
+        let ids = tokenizer.encode_special(s, minbpe::AllowedSpecial::All);
+        assert_eq!(ids, tiktoken_ids);
+        assert_eq!(minbpe::Tokenizer::decode(tokenizer, &ids), s);
+    }
+
+    #[test]
+    fn test_gpt2_and_p50k_presets() {
+        // p50k_base adds tokens for runs of spaces, which r50k_base spells out
+        let s = "def f():\n        return 1<|endoftext|> 👋🏽 日本語";
+        test_preset_one(&R50K_ENC, &*GPT2_TOKENIZER, s);
+        test_preset_one(&P50K_ENC, &*P50K_TOKENIZER, s);
+        assert!(P50K_TOKENIZER.encode_ordinary(s).len() < GPT2_TOKENIZER.encode_ordinary(s).len());
+        assert_eq!(
+            GPT2_TOKENIZER.encode_special("<|endoftext|>", minbpe::AllowedSpecial::All),
+            [50256]
+        );
+        assert_eq!(minbpe::Tokenizer::vocab(&*P50K_TOKENIZER).len(), 50280);
+    }
+
+    // A trained tokenizer saved in the tiktoken format must encode the same in tiktoken
+    #[test]
+    fn test_save_tiktoken_loads_in_tiktoken() {
+        use minbpe::test_common::{unpack, LLAMA_TEXT, SPECIAL_TOKENS};
+        use minbpe::{Loadable, RegexTokenizerStruct, Trainable};
+
+        let taylorswift = unpack("FILE:../tests/taylorswift.txt").unwrap();
+        let mut tokenizer = RegexTokenizerStruct::default();
+        tokenizer.train(&taylorswift[..20000], 256 + 512, false);
+        tokenizer.set_special_tokens(SPECIAL_TOKENS.clone());
+        let dir = tempfile::tempdir().unwrap();
+        minbpe::Tokenizer::save_tiktoken(&tokenizer, dir.path(), "taylorswift");
+
+        let data = std::fs::read(dir.path().join("taylorswift.tiktoken")).unwrap();
+        let ranks = minbpe::parse_tiktoken_ranks(&data).unwrap();
+        let enc = CoreBPE::new(
+            ranks
+                .into_iter()
+                .map(|(token, rank)| (token, rank as usize))
+                .collect(),
+            SPECIAL_TOKENS
+                .iter()
+                .map(|(token, &idx)| (token.clone(), idx as usize))
+                .collect(),
+            minbpe::Saveable::pattern(&tokenizer),
+        )
+        .unwrap();
+
+        for text in [&taylorswift[20000..30000], LLAMA_TEXT] {
+            let ids: Vec<Token> = enc
+                .encode_with_special_tokens(text)
+                .iter()
+                .map(|&id| id as Token)
+                .collect();
+            assert_eq!(
+                tokenizer.encode_special(text, minbpe::AllowedSpecial::All),
+                ids
+            );
+        }
+    }
+
     proptest! {
         #[test]
         #[allow(unused_must_use)]
         fn gpt4_tokenizer_matches_tiktoken(s in "\\PC*") {
           test_one(&s);
         }
+
+        #[test]
+        fn gpt4_decode_round_trips_and_matches_tiktoken(s in "\\PC*") {
+          test_decode_one(&s);
+        }
+
+        #[test]
+        fn gpt2_tokenizer_matches_tiktoken(s in "\\PC*") {
+          test_preset_one(&R50K_ENC, &*GPT2_TOKENIZER, &s);
+        }
+
+        #[test]
+        fn p50k_tokenizer_matches_tiktoken(s in "\\PC*") {
+          test_preset_one(&P50K_ENC, &*P50K_TOKENIZER, &s);
+        }
     }
 }
@@ -1,16 +1,33 @@
-#[cfg(test)]
+#[cfg(all(test, feature = "basic", feature = "regex"))]
 mod tests {
-    use minbpe::test_common::{LLAMA_TEXT, SPECIAL_TOKENS};
+    use minbpe::test_common::{unpack, LLAMA_TEXT, SPECIAL_TOKENS};
     use minbpe::AllowedSpecial;
     use minbpe::BasicTokenizer;
+    use minbpe::CheckpointConfig;
+    use minbpe::ChunkCounts;
+    use minbpe::DecodeOptions;
+    use minbpe::DocumentSplit;
     use minbpe::Loadable;
+    use minbpe::MergeEvent;
+    use minbpe::MinbpeError;
     use minbpe::RegexTokenizerStruct;
     use minbpe::RegexTokenizerTrait;
     use minbpe::Saveable;
+    use minbpe::SilentObserver;
+    use minbpe::SpecialTokenPlacement;
+    use minbpe::StopReason;
+    use minbpe::StreamingDecoder;
     use minbpe::Token;
     use minbpe::Trainable;
+    use minbpe::TrainingConfig;
+    use minbpe::TrainingObserver;
+    use minbpe::TrainingPhase;
+    use minbpe::UnknownIdPolicy;
+    use minbpe::Utf8Policy;
+    use minbpe::{get_max_entry, get_stats, merge, update_stats};
 
     use indexmap::IndexMap;
+    use std::fs;
     use tempfile::tempdir;
 
     // Quick unit test, following along the Wikipedia example:
@@ -91,4 +108,1049 @@ mod tests {
         let special_tokens = &SPECIAL_TOKENS;
         test_save_load_inner(special_tokens);
     }
+
+    fn try_load_str(contents: &str) -> Result<(), MinbpeError> {
+        let dir = tempdir().unwrap();
+        let model_file = dir.path().join("broken.model");
+        fs::write(&model_file, contents).unwrap();
+        let mut tokenizer = RegexTokenizerStruct::default();
+        tokenizer.try_load(&model_file)
+    }
+
+    #[test]
+    fn test_try_load_errors() {
+        assert!(matches!(
+            try_load_str("minbpe v2\n\n0\n"),
+            Err(MinbpeError::InvalidVersion(v)) if v == "minbpe v2"
+        ));
+        assert!(matches!(
+            try_load_str("minbpe v1\n\nmany\n"),
+            Err(MinbpeError::MalformedModel { line: 3, .. })
+        ));
+        assert!(matches!(
+            try_load_str("minbpe v1\n\n1\n"),
+            Err(MinbpeError::MalformedModel { line: 4, .. })
+        ));
+        assert!(matches!(
+            try_load_str("minbpe v1\n\n0\n101 32\n256 x\n"),
+            Err(MinbpeError::MalformedMerge { line: 5, content }) if content == "256 x"
+        ));
+        assert!(matches!(
+            try_load_str("minbpe v1\n\n2\n<|a|> 300\n<|b|> 300\n"),
+            Err(MinbpeError::SpecialTokenIdCollision { token, id: 300 }) if token == "<|b|>"
+        ));
+        assert!(matches!(
+            try_load_str("minbpe v1\n\n1\n<|a|> 256\n101 256\n"),
+            Err(MinbpeError::MalformedMerge { line: 5, .. })
+        ));
+        assert!(matches!(
+            try_load_str("minbpe v1\n(unclosed\n0\n"),
+            Err(MinbpeError::InvalidRegex(_))
+        ));
+
+        // A failed load leaves the tokenizer unchanged, its pattern included
+        let dir = tempdir().unwrap();
+        let model_file = dir.path().join("broken.model");
+        fs::write(
+            &model_file,
+            "minbpe v1\n\\w+\n1\n<|a|> 300\n101 32\n256 x\n",
+        )
+        .unwrap();
+        let mut tokenizer = RegexTokenizerStruct::default();
+        let pattern = tokenizer.pattern().to_string();
+        tokenizer.train("hello hello", 256 + 1, false);
+        let merges = minbpe::Tokenizer::merges(&tokenizer).clone();
+        assert!(matches!(
+            tokenizer.try_load(&model_file),
+            Err(MinbpeError::MalformedMerge { line: 6, .. })
+        ));
+        assert_eq!(tokenizer.pattern(), pattern);
+        assert_eq!(minbpe::Tokenizer::merges(&tokenizer), &merges);
+        assert!(minbpe::Tokenizer::special_tokens(&tokenizer).is_empty());
+
+        let mut tokenizer = RegexTokenizerStruct::default();
+        assert!(matches!(
+            tokenizer.try_load(&tempdir().unwrap().path().join("missing.txt")),
+            Err(MinbpeError::InvalidModelExtension(_))
+        ));
+        assert!(matches!(
+            tokenizer.try_load(&tempdir().unwrap().path().join("missing.model")),
+            Err(MinbpeError::Io(_))
+        ));
+    }
+
+    #[test]
+    fn test_try_train_invalid_vocab_size() {
+        let mut tokenizer = BasicTokenizer::new();
+        assert!(matches!(
+            tokenizer.try_train("aaabdaaabac", 255, false),
+            Err(MinbpeError::InvalidVocabSize(255))
+        ));
+    }
+
+    // The straightforward training algorithm: recount all pairs and re-merge all chunks for
+    // every new token.
+    fn reference_merges(chunks: &[&str], num_merges: Token) -> IndexMap<(Token, Token), Token> {
+        let mut ids: Vec<Vec<Token>> = chunks
+            .iter()
+            .map(|chunk| chunk.bytes().map(|b| b as Token).collect())
+            .collect();
+        let mut merges = IndexMap::new();
+        for i in 0..num_merges {
+            let mut stats = IndexMap::new();
+            for chunk_ids in &ids {
+                update_stats(chunk_ids, &mut stats);
+            }
+            let pair = *get_max_entry(&stats).unwrap().0;
+            let idx = 256 + i;
+            ids = ids
+                .iter()
+                .map(|chunk_ids| merge(chunk_ids, pair, idx))
+                .collect();
+            merges.insert(pair, idx);
+        }
+        merges
+    }
+
+    #[test]
+    fn test_train_matches_reference() {
+        use minbpe::Tokenizer;
+
+        let taylorswift = unpack("FILE:../tests/taylorswift.txt").unwrap();
+        let texts = [LLAMA_TEXT, &taylorswift[..20000]];
+        let num_merges = 200;
+
+        for text in texts {
+            let mut basic = BasicTokenizer::new();
+            basic.train(text, 256 + num_merges, false);
+            assert_eq!(basic.merges(), &reference_merges(&[text], num_merges));
+
+            let mut regex = RegexTokenizerStruct::default();
+            regex.train(text, 256 + num_merges, false);
+            let chunks: Vec<&str> = regex
+                .compiled_pattern()
+                .find_iter(text)
+                .map(|m| m.unwrap().as_str())
+                .collect();
+            assert_eq!(regex.merges(), &reference_merges(&chunks, num_merges));
+        }
+    }
+
+    // The straightforward encoding algorithm: recount all pairs and re-merge the whole sequence
+    // for every merge applied.
+    fn reference_encode(text: &str, merges: &IndexMap<(Token, Token), Token>) -> Vec<Token> {
+        let mut ids: Vec<Token> = text.bytes().map(|b| b as Token).collect();
+        while ids.len() >= 2 {
+            let stats = get_stats(&ids);
+            let Some(pair) = stats
+                .keys()
+                .filter(|pair| merges.contains_key(*pair))
+                .min_by_key(|pair| merges[*pair])
+            else {
+                break;
+            };
+            ids = merge(&ids, *pair, merges[pair]);
+        }
+        ids
+    }
+
+    #[test]
+    fn test_encode_matches_reference() {
+        use minbpe::Tokenizer;
+
+        let taylorswift = unpack("FILE:../tests/taylorswift.txt").unwrap();
+        let (train_text, text) = (&taylorswift[..20000], &taylorswift[20000..30000]);
+
+        let mut basic = BasicTokenizer::new();
+        basic.train(train_text, 256 + 200, false);
+        assert_eq!(
+            Tokenizer::encode(&basic, text),
+            reference_encode(text, basic.merges())
+        );
+
+        let mut regex = RegexTokenizerStruct::default();
+        regex.train(train_text, 256 + 200, false);
+        let expected: Vec<Token> = regex
+            .compiled_pattern()
+            .find_iter(text)
+            .flat_map(|m| reference_encode(m.unwrap().as_str(), regex.merges()))
+            .collect();
+        assert_eq!(regex.encode_ordinary(text), expected);
+    }
+
+    #[test]
+    fn test_chunk_cache() {
+        let text = LLAMA_TEXT;
+        let mut tokenizer = RegexTokenizerStruct::default();
+        tokenizer.train(text, 256 + 64, false);
+        let expected = tokenizer.encode_ordinary(text);
+        assert_eq!(tokenizer.cache_stats(), None);
+
+        tokenizer.set_chunk_cache_capacity(1024);
+        assert_eq!(tokenizer.encode_ordinary(text), expected);
+        let stats = tokenizer.cache_stats().unwrap();
+        assert!(stats.hits > 0 && stats.misses > 0);
+        assert!(stats.len as u64 <= stats.misses);
+        assert_eq!(tokenizer.encode_ordinary(text), expected);
+        assert_eq!(tokenizer.cache_stats().unwrap().misses, stats.misses);
+
+        // Evicted chunks are encoded again
+        tokenizer.set_chunk_cache_capacity(8);
+        assert_eq!(tokenizer.encode_ordinary(text), expected);
+        let stats = tokenizer.cache_stats().unwrap();
+        assert_eq!((stats.len, stats.capacity), (8, 8));
+
+        // Retraining clears the cache
+        tokenizer.train(text, 256 + 32, false);
+        assert_eq!(tokenizer.cache_stats().unwrap().len, 0);
+        let mut uncached = RegexTokenizerStruct::default();
+        uncached.train(text, 256 + 32, false);
+        assert_eq!(
+            tokenizer.encode_ordinary(text),
+            uncached.encode_ordinary(text)
+        );
+    }
+
+    #[test]
+    fn test_encode_decode_batch() {
+        use minbpe::Tokenizer;
+
+        let texts: Vec<&str> = LLAMA_TEXT.lines().collect();
+
+        let mut basic = BasicTokenizer::new();
+        basic.train(LLAMA_TEXT, 256 + 64, false);
+        let batch = basic.encode_batch(&texts);
+        assert_eq!(batch.len(), texts.len());
+        for (ids, text) in batch.iter().zip(&texts) {
+            assert_eq!(ids, &Tokenizer::encode(&basic, text));
+        }
+        assert_eq!(basic.decode_batch(&batch), texts);
+        assert!(matches!(
+            basic.try_decode_batch(&[vec![97], vec![100000]]),
+            Err(MinbpeError::UnknownTokenId(100000))
+        ));
+
+        let mut regex = RegexTokenizerStruct::default();
+        regex.train(LLAMA_TEXT, 256 + 64, false);
+        regex.set_special_tokens(SPECIAL_TOKENS.clone());
+        let batch = regex.encode_special_batch(&texts, AllowedSpecial::All);
+        for (ids, text) in batch.iter().zip(&texts) {
+            assert_eq!(ids, &regex.encode_special(text, AllowedSpecial::All));
+        }
+        assert_eq!(regex.decode_batch(&batch), texts);
+        assert_eq!(
+            regex.encode_special_batch(&texts, AllowedSpecial::None),
+            texts
+                .iter()
+                .map(|text| regex.encode_ordinary(text))
+                .collect::<Vec<_>>()
+        );
+        assert!(matches!(
+            regex.try_encode_batch(&texts),
+            Err(MinbpeError::DisallowedSpecialToken(_))
+        ));
+        assert!(regex.encode_batch(&[]).is_empty());
+    }
+
+    #[test]
+    fn test_tokenizer_not_sync() {
+        use minbpe::Tokenizer;
+        use std::cell::Cell;
+
+        // Counts its encodings in a `Cell`, so it is not `Sync`
+        struct CountingTokenizer {
+            inner: BasicTokenizer,
+            encoded: Cell<usize>,
+        }
+
+        impl Tokenizer for CountingTokenizer {
+            fn special_tokens(&self) -> &IndexMap<String, Token> {
+                self.inner.special_tokens()
+            }
+
+            fn merges(&self) -> &IndexMap<(Token, Token), Token> {
+                self.inner.merges()
+            }
+
+            fn vocab(&self) -> &IndexMap<Token, Vec<u8>> {
+                self.inner.vocab()
+            }
+
+            fn try_encode(&self, text: &str) -> minbpe::Result<Vec<Token>> {
+                self.encoded.set(self.encoded.get() + 1);
+                self.inner.try_encode(text)
+            }
+        }
+
+        let mut inner = BasicTokenizer::new();
+        inner.train(LLAMA_TEXT, 256 + 16, false);
+        let tokenizer = CountingTokenizer {
+            inner,
+            encoded: Cell::new(0),
+        };
+        let ids = tokenizer.encode("hello world");
+        assert_eq!(tokenizer.decode(&ids), "hello world");
+        assert_eq!(tokenizer.encoded.get(), 1);
+
+        // Batches are only encoded by `Sync` tokenizers with the `parallel` feature
+        #[cfg(not(feature = "parallel"))]
+        {
+            let batch = tokenizer.encode_batch(&["hello", "world"]);
+            assert_eq!(tokenizer.decode_batch(&batch), ["hello", "world"]);
+            assert_eq!(tokenizer.encoded.get(), 3);
+        }
+    }
+
+    #[test]
+    fn test_encode_with_offsets() {
+        use minbpe::Tokenizer;
+
+        // Multi-byte characters that the merges do not cover are split into byte tokens
+        let text = "hello wörld 👋 hello<|endoftext|> wörld";
+        let mut regex = RegexTokenizerStruct::default();
+        regex.train("hello world hello world", 256 + 8, false);
+        regex.set_special_tokens(SPECIAL_TOKENS.clone());
+
+        let spans = regex.encode_with_offsets(text, AllowedSpecial::All);
+        let ids: Vec<Token> = spans.iter().map(|span| span.id).collect();
+        assert_eq!(ids, regex.encode_special(text, AllowedSpecial::All));
+        for span in &spans {
+            let bytes = match regex.vocab().get(&span.id) {
+                Some(bytes) => bytes.clone(),
+                None => RegexTokenizerTrait::decode(&regex, &[span.id]).into_bytes(),
+            };
+            assert_eq!(&text.as_bytes()[span.bytes.clone()], &bytes[..]);
+        }
+        assert_eq!(spans.last().unwrap().bytes.end, text.len());
+
+        let special = spans.iter().find(|span| span.id == 100257).unwrap();
+        assert_eq!(&text[special.bytes.clone()], "<|endoftext|>");
+        let chars: Vec<char> = text.chars().collect();
+        let special_chars: String = chars[special.chars.clone()].iter().collect();
+        assert_eq!(special_chars, "<|endoftext|>");
+
+        // The 4 byte tokens of the emoji all cover its single char
+        let emoji = text.chars().position(|c| c == '👋').unwrap();
+        let emoji_spans: Vec<_> = spans
+            .iter()
+            .filter(|span| span.chars == (emoji..emoji + 1))
+            .collect();
+        assert_eq!(emoji_spans.len(), 4);
+        assert!(emoji_spans.iter().all(|span| span.bytes.len() == 1));
+
+        assert!(matches!(
+            regex.try_encode_with_offsets(text, AllowedSpecial::NoneRaise),
+            Err(MinbpeError::DisallowedSpecialToken(_))
+        ));
+
+        let mut basic = BasicTokenizer::new();
+        basic.train("hello world hello world", 256 + 8, false);
+        let spans = basic.encode_with_offsets(text);
+        let ids: Vec<Token> = spans.iter().map(|span| span.id).collect();
+        assert_eq!(ids, Tokenizer::encode(&basic, text));
+        for span in &spans {
+            assert_eq!(
+                &text.as_bytes()[span.bytes.clone()],
+                &basic.vocab()[&span.id][..]
+            );
+        }
+    }
+
+    #[test]
+    fn test_streaming_decoder() {
+        let text = "naïve 日本語のテキスト 👋🏽 done<|endoftext|>";
+        let mut regex = RegexTokenizerStruct::default();
+        regex.train("日本語 日本 naïve", 256 + 6, false);
+        regex.set_special_tokens(SPECIAL_TOKENS.clone());
+        let ids = regex.encode_special(text, AllowedSpecial::All);
+
+        let mut decoder = StreamingDecoder::new(&regex);
+        let mut streamed = String::new();
+        for &id in &ids {
+            let piece = decoder.push(id);
+            assert!(!piece.contains(char::REPLACEMENT_CHARACTER));
+            streamed.push_str(&piece);
+        }
+        assert!(!decoder.has_pending());
+        assert_eq!(decoder.flush(), "");
+        assert_eq!(streamed, text);
+
+        // An invalid byte is replaced as soon as it is known to be invalid, an incomplete
+        // character only when the stream is flushed
+        assert_eq!(decoder.push(0xE6), "");
+        assert_eq!(decoder.push('a' as Token), "\u{FFFD}a");
+        assert_eq!(decoder.push(0xE6), "");
+        assert!(decoder.has_pending());
+        assert!(matches!(
+            decoder.try_push(100000),
+            Err(MinbpeError::UnknownTokenId(100000))
+        ));
+        assert_eq!(decoder.flush(), "\u{FFFD}");
+    }
+
+    #[test]
+    fn test_decode_options() {
+        use minbpe::Tokenizer;
+
+        let mut basic = BasicTokenizer::new();
+        basic.train(LLAMA_TEXT, 256 + 16, false);
+        basic.set_special_tokens(SPECIAL_TOKENS.clone());
+        let mut regex = RegexTokenizerStruct::default();
+        regex.train(LLAMA_TEXT, 256 + 16, false);
+        regex.set_special_tokens(SPECIAL_TOKENS.clone());
+
+        // "hi", a special token, a truncated "日" and an unknown id
+        let tokenizers: [&dyn Tokenizer; 2] = [&basic, &regex];
+        for tokenizer in tokenizers {
+            let ids = [104, 105, 100257, 0xE6, 0x97];
+            assert_eq!(tokenizer.decode_bytes(&ids), b"hi<|endoftext|>\xE6\x97");
+            assert_eq!(tokenizer.decode(&ids), "hi<|endoftext|>\u{FFFD}");
+
+            let escape = DecodeOptions {
+                utf8: Utf8Policy::Escape,
+                skip_special_tokens: true,
+                ..Default::default()
+            };
+            assert_eq!(
+                tokenizer.try_decode_with(&ids, &escape).unwrap(),
+                "hi\\xE6\\x97"
+            );
+            let strict = DecodeOptions {
+                utf8: Utf8Policy::Strict,
+                ..Default::default()
+            };
+            assert!(matches!(
+                tokenizer.try_decode_with(&ids, &strict),
+                Err(MinbpeError::InvalidUtf8(_))
+            ));
+            assert_eq!(
+                tokenizer.try_decode_with(&ids[..3], &strict).unwrap(),
+                "hi<|endoftext|>"
+            );
+
+            let ids = [104, 99999, 105];
+            assert!(matches!(
+                tokenizer.try_decode(&ids),
+                Err(MinbpeError::UnknownTokenId(99999))
+            ));
+            assert!(matches!(
+                tokenizer.try_decode_bytes(&ids),
+                Err(MinbpeError::UnknownTokenId(99999))
+            ));
+            let skip = DecodeOptions {
+                unknown_ids: UnknownIdPolicy::Skip,
+                ..Default::default()
+            };
+            assert_eq!(tokenizer.try_decode_with(&ids, &skip).unwrap(), "hi");
+        }
+    }
+
+    #[test]
+    fn test_train_from_files() {
+        use minbpe::Tokenizer;
+
+        let path = "tests/taylorswift.txt";
+        let text = fs::read_to_string(path).unwrap();
+        let vocab_size = 256 + 64;
+
+        // Streaming the lines of a file is the same as training on its lines as documents
+        let mut from_lines = RegexTokenizerStruct::default();
+        from_lines
+            .train_from_files([path], DocumentSplit::Lines, vocab_size, false)
+            .unwrap();
+        let mut from_iter = RegexTokenizerStruct::default();
+        from_iter
+            .train_from_iter(text.split_inclusive('\n'), vocab_size, false)
+            .unwrap();
+        assert_eq!(from_lines.merges(), from_iter.merges());
+
+        // With a configuration, the same as streaming the lines with that configuration
+        let config = TrainingConfig {
+            special_tokens: vec!["<|endoftext|>".to_string()],
+            max_merges: Some(32),
+            ..TrainingConfig::new(vocab_size)
+        };
+        let mut from_lines = RegexTokenizerStruct::default();
+        let outcome = from_lines
+            .train_from_files_with_observer(
+                [path],
+                DocumentSplit::Lines,
+                &config,
+                &mut SilentObserver,
+            )
+            .unwrap();
+        assert_eq!(outcome.stop_reason, StopReason::MaxMerges);
+        let mut from_iter = RegexTokenizerStruct::default();
+        from_iter
+            .train_from_iter_with_observer(text.split_inclusive('\n'), &config, &mut SilentObserver)
+            .unwrap();
+        assert_eq!(from_lines.merges(), from_iter.merges());
+        assert_eq!(from_lines.special_tokens(), from_iter.special_tokens());
+        assert_eq!(from_lines.special_tokens()["<|endoftext|>"], 256 + 32);
+    }
+
+    #[test]
+    fn test_train_from_files_delimiter() {
+        use minbpe::Tokenizer;
+
+        let path = "tests/taylorswift.txt";
+        let text = fs::read_to_string(path).unwrap();
+        let vocab_size = 256 + 64;
+
+        // Streaming the paragraphs of a file is the same as training on them as documents
+        let mut from_paragraphs = RegexTokenizerStruct::default();
+        from_paragraphs
+            .train_from_files(
+                [path],
+                DocumentSplit::Delimiter("\n\n".to_string()),
+                vocab_size,
+                false,
+            )
+            .unwrap();
+        let mut from_iter = RegexTokenizerStruct::default();
+        from_iter
+            .train_from_iter(text.split("\n\n"), vocab_size, false)
+            .unwrap();
+        assert_eq!(from_paragraphs.merges(), from_iter.merges());
+
+        // The delimiter is never trained on, even when it spans several reads
+        let dir = tempdir().unwrap();
+        let concatenated = dir.path().join("concatenated.txt");
+        fs::write(&concatenated, "aaab<|sep|>aaab<|sep|>ab<|sep|").unwrap();
+        let mut tokenizer = BasicTokenizer::new();
+        tokenizer
+            .train_from_files(
+                [&concatenated],
+                DocumentSplit::Delimiter("<|sep|>".to_string()),
+                256 + 3,
+                false,
+            )
+            .unwrap();
+        let mut expected = BasicTokenizer::new();
+        expected
+            .train_from_iter(["aaab", "aaab", "ab<|sep|"], 256 + 3, false)
+            .unwrap();
+        assert_eq!(tokenizer.merges(), expected.merges());
+
+        assert!(matches!(
+            tokenizer.train_from_files(
+                [&concatenated],
+                DocumentSplit::Delimiter(String::new()),
+                256 + 3,
+                false
+            ),
+            Err(MinbpeError::Io(e)) if e.kind() == std::io::ErrorKind::InvalidInput
+        ));
+    }
+
+    #[cfg(feature = "jsonl")]
+    #[test]
+    fn test_train_from_files_json_lines() {
+        use minbpe::Tokenizer;
+
+        let text = fs::read_to_string("tests/taylorswift.txt").unwrap();
+        let documents: Vec<&str> = text.split("\n\n").collect();
+        let vocab_size = 256 + 64;
+
+        // Only the text of the documents is trained on, not the JSON syntax around it
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("shard.jsonl");
+        let mut jsonl = String::new();
+        for (i, document) in documents.iter().enumerate() {
+            let text = serde_json::to_string(document).unwrap();
+            jsonl.push_str(&format!("{{\"id\": {}, \"text\": {}}}\n", i, text));
+        }
+        jsonl.push('\n');
+        fs::write(&path, jsonl).unwrap();
+        let split = DocumentSplit::JsonLines {
+            field: "text".to_string(),
+        };
+        let mut from_jsonl = RegexTokenizerStruct::default();
+        from_jsonl
+            .train_from_files([&path], split.clone(), vocab_size, false)
+            .unwrap();
+        let mut from_iter = RegexTokenizerStruct::default();
+        from_iter
+            .train_from_iter(&documents, vocab_size, false)
+            .unwrap();
+        assert_eq!(from_jsonl.merges(), from_iter.merges());
+
+        for (line, message) in [
+            ("{\"id\": 1}", "Missing member"),
+            ("{\"text\": 1}", "is not a string"),
+            ("[\"text\"]", "Not a JSON object"),
+            ("{\"text\": ", "EOF"),
+        ] {
+            fs::write(&path, format!("{{\"text\": \"a\"}}\n\n{}\n", line)).unwrap();
+            let result = from_jsonl.train_from_files([&path], split.clone(), vocab_size, false);
+            assert!(
+                matches!(
+                    &result,
+                    Err(MinbpeError::MalformedJsonLine { line: 3, message: m, .. })
+                        if m.contains(message)
+                ),
+                "{:?}",
+                result
+            );
+        }
+    }
+
+    #[test]
+    fn test_resume_training() {
+        use minbpe::Tokenizer;
+
+        let text = fs::read_to_string("tests/taylorswift.txt").unwrap();
+        let text: String = text.chars().take(20000).collect();
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("regex.checkpoint");
+
+        let mut uninterrupted = RegexTokenizerStruct::default();
+        uninterrupted.train(&text, 256 + 100, false);
+
+        // Interrupt a run after 40 merges, checkpointing every 16 merges and at the end
+        let mut interrupted = RegexTokenizerStruct::default();
+        let mut chunks = ChunkCounts::new();
+        interrupted.pre_tokenize(&text, &mut chunks).unwrap();
+        let config = TrainingConfig {
+            checkpoint: Some(CheckpointConfig {
+                path: path.clone(),
+                interval: 16,
+            }),
+            ..TrainingConfig::new(256 + 40)
+        };
+        interrupted
+            .train_chunks_with_config(&chunks, &config, &mut SilentObserver)
+            .unwrap();
+
+        let mut resumed = RegexTokenizerStruct::default();
+        resumed
+            .resume_training(&path, &TrainingConfig::new(256 + 100), &mut SilentObserver)
+            .unwrap();
+        assert_eq!(resumed.merges(), uninterrupted.merges());
+        assert_eq!(resumed.vocab(), uninterrupted.vocab());
+        assert_eq!(
+            resumed.encode_ordinary(&text),
+            uninterrupted.encode_ordinary(&text)
+        );
+
+        // Resuming to a smaller vocabulary keeps the first merges
+        let mut shorter = RegexTokenizerStruct::default();
+        shorter
+            .resume_training(&path, &TrainingConfig::new(256 + 20), &mut SilentObserver)
+            .unwrap();
+        let expected: Vec<_> = uninterrupted.merges().iter().take(20).collect();
+        assert_eq!(shorter.merges().iter().collect::<Vec<_>>(), expected);
+
+        // The checkpoint is only resumed with the split pattern and special tokens of its run
+        let mut basic = BasicTokenizer::new();
+        assert!(matches!(
+            basic.resume_training(&path, &TrainingConfig::new(256 + 100), &mut SilentObserver),
+            Err(MinbpeError::CheckpointMismatch(_))
+        ));
+        let config = TrainingConfig {
+            special_tokens: vec!["<|endoftext|>".to_string()],
+            ..TrainingConfig::new(256 + 100)
+        };
+        assert!(matches!(
+            resumed.resume_training(&path, &config, &mut SilentObserver),
+            Err(MinbpeError::CheckpointMismatch(_))
+        ));
+
+        fs::write(&path, "minbpe v1\n").unwrap();
+        assert!(matches!(
+            resumed.resume_training(&path, &TrainingConfig::new(256 + 100), &mut SilentObserver),
+            Err(MinbpeError::MalformedCheckpoint { line: 1, .. })
+        ));
+    }
+
+    #[test]
+    fn test_train_special_tokens() {
+        use minbpe::Tokenizer;
+
+        let special_tokens: Vec<String> = SPECIAL_TOKENS.keys().cloned().collect();
+        let num_special = special_tokens.len() as Token;
+        let config = TrainingConfig {
+            special_tokens: special_tokens.clone(),
+            ..TrainingConfig::new(256 + 64 + num_special)
+        };
+
+        // The text of the special tokens is never trained on
+        let mut documents = vec![LLAMA_TEXT];
+        for token in &special_tokens {
+            documents = documents
+                .iter()
+                .flat_map(|document| document.split(token.as_str()))
+                .collect();
+        }
+        let mut expected = RegexTokenizerStruct::default();
+        expected
+            .train_documents(&documents, 256 + 64, false)
+            .unwrap();
+
+        let mut tokenizer = RegexTokenizerStruct::default();
+        tokenizer
+            .train_with_observer(LLAMA_TEXT, &config, &mut SilentObserver)
+            .unwrap();
+        assert_eq!(tokenizer.merges(), expected.merges());
+        let ids: Vec<Token> = (256 + 64..256 + 64 + num_special).collect();
+        assert_eq!(
+            tokenizer
+                .special_tokens()
+                .values()
+                .copied()
+                .collect::<Vec<_>>(),
+            ids
+        );
+        let ids = tokenizer.encode_special(LLAMA_TEXT, AllowedSpecial::All);
+        assert_eq!(ids[0], 256 + 64);
+        assert_eq!(RegexTokenizerTrait::decode(&tokenizer, &ids), LLAMA_TEXT);
+
+        // Reserved before the merges, the special tokens keep their ids through save and load
+        let config = TrainingConfig {
+            special_token_placement: SpecialTokenPlacement::BeforeMerges,
+            ..config
+        };
+        tokenizer
+            .train_with_observer(LLAMA_TEXT, &config, &mut SilentObserver)
+            .unwrap();
+        let ids: Vec<Token> = (256..256 + num_special).collect();
+        assert_eq!(
+            tokenizer
+                .special_tokens()
+                .values()
+                .copied()
+                .collect::<Vec<_>>(),
+            ids
+        );
+        assert_eq!(
+            tokenizer.merges().values().next(),
+            Some(&(256 + num_special))
+        );
+        let merged = |tokenizer: &RegexTokenizerStruct| {
+            tokenizer
+                .merges()
+                .values()
+                .map(|idx| tokenizer.vocab()[idx].clone())
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(merged(&tokenizer), merged(&expected));
+
+        let dir = tempdir().unwrap();
+        tokenizer.save(dir.path(), "reserved");
+        let mut loaded = RegexTokenizerStruct::default();
+        loaded.load(&dir.path().join("reserved.model"));
+        assert_eq!(loaded.special_tokens(), tokenizer.special_tokens());
+        assert_eq!(loaded.merges(), tokenizer.merges());
+        assert_eq!(loaded.vocab(), tokenizer.vocab());
+
+        let mut tokenizer = BasicTokenizer::new();
+        let config = TrainingConfig {
+            special_tokens: vec!["<|a|>".to_string(), "<|a|>".to_string()],
+            ..TrainingConfig::new(512)
+        };
+        assert!(matches!(
+            tokenizer.train_with_observer("a<|a|>b", &config, &mut SilentObserver),
+            Err(MinbpeError::InvalidSpecialToken(token)) if token == "<|a|>"
+        ));
+        let config = TrainingConfig {
+            special_tokens: vec!["<|a|>".to_string()],
+            ..TrainingConfig::new(256)
+        };
+        assert!(matches!(
+            tokenizer.train_with_observer("a<|a|>b", &config, &mut SilentObserver),
+            Err(MinbpeError::InvalidVocabSize(256))
+        ));
+    }
+
+    #[test]
+    fn test_kept_special_tokens() {
+        fn check<T: Trainable + Loadable>(mut tokenizer: T) {
+            let eot = |id: Token| IndexMap::from([("<|eot|>".to_string(), id)]);
+
+            // A special token kept through training must not take the id of a merge
+            tokenizer.try_set_special_tokens(eot(256)).unwrap();
+            let config = TrainingConfig::new(256 + 3);
+            assert!(matches!(
+                tokenizer.train_with_observer("aaabdaaabac", &config, &mut SilentObserver),
+                Err(MinbpeError::SpecialTokenIdCollision { id: 256, .. })
+            ));
+            assert!(tokenizer.merges().is_empty());
+            assert_eq!(tokenizer.special_tokens(), &eot(256));
+
+            tokenizer.try_set_special_tokens(eot(256 + 3)).unwrap();
+            tokenizer
+                .train_with_observer("aaabdaaabac", &config, &mut SilentObserver)
+                .unwrap();
+            assert_eq!(tokenizer.merges().len(), 3);
+            assert_eq!(tokenizer.decode(&[256 + 3]), "<|eot|>");
+
+            // Nor can one be set to the id of a byte, of a merge or of another special token
+            for special_tokens in [
+                eot(97),
+                eot(256 + 1),
+                IndexMap::from([("<|a|>".to_string(), 300), ("<|b|>".to_string(), 300)]),
+            ] {
+                assert!(matches!(
+                    tokenizer.try_set_special_tokens(special_tokens),
+                    Err(MinbpeError::SpecialTokenIdCollision { .. })
+                ));
+                assert_eq!(tokenizer.special_tokens(), &eot(256 + 3));
+            }
+        }
+
+        check(BasicTokenizer::new());
+        check(RegexTokenizerStruct::default());
+    }
+
+    #[derive(Default)]
+    struct RecordingObserver {
+        phases: Vec<TrainingPhase>,
+        merges: Vec<((Token, Token), Token, Vec<u8>)>,
+        stop_after: usize,
+    }
+
+    impl TrainingObserver for RecordingObserver {
+        fn on_phase(&mut self, phase: TrainingPhase) {
+            self.phases.push(phase);
+        }
+
+        fn on_merge(&mut self, merge: &MergeEvent) {
+            assert_eq!(merge.index, self.merges.len());
+            self.merges
+                .push((merge.pair, merge.new_id, merge.bytes.to_vec()));
+        }
+
+        fn should_stop(&mut self) -> bool {
+            self.merges.len() == self.stop_after
+        }
+    }
+
+    #[test]
+    fn test_training_observer() {
+        use minbpe::Tokenizer;
+
+        let mut full = RegexTokenizerStruct::default();
+        full.train(LLAMA_TEXT, 256 + 20, false);
+
+        let mut observer = RecordingObserver {
+            stop_after: 10,
+            ..Default::default()
+        };
+        let mut stopped = RegexTokenizerStruct::default();
+        stopped
+            .train_with_observer(LLAMA_TEXT, &TrainingConfig::new(256 + 20), &mut observer)
+            .unwrap();
+
+        assert_eq!(
+            observer.phases,
+            [
+                TrainingPhase::PreTokenizing,
+                TrainingPhase::CountingPairs,
+                TrainingPhase::Merging
+            ]
+        );
+        let expected: Vec<_> = full
+            .merges()
+            .iter()
+            .take(10)
+            .map(|(&pair, &idx)| (pair, idx, full.vocab()[&idx].clone()))
+            .collect();
+        assert_eq!(observer.merges, expected);
+        assert_eq!(
+            stopped.merges().iter().collect::<Vec<_>>(),
+            full.merges().iter().take(10).collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn test_stop_criteria() {
+        use minbpe::Tokenizer;
+
+        let text = "aaabdaaabac";
+        let train = |config: &TrainingConfig| {
+            let mut tokenizer = BasicTokenizer::new();
+            let outcome = tokenizer
+                .train_with_observer(text, config, &mut SilentObserver)
+                .unwrap();
+            (outcome.num_merges, outcome.stop_reason)
+        };
+
+        assert_eq!(
+            train(&TrainingConfig::new(256 + 3)),
+            (3, StopReason::VocabSize)
+        );
+        assert_eq!(
+            train(&TrainingConfig::new(512)),
+            (7, StopReason::NoMorePairs)
+        );
+        let config = TrainingConfig {
+            max_merges: Some(5),
+            ..TrainingConfig::new(512)
+        };
+        assert_eq!(train(&config), (5, StopReason::MaxMerges));
+        let config = TrainingConfig {
+            min_frequency: Some(2),
+            ..TrainingConfig::new(512)
+        };
+        assert_eq!(train(&config), (3, StopReason::MinFrequency));
+
+        // Running out of pairs no longer panics
+        let mut tokenizer = BasicTokenizer::new();
+        tokenizer.train(text, 512, false);
+        assert_eq!(tokenizer.merges().len(), 7);
+
+        // Training stops at the first merge reaching the target compression
+        let bytes_per_token = |tokenizer: &RegexTokenizerStruct| {
+            LLAMA_TEXT.len() as f64 / tokenizer.encode_ordinary(LLAMA_TEXT).len() as f64
+        };
+        let mut tokenizer = RegexTokenizerStruct::default();
+        let config = TrainingConfig {
+            target_bytes_per_token: Some(2.0),
+            ..TrainingConfig::new(1024)
+        };
+        let outcome = tokenizer
+            .train_with_observer(LLAMA_TEXT, &config, &mut SilentObserver)
+            .unwrap();
+        assert_eq!(outcome.stop_reason, StopReason::TargetBytesPerToken);
+        assert!(bytes_per_token(&tokenizer) >= 2.0);
+        let config = TrainingConfig {
+            max_merges: Some(outcome.num_merges - 1),
+            ..TrainingConfig::new(1024)
+        };
+        tokenizer
+            .train_with_observer(LLAMA_TEXT, &config, &mut SilentObserver)
+            .unwrap();
+        assert!(bytes_per_token(&tokenizer) < 2.0);
+
+        // An empty corpus runs out of pairs without reaching any compression
+        let config = TrainingConfig {
+            target_bytes_per_token: Some(2.0),
+            ..TrainingConfig::new(512)
+        };
+        let outcome = BasicTokenizer::new()
+            .train_with_observer("", &config, &mut SilentObserver)
+            .unwrap();
+        assert_eq!(outcome.stop_reason, StopReason::NoMorePairs);
+    }
+
+    #[test]
+    fn test_train_from_iter_counts_duplicates() {
+        use minbpe::Tokenizer;
+
+        // Repeating every document doubles every count, which does not change the merges
+        let mut once = BasicTokenizer::new();
+        once.train(LLAMA_TEXT, 256 + 32, false);
+        let mut twice = BasicTokenizer::new();
+        twice
+            .train_from_iter([LLAMA_TEXT, LLAMA_TEXT], 256 + 32, false)
+            .unwrap();
+        assert_eq!(once.merges(), twice.merges());
+    }
+
+    // Every token learned from documents must occur within a single document
+    fn assert_no_merge_across_documents(tokenizer: &dyn Trainable, documents: &[&str]) {
+        for idx in tokenizer.merges().values() {
+            let bytes = &tokenizer.vocab()[idx];
+            assert!(
+                documents
+                    .iter()
+                    .any(|document| document.as_bytes().windows(bytes.len()).any(|w| w == bytes)),
+                "Token {:?} spans a document boundary",
+                String::from_utf8_lossy(bytes)
+            );
+        }
+    }
+
+    #[test]
+    fn test_train_documents() {
+        let separator = SPECIAL_TOKENS.keys().next().unwrap();
+        let documents: Vec<&str> = LLAMA_TEXT
+            .lines()
+            .flat_map(|line| line.split(separator.as_str()))
+            .collect();
+        let text = documents.join(separator);
+
+        let tokenizers: Vec<Box<dyn Trainable>> = vec![
+            Box::new(BasicTokenizer::new()),
+            Box::<RegexTokenizerStruct>::default(),
+        ];
+
+        for mut tokenizer in tokenizers {
+            tokenizer
+                .train_documents(&documents, 256 + 128, false)
+                .unwrap();
+            assert_no_merge_across_documents(tokenizer.as_ref(), &documents);
+            let merges = tokenizer.merges().clone();
+
+            // The separator is a special token, taking an id of the vocabulary
+            tokenizer
+                .train_with_separator(&text, separator, 256 + 129, false)
+                .unwrap();
+            assert_eq!(tokenizer.merges(), &merges);
+            assert_eq!(tokenizer.special_tokens().len(), 1);
+            assert_eq!(tokenizer.special_tokens()[separator], 256 + 128);
+        }
+    }
+
+    #[cfg(feature = "tiktoken")]
+    #[test]
+    fn test_save_tiktoken() {
+        use minbpe::{TiktokenTokenizer, Tokenizer};
+
+        let taylorswift = unpack("FILE:../tests/taylorswift.txt").unwrap();
+        let (train_text, text) = (&taylorswift[..20000], &taylorswift[20000..30000]);
+        let dir = tempdir().unwrap();
+
+        let mut regex = RegexTokenizerStruct::default();
+        regex.train(train_text, 256 + 512, false);
+        regex.set_special_tokens(SPECIAL_TOKENS.clone());
+        regex.save_tiktoken(dir.path(), "regex");
+
+        let loaded = TiktokenTokenizer::try_from_saved(dir.path(), "regex").unwrap();
+        assert_eq!(loaded.pattern(), regex.pattern());
+        assert_eq!(loaded.special_tokens(), regex.special_tokens());
+        assert_eq!(loaded.merges(), regex.merges());
+        assert_eq!(loaded.encode_ordinary(text), regex.encode_ordinary(text));
+        let ids = regex.encode_special(LLAMA_TEXT, AllowedSpecial::All);
+        assert_eq!(loaded.encode_special(LLAMA_TEXT, AllowedSpecial::All), ids);
+        assert_eq!(loaded.decode(&ids), LLAMA_TEXT);
+
+        let ranks = fs::read_to_string(dir.path().join("regex.tiktoken")).unwrap();
+        assert_eq!(ranks.lines().count(), 256 + 512);
+        assert_eq!(ranks.lines().nth(97), Some("YQ== 97"));
+
+        let mut basic = BasicTokenizer::new();
+        basic.train(train_text, 256 + 128, false);
+        basic.save_tiktoken(dir.path(), "basic");
+
+        let loaded = TiktokenTokenizer::try_from_saved(dir.path(), "basic").unwrap();
+        assert_eq!(
+            loaded.encode_ordinary(text),
+            Tokenizer::encode(&basic, text)
+        );
+    }
+
+    // A preset saves the rank file it was built from, with its byte shuffle undone
+    #[cfg(feature = "gpt2")]
+    #[test]
+    fn test_save_tiktoken_preset() {
+        use minbpe::{GPT2Tokenizer, TiktokenTokenizer, Tokenizer};
+
+        let dir = tempdir().unwrap();
+        let tokenizer = GPT2Tokenizer::new();
+        tokenizer.save_tiktoken(dir.path(), "r50k_base");
+
+        let saved = fs::read(dir.path().join("r50k_base.tiktoken")).unwrap();
+        assert_eq!(saved, include_bytes!("../assets/r50k_base.tiktoken"));
+        let loaded = TiktokenTokenizer::try_from_saved(dir.path(), "r50k_base").unwrap();
+        assert_eq!(loaded.special_tokens(), tokenizer.special_tokens());
+        assert_eq!(loaded.pattern(), tokenizer.split_pattern());
+        let text = unpack("FILE:../tests/taylorswift.txt").unwrap();
+        let ids = tokenizer.encode_special(&text[..10000], AllowedSpecial::All);
+        assert_eq!(
+            loaded.encode_special(&text[..10000], AllowedSpecial::All),
+            ids
+        );
+        assert_eq!(loaded.decode(&ids), &text[..10000]);
+    }
 }
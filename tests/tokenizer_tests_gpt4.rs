@@ -5,6 +5,7 @@ mod tests {
 
     use minbpe::GPT4Tokenizer;
     use minbpe::RegexTokenizerTrait;
+    use minbpe::StreamingDecoder;
     use minbpe::Token;
 
     use minbpe::test_common::{unpack, TEST_STRINGS};
@@ -58,4 +59,23 @@ mod tests {
             test_gpt4_tiktoken_equality_inner(text);
         }
     }
+
+    #[test]
+    fn test_gpt4_streaming_decoder() {
+        // cl100k splits many CJK characters and emoji across tokens
+        let text = "Streaming 日本語のテキスト, 한국어 텍스트 and 👋🏽🎉 emoji";
+        let tokenizer = GPT4Tokenizer::new();
+        let ids = tokenizer.encode(text);
+
+        let mut decoder = StreamingDecoder::new(&tokenizer);
+        let mut streamed = String::new();
+        for &id in &ids {
+            let piece = decoder.push(id);
+            assert!(!piece.contains(char::REPLACEMENT_CHARACTER));
+            streamed.push_str(&piece);
+        }
+        streamed.push_str(&decoder.flush());
+        assert_eq!(streamed, text);
+        assert_eq!(streamed, tokenizer.decode(&ids));
+    }
 }